@@ -0,0 +1,62 @@
+//! Parsing the tools' arguments
+//!
+//! Numbers may be given in decimal or with a `0x` prefix, and data as pairs of
+//! hex digits, e.g. `0a1b2c`.
+
+/// A number, in decimal or with `0x`
+pub fn parse_num<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.ok()
+        .and_then(|n| n.try_into().ok())
+        .ok_or_else(|| format!("bad number '{s}'"))
+}
+
+/// A 7 bit I2C address
+pub fn parse_addr(s: &str) -> Result<u8, String> {
+    match parse_num(s)? {
+        addr @ 0..=0x7F => Ok(addr),
+        _ => Err("I2C addresses are 7 bits".into()),
+    }
+}
+
+/// Pairs of hex digits, optionally after `0x`
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    if !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+        return Err(format!("bad hex '{s}', expected pairs of hex digits"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("bad hex '{s}'")))
+        .collect()
+}
+
+/// The positional argument at `idx`, called `name` in errors
+pub fn arg<'a>(args: &'a [String], idx: usize, name: &str) -> Result<&'a str, String> {
+    args.get(idx)
+        .map(String::as_str)
+        .ok_or_else(|| format!("missing <{name}>"))
+}
+
+/// Remove `--name` from `args`, returning whether it was there
+pub fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|a| a != name);
+    args.len() != len
+}
+
+/// Remove `--name <value>` from `args`, returning the value
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let Some(idx) = args.iter().position(|a| a == name) else {
+        return Ok(None);
+    };
+    if idx + 1 >= args.len() {
+        return Err(format!("missing value for {name}"));
+    }
+    let value = args.remove(idx + 1);
+    args.remove(idx);
+    Ok(Some(value))
+}
@@ -0,0 +1,160 @@
+//! Take a logic analyzer capture on the jig, and save it as a VCD file
+//!
+//! Usage: `capture [--jig <serial|alias>] [--server <addr>] [--usb] <first-gpio>
+//! <gpio-count> <rate-hz> <samples> <out.vcd> [trigger]`
+//!
+//! `trigger` is one of `high:N`, `low:N`, `rising:N` or `falling:N`, where `N` is
+//! a GPIO number. Without it, the capture starts immediately.
+//!
+//! The jig is picked as with `demo`, see `demo::connect`.
+
+use std::{fs::File, io::Write, time::Duration};
+
+use demo::connect::JigArgs;
+use picocalc_jig_icd::*;
+
+/// The largest chunk the firmware will send at once
+const CHUNK: u32 = 256;
+
+fn parse_trigger(s: &str) -> Result<CaptureTrigger, String> {
+    let (kind, gpio) = s
+        .split_once(':')
+        .ok_or_else(|| format!("bad trigger '{s}'"))?;
+    let gpio = gpio
+        .parse()
+        .map_err(|_| format!("bad trigger gpio '{gpio}'"))?;
+    match kind {
+        "high" => Ok(CaptureTrigger::High(gpio)),
+        "low" => Ok(CaptureTrigger::Low(gpio)),
+        "rising" => Ok(CaptureTrigger::Rising(gpio)),
+        "falling" => Ok(CaptureTrigger::Falling(gpio)),
+        _ => Err(format!("bad trigger kind '{kind}'")),
+    }
+}
+
+fn parse<T: std::str::FromStr>(args: &[String], idx: usize, name: &str) -> Result<T, String> {
+    let arg = args.get(idx).ok_or_else(|| format!("missing <{name}>"))?;
+    arg.parse().map_err(|_| format!("bad <{name}>: '{arg}'"))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let jig_args = JigArgs::take(&mut args)?;
+    let cfg = CaptureConfig {
+        first_gpio: parse(&args, 0, "first-gpio")?,
+        gpio_count: parse(&args, 1, "gpio-count")?,
+        sample_rate_hz: parse(&args, 2, "rate-hz")?,
+        samples: parse(&args, 3, "samples")?,
+        trigger: match args.get(5) {
+            Some(t) => parse_trigger(t)?,
+            None => CaptureTrigger::Immediate,
+        },
+    };
+    let path: String = parse(&args, 4, "out.vcd")?;
+
+    let jig = jig_args.connect().await.map_err(|e| e.to_string())?;
+
+    jig.capture_start(cfg)
+        .await
+        .map_err(|e| format!("capture rejected: {e}"))?;
+    println!("Armed, waiting for the capture to complete...");
+
+    let status = loop {
+        let status = jig.capture_status().await.map_err(|e| e.to_string())?;
+        match status.state {
+            CaptureState::Done => break status,
+            CaptureState::Idle => return Err("capture was cancelled".into()),
+            CaptureState::Armed | CaptureState::Running => {}
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+
+    let len = cfg.samples * sample_bytes(cfg.gpio_count) as u32;
+    let mut data = Vec::with_capacity(len as usize);
+    while (data.len() as u32) < len {
+        let offset = data.len() as u32;
+        let chunk = jig
+            .capture_fetch(offset, CHUNK.min(len - offset))
+            .await
+            .map_err(|e| format!("fetch failed: {e}"))?;
+        data.extend_from_slice(&chunk);
+    }
+
+    let mut file = File::create(&path).map_err(|e| e.to_string())?;
+    write_vcd(&mut file, &cfg, status.actual_rate_hz, &data).map_err(|e| e.to_string())?;
+    println!(
+        "Wrote {} samples at {} Hz to {path}",
+        cfg.samples, status.actual_rate_hz
+    );
+    Ok(())
+}
+
+fn sample_bytes(gpio_count: u8) -> usize {
+    match gpio_count {
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+    }
+}
+
+/// Write the capture out as a Value Change Dump, one wire per GPIO
+fn write_vcd(
+    out: &mut impl Write,
+    cfg: &CaptureConfig,
+    rate_hz: u32,
+    data: &[u8],
+) -> std::io::Result<()> {
+    // VCD identifiers are printable ASCII characters, starting at '!'
+    let ident = |gpio: u8| char::from(b'!' + gpio);
+
+    writeln!(out, "$version picocalc-jig capture $end")?;
+    writeln!(out, "$timescale 1ns $end")?;
+    writeln!(out, "$scope module jig $end")?;
+    for i in 0..cfg.gpio_count {
+        writeln!(
+            out,
+            "$var wire 1 {} gpio{} $end",
+            ident(i),
+            cfg.first_gpio + i
+        )?;
+    }
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$enddefinitions $end")?;
+
+    let width = sample_bytes(cfg.gpio_count);
+    let mut last: Option<u32> = None;
+    for (n, sample) in data.chunks_exact(width).enumerate() {
+        let mut word = [0u8; 4];
+        word[..width].copy_from_slice(sample);
+        let value = u32::from_le_bytes(word);
+        let changed = match last {
+            Some(prev) => prev ^ value,
+            None => u32::MAX,
+        };
+        if changed & mask(cfg.gpio_count) == 0 {
+            continue;
+        }
+
+        let ns = n as u64 * 1_000_000_000 / rate_hz as u64;
+        writeln!(out, "#{ns}")?;
+        for i in 0..cfg.gpio_count {
+            if changed & (1 << i) != 0 {
+                writeln!(out, "{}{}", (value >> i) & 1, ident(i))?;
+            }
+        }
+        last = Some(value);
+    }
+
+    // Mark the end of the capture
+    let ns = (data.len() / width) as u64 * 1_000_000_000 / rate_hz as u64;
+    writeln!(out, "#{ns}")?;
+    Ok(())
+}
+
+fn mask(gpio_count: u8) -> u32 {
+    match gpio_count {
+        32.. => u32::MAX,
+        n => (1 << n) - 1,
+    }
+}
@@ -0,0 +1,206 @@
+//! Sweep the southbridge's registers, to help work out its protocol
+//!
+//! Usage: `explore [--from <reg>] [--to <reg>] [--len <n>] [--rounds <n>]
+//! [--out <file>] [--jig <serial|alias>] [--server <addr>] [--usb] [phase...]`
+//!
+//! Each register in `from..=to` (default `0x00..=0x7F`) is read with a
+//! write-read of `len` bytes (default 2), `rounds` times (default 5) per phase.
+//! The phases default to `idle keys power`. Before every phase but the first,
+//! you'll be asked to do something to the PicoCalc (e.g. hold some keys, or
+//! plug in the charger) and press enter once it's done.
+//!
+//! The report is written as JSON to `out` (default `sb-registers.json`),
+//! listing every sample and flagging the registers which changed within a
+//! phase, or between the first phase and the others.
+//!
+//! Note that reading some registers has side effects, e.g. `0x09` pops the
+//! key FIFO.
+//!
+//! The jig is picked as with `demo`, see `demo::connect`.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+    time::{Duration, Instant},
+};
+
+use demo::{
+    args::{parse_num, take_flag},
+    connect::{AnyTransport, JigArgs},
+};
+use picocalc_jig_host::{Error, Jig};
+use picocalc_jig_icd::*;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Sample {
+    phase: String,
+    /// Time since the sweep started
+    t_ms: u64,
+    /// What was read, if the read succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<u8>>,
+    /// Why the read failed, otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Register {
+    reg: u8,
+    /// At least one read succeeded
+    responds: bool,
+    errors: usize,
+    /// Every value read, in order of first appearance
+    values: Vec<Vec<u8>>,
+    /// Phases in which the value changed from one read to the next
+    changes_within: Vec<String>,
+    /// Phases in which values were read that the first phase never saw
+    differs_from_first: Vec<String>,
+    samples: Vec<Sample>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    addr: u8,
+    rx_len: u32,
+    rounds: usize,
+    phases: Vec<String>,
+    registers: Vec<Register>,
+}
+
+async fn read(jig: &Jig<AnyTransport>, reg: u8, rx_len: u32) -> Result<Vec<u8>, String> {
+    let mut data = vec![0; rx_len as usize];
+    match jig.i2c_write_read(SB_I2C_ADDR, &[reg], &mut data).await {
+        Ok(()) => Ok(data),
+        Err(Error::I2c(e)) => Err(format!("{e:?}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Flag the phases where `reg` changed, see [`Register`]
+fn analyze(reg: u8, phases: &[String], samples: Vec<Sample>) -> Register {
+    fn data_in<'a>(samples: &'a [Sample], phase: &'a str) -> impl Iterator<Item = &'a Vec<u8>> {
+        samples
+            .iter()
+            .filter(move |s| s.phase == phase)
+            .filter_map(|s| s.data.as_ref())
+    }
+    let first: BTreeSet<&Vec<u8>> = data_in(&samples, &phases[0]).collect();
+
+    let mut values: Vec<Vec<u8>> = vec![];
+    for data in samples.iter().filter_map(|s| s.data.as_ref()) {
+        if !values.contains(data) {
+            values.push(data.clone());
+        }
+    }
+    let changes_within = phases
+        .iter()
+        .filter(|p| {
+            let seen: Vec<_> = data_in(&samples, p).collect();
+            seen.windows(2).any(|w| w[0] != w[1])
+        })
+        .cloned()
+        .collect();
+    let differs_from_first = phases[1..]
+        .iter()
+        .filter(|p| data_in(&samples, p).any(|d| !first.contains(d)))
+        .cloned()
+        .collect();
+
+    Register {
+        reg,
+        responds: samples.iter().any(|s| s.data.is_some()),
+        errors: samples.iter().filter(|s| s.error.is_some()).count(),
+        values,
+        changes_within,
+        differs_from_first,
+        samples,
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let jig_args = JigArgs::take(&mut args)?;
+    let from: u8 = take_flag(&mut args, "--from")?
+        .map(|s| parse_num(&s))
+        .transpose()?
+        .unwrap_or(0x00);
+    let to: u8 = take_flag(&mut args, "--to")?
+        .map(|s| parse_num(&s))
+        .transpose()?
+        .unwrap_or(0x7F);
+    let rx_len: u32 = take_flag(&mut args, "--len")?
+        .map(|s| parse_num(&s))
+        .transpose()?
+        .unwrap_or(2);
+    let rounds: usize = take_flag(&mut args, "--rounds")?
+        .map(|s| parse_num(&s))
+        .transpose()?
+        .unwrap_or(5);
+    let out = take_flag(&mut args, "--out")?.unwrap_or("sb-registers.json".into());
+    let phases = match args.is_empty() {
+        true => vec!["idle".into(), "keys".into(), "power".into()],
+        false => args,
+    };
+    if from > to || rx_len == 0 || rx_len as usize > I2C_CHUNK_LEN || rounds == 0 {
+        return Err("nothing to sweep".into());
+    }
+
+    let jig = jig_args.connect().await.map_err(|e| e.to_string())?;
+
+    let start = Instant::now();
+    let mut samples: Vec<Vec<Sample>> = (from..=to).map(|_| vec![]).collect();
+    for (i, phase) in phases.iter().enumerate() {
+        if i != 0 {
+            print!("Get the PicoCalc into the '{phase}' state, then press enter: ");
+            io::stdout().flush().map_err(|e| e.to_string())?;
+            let mut line = String::new();
+            io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .map_err(|e| e.to_string())?;
+        }
+        for round in 0..rounds {
+            println!("{phase}: round {}/{rounds}", round + 1);
+            for (reg, samples) in (from..=to).zip(samples.iter_mut()) {
+                let res = read(&jig, reg, rx_len).await;
+                samples.push(Sample {
+                    phase: phase.clone(),
+                    t_ms: start.elapsed().as_millis() as u64,
+                    data: res.as_ref().ok().cloned(),
+                    error: res.err(),
+                });
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    let registers: Vec<Register> = (from..=to)
+        .zip(samples)
+        .map(|(reg, samples)| analyze(reg, &phases, samples))
+        .collect();
+    for reg in registers.iter().filter(|r| r.responds) {
+        println!(
+            "0x{:02X}: {} value(s), changed within {:?}, differs from '{}' in {:?}",
+            reg.reg,
+            reg.values.len(),
+            reg.changes_within,
+            phases[0],
+            reg.differs_from_first
+        );
+    }
+
+    let report = Report {
+        addr: SB_I2C_ADDR,
+        rx_len,
+        rounds,
+        phases,
+        registers,
+    };
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    std::fs::write(&out, json).map_err(|e| e.to_string())?;
+    println!("Report written to {out}");
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Manage periodic southbridge reads running on the jig
+//!
+//! Usage:
+//!
+//! * `poll add <addr> <tx-hex> <rx-len> <interval-ms> [delay-us] [--on-change]`
+//! * `poll list`
+//! * `poll cancel <id>`
+//! * `poll watch [id]`
+//!
+//! Numbers may be given in decimal or with a `0x` prefix. `tx-hex` is the bytes
+//! to write, e.g. `09` or `0a0b`.
+//!
+//! Each also takes `--jig <serial|alias>`, `--server <addr>` and `--usb`, to
+//! pick the jig as with `demo`, see `demo::connect`.
+
+use demo::{
+    args::{arg, parse_addr, parse_hex, parse_num, take_switch},
+    connect::JigArgs,
+};
+use picocalc_jig_icd::*;
+
+fn parse_job(args: &[String]) -> Result<PollJob, String> {
+    let mut args = args.to_vec();
+    let only_on_change = take_switch(&mut args, "--on-change");
+
+    let tx = parse_hex(arg(&args, 1, "tx-hex")?)?;
+    if tx.len() > MAX_POLL_TX {
+        return Err(format!("at most {MAX_POLL_TX} bytes can be written"));
+    }
+    let mut tx_data = [0u8; MAX_POLL_TX];
+    tx_data[..tx.len()].copy_from_slice(&tx);
+    Ok(PollJob {
+        addr: parse_addr(arg(&args, 0, "addr")?)?,
+        tx_len: tx.len() as u8,
+        tx_data,
+        rx_len: parse_num(arg(&args, 2, "rx-len")?)?,
+        delay_us: match args.get(4) {
+            Some(d) => parse_num(d)?,
+            None => 0,
+        },
+        interval_ms: parse_num(arg(&args, 3, "interval-ms")?)?,
+        only_on_change,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let jig = JigArgs::take(&mut args)?
+        .connect()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match args.first().map(String::as_str) {
+        Some("add") => {
+            let job = parse_job(&args[1..])?;
+            let id = jig
+                .poll_register(job)
+                .await
+                .map_err(|e| format!("job rejected: {e}"))?;
+            println!("Registered job {id}");
+        }
+        Some("list") => {
+            let jobs = jig.poll_list().await.map_err(|e| e.to_string())?;
+            for PollJobInfo { id, job } in jobs {
+                println!(
+                    "{id}: addr 0x{:02X}, write {:02X?}, read {}, every {} ms{}",
+                    job.addr,
+                    &job.tx_data[..job.tx_len as usize],
+                    job.rx_len,
+                    job.interval_ms,
+                    if job.only_on_change {
+                        ", on change"
+                    } else {
+                        ""
+                    },
+                );
+            }
+        }
+        Some("cancel") => {
+            let id = parse_num(arg(&args, 1, "id")?)?;
+            jig.poll_cancel(id)
+                .await
+                .map_err(|e| format!("cancel failed: {e}"))?;
+            println!("Cancelled job {id}");
+        }
+        Some("watch") => {
+            let only: Option<u32> = args.get(1).map(|id| parse_num(id)).transpose()?;
+            let mut sub = jig.poll_results().await.map_err(|e| e.to_string())?;
+            while let Some(res) = sub.recv().await {
+                if only.is_some_and(|id| id != res.id) {
+                    continue;
+                }
+                match res.data {
+                    Ok(data) => println!("[{}] {}: {:02X?}", res.timestamp_us, res.id, data.data),
+                    Err(e) => println!("[{}] {}: {e:?}", res.timestamp_us, res.id),
+                }
+            }
+        }
+        _ => return Err("expected one of: add, list, cancel, watch".into()),
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! Talk to SMBus devices on the southbridge bus
+//!
+//! Usage: `smbus [--pec] <addr> <op> [args...]`, where `op` is one of:
+//!
+//! * `quick-read`, `quick-write`
+//! * `send <byte>`, `receive`
+//! * `write-byte <cmd> <value>`, `read-byte <cmd>`
+//! * `write-word <cmd> <value>`, `read-word <cmd>`
+//! * `process-call <cmd> <value>`
+//! * `block-write <cmd> <hex>`, `block-read <cmd>`
+//!
+//! Numbers may be given in decimal or with a `0x` prefix.
+//!
+//! Each also takes `--jig <serial|alias>`, `--server <addr>` and `--usb`, to
+//! pick the jig as with `demo`, see `demo::connect`.
+
+use demo::{
+    args::{arg, parse_addr, parse_hex, parse_num, take_switch},
+    connect::JigArgs,
+};
+use picocalc_jig_host::smbus::{Smbus, SmbusDev};
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let jig_args = JigArgs::take(&mut args)?;
+    let pec = take_switch(&mut args, "--pec");
+
+    let addr = parse_addr(arg(&args, 0, "addr")?)?;
+    let op = arg(&args, 1, "op")?;
+    let num = |idx: usize, name: &str| arg(&args, idx, name).and_then(parse_num::<u16>);
+    let byte = |idx: usize, name: &str| arg(&args, idx, name).and_then(parse_num::<u8>);
+
+    let jig = jig_args.connect().await.map_err(|e| e.to_string())?;
+    let mut dev = SmbusDev::new(jig, pec);
+
+    let res = match op {
+        "quick-read" => dev.quick(addr, true).await.map(|()| "ACK".to_string()),
+        "quick-write" => dev.quick(addr, false).await.map(|()| "ACK".to_string()),
+        "send" => dev
+            .send_byte(addr, byte(2, "byte")?)
+            .await
+            .map(|()| "OK".to_string()),
+        "receive" => dev.receive_byte(addr).await.map(|b| format!("0x{b:02X}")),
+        "write-byte" => dev
+            .write_byte(addr, byte(2, "cmd")?, byte(3, "value")?)
+            .await
+            .map(|()| "OK".to_string()),
+        "read-byte" => dev
+            .read_byte(addr, byte(2, "cmd")?)
+            .await
+            .map(|b| format!("0x{b:02X}")),
+        "write-word" => dev
+            .write_word(addr, byte(2, "cmd")?, num(3, "value")?)
+            .await
+            .map(|()| "OK".to_string()),
+        "read-word" => dev
+            .read_word(addr, byte(2, "cmd")?)
+            .await
+            .map(|w| format!("0x{w:04X}")),
+        "process-call" => dev
+            .process_call(addr, byte(2, "cmd")?, num(3, "value")?)
+            .await
+            .map(|w| format!("0x{w:04X}")),
+        "block-write" => dev
+            .block_write(addr, byte(2, "cmd")?, &parse_hex(arg(&args, 3, "hex")?)?)
+            .await
+            .map(|()| "OK".to_string()),
+        "block-read" => dev
+            .block_read(addr, byte(2, "cmd")?)
+            .await
+            .map(|data| format!("{data:02X?}")),
+        _ => return Err(format!("unknown op '{op}'")),
+    };
+    println!("{}", res.map_err(|e| e.to_string())?);
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Impersonate the southbridge, feeding key presses to firmware on another Pico
+//!
+//! Usage:
+//!
+//! * `target on`, `target off`
+//! * `target status`
+//! * `target type <text>`, pressing and releasing each character in turn
+//! * `target key <code> [press|hold|release]`, where `code` is a southbridge
+//!   key code, e.g. `0xB5` for up on the D-pad
+//!
+//! Numbers may be given in decimal or with a `0x` prefix.
+//!
+//! Each also takes `--jig <serial|alias>`, `--server <addr>` and `--usb`, to
+//! pick the jig as with `demo`, see `demo::connect`.
+
+use std::time::Duration;
+
+use demo::{
+    args::{arg, parse_num},
+    connect::{AnyTransport, JigArgs},
+};
+use picocalc_jig_host::Jig;
+use picocalc_jig_icd::*;
+
+/// The southbridge key code for a character, if there is one
+fn key_code(ch: char) -> Option<u8> {
+    match ch {
+        '\n' => Some(0x0A),
+        '\t' => Some(0x09),
+        ch if ch.is_ascii() && !ch.is_ascii_control() => Some(ch as u8),
+        _ => None,
+    }
+}
+
+async fn set_mode(jig: &Jig<AnyTransport>, enabled: bool) -> Result<(), String> {
+    jig.set_target_mode(enabled)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Queue a key event, waiting for room in the jig's FIFO first
+async fn send_key(jig: &Jig<AnyTransport>, state: KeyEventState, key: u8) -> Result<(), String> {
+    loop {
+        let status = jig.target_status().await.map_err(|e| e.to_string())?;
+        if (status.queued as usize) < TARGET_KEY_FIFO_LEN {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    jig.send_target_key(TargetKey { state, key })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let jig = JigArgs::take(&mut args)?
+        .connect()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match arg(&args, 0, "command")? {
+        "on" => set_mode(&jig, true).await?,
+        "off" => set_mode(&jig, false).await?,
+        "status" => {
+            let status = jig.target_status().await.map_err(|e| e.to_string())?;
+            println!("{status:?}");
+        }
+        "type" => {
+            for ch in arg(&args, 1, "text")?.chars() {
+                let key = key_code(ch).ok_or_else(|| format!("can't type {ch:?}"))?;
+                send_key(&jig, KeyEventState::Pressed, key).await?;
+                send_key(&jig, KeyEventState::Released, key).await?;
+            }
+        }
+        "key" => {
+            let key = parse_num(arg(&args, 1, "code")?)?;
+            let states: &[KeyEventState] = match args.get(2).map(String::as_str) {
+                None => &[KeyEventState::Pressed, KeyEventState::Released],
+                Some("press") => &[KeyEventState::Pressed],
+                Some("hold") => &[KeyEventState::Hold],
+                Some("release") => &[KeyEventState::Released],
+                Some(s) => return Err(format!("unknown key state '{s}'")),
+            };
+            for state in states {
+                send_key(&jig, *state, key).await?;
+            }
+        }
+        cmd => return Err(format!("unknown command '{cmd}'")),
+    }
+    Ok(())
+}
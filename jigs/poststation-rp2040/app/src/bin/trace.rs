@@ -0,0 +1,92 @@
+//! Trace the transactions on the southbridge bus
+//!
+//! Usage:
+//!
+//! * `trace on`, `trace off`
+//! * `trace watch [--save <file>]`, turning tracing on and printing each
+//!   transaction. With `--save`, events are also appended to `file` as JSON,
+//!   one per line.
+//!
+//! Each also takes `--jig <serial|alias>`, `--server <addr>` and `--usb`, to
+//! pick the jig as with `demo`, see `demo::connect`.
+
+use std::{fs::OpenOptions, io::Write};
+
+use demo::connect::{AnyTransport, JigArgs};
+use picocalc_jig_host::Jig;
+use picocalc_jig_icd::*;
+
+fn data(kept: &[u8], len: u32) -> String {
+    match (len as usize).saturating_sub(kept.len()) {
+        0 => format!("{kept:02X?}"),
+        more => format!("{kept:02X?} (+{more} bytes)"),
+    }
+}
+
+fn pretty(ev: &I2cTraceEvent) -> String {
+    let mut line = format!(
+        "[{:>12}us +{:>6}us] {:<6} 0x{:02X}",
+        ev.start_us,
+        ev.duration_us,
+        format!("{:?}", ev.user),
+        ev.addr
+    );
+    if matches!(ev.dir, I2cDirection::Write | I2cDirection::WriteRead) {
+        line += &format!(" W {}", data(&ev.write, ev.write_len));
+    }
+    if matches!(ev.dir, I2cDirection::Read | I2cDirection::WriteRead) {
+        line += &format!(" R {}", data(&ev.read, ev.read_len));
+    }
+    match ev.result {
+        Ok(()) => line += " ok",
+        Err(e) => line += &format!(" {e:?}"),
+    }
+    line
+}
+
+async fn set_enabled(jig: &Jig<AnyTransport>, on: bool) -> Result<(), String> {
+    jig.set_trace(on).await.map_err(|e| e.to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let jig = JigArgs::take(&mut args)?
+        .connect()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match args.first().map(String::as_str) {
+        Some("on") => set_enabled(&jig, true).await?,
+        Some("off") => set_enabled(&jig, false).await?,
+        Some("watch") => {
+            let mut save = match args.get(1).map(String::as_str) {
+                Some("--save") => {
+                    let path = args.get(2).ok_or("missing <file>")?;
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .map_err(|e| e.to_string())?;
+                    Some(file)
+                }
+                Some(a) => return Err(format!("unexpected '{a}'")),
+                None => None,
+            };
+            let mut sub = jig.trace_events().await.map_err(|e| e.to_string())?;
+            set_enabled(&jig, true).await?;
+            while let Some(ev) = sub.recv().await {
+                if ev.dropped != 0 {
+                    println!("... {} events dropped", ev.dropped);
+                }
+                println!("{}", pretty(&ev));
+                if let Some(file) = save.as_mut() {
+                    let json = serde_json::to_string(&ev).map_err(|e| e.to_string())?;
+                    writeln!(file, "{json}").map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        _ => return Err("expected one of: on, off, watch".into()),
+    }
+    Ok(())
+}
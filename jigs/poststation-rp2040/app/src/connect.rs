@@ -0,0 +1,89 @@
+//! Picking the jig to talk to, with the flags every tool takes
+//!
+//! `--jig <serial|alias>` names the jig, see `discovery::select` for what
+//! happens without it. `--server <addr>` is the poststation server to go
+//! through, and `--usb` skips poststation to open a jig plugged into this
+//! machine.
+
+use picocalc_jig_host::{
+    discovery::{self, DiscoveryError},
+    transport::{DirectUsb, Poststation, Subscription, Transport, TransportError},
+    Jig,
+};
+use postcard_rpc::{Endpoint, Topic};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::args::{take_flag, take_switch};
+
+/// Where poststation listens unless told otherwise
+pub const DEFAULT_SERVER: &str = "127.0.0.1:51837";
+
+/// Which jig to use, and how to reach it
+pub struct JigArgs {
+    pub jig: Option<String>,
+    pub server: String,
+    pub usb: bool,
+}
+
+impl JigArgs {
+    /// Remove the flags from `args`, leaving the tool's own
+    pub fn take(args: &mut Vec<String>) -> Result<Self, String> {
+        Ok(Self {
+            jig: take_flag(args, "--jig")?,
+            server: take_flag(args, "--server")?.unwrap_or_else(|| DEFAULT_SERVER.into()),
+            usb: take_switch(args, "--usb"),
+        })
+    }
+
+    /// Find the jig, and connect to it
+    pub async fn connect(&self) -> Result<Jig<AnyTransport>, DiscoveryError> {
+        let wanted = self.jig.as_deref();
+        let transport = match self.usb {
+            true => AnyTransport::Usb(discovery::usb(wanted)?),
+            false => AnyTransport::Poststation(discovery::poststation(&self.server, wanted).await?),
+        };
+        Ok(Jig::new(transport))
+    }
+}
+
+/// Either way of reaching a jig, as picked by [`JigArgs`]
+pub enum AnyTransport {
+    Poststation(Poststation),
+    Usb(DirectUsb),
+}
+
+impl Transport for AnyTransport {
+    async fn request<E>(&self, seq_no: u32, req: &E::Request) -> Result<E::Response, TransportError>
+    where
+        E: Endpoint,
+        E::Request: Serialize,
+        E::Response: DeserializeOwned,
+    {
+        match self {
+            AnyTransport::Poststation(t) => t.request::<E>(seq_no, req).await,
+            AnyTransport::Usb(t) => t.request::<E>(seq_no, req).await,
+        }
+    }
+
+    async fn publish<T>(&self, seq_no: u32, msg: &T::Message) -> Result<(), TransportError>
+    where
+        T: Topic,
+        T::Message: Serialize,
+    {
+        match self {
+            AnyTransport::Poststation(t) => t.publish::<T>(seq_no, msg).await,
+            AnyTransport::Usb(t) => t.publish::<T>(seq_no, msg).await,
+        }
+    }
+
+    async fn subscribe<T>(&self) -> Result<Subscription<T>, TransportError>
+    where
+        T: Topic,
+        T::Message: DeserializeOwned,
+    {
+        match self {
+            AnyTransport::Poststation(t) => t.subscribe::<T>().await,
+            AnyTransport::Usb(t) => t.subscribe::<T>().await,
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! What the command line tools share
+
+pub mod args;
+pub mod connect;
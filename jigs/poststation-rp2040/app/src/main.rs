@@ -1,334 +1,421 @@
-use std::{
-    sync::atomic::{AtomicU32, Ordering},
-    time::Duration,
-};
+//! Talk to a PicoCalc jig from the command line
+//!
+//! ```text
+//! demo list
+//! demo --jig quirky-toe-123 i2c write-read 0x1f 09 2
+//! demo --usb --json keyboard
+//! ```
+//!
+//! Without `--jig`, the jig is taken from `PICOCALC_JIG` or is the only one
+//! connected, see `discovery::select`. With `--json`, results are printed as
+//! one JSON object per line, and errors as `{"error": "..."}`.
+//!
+//! Lost connections are opened again, see `reconnect`. `keyboard` keeps trying
+//! for as long as it runs, reporting each disconnect and reconnect.
+
+use std::{fmt, process::ExitCode, time::Duration};
 
-use embedded_hal_async::i2c::{Error, ErrorType, I2c, Operation};
+use clap::{Parser, Subcommand, ValueEnum};
+use demo::{
+    args::{parse_addr, parse_hex, parse_num},
+    connect::DEFAULT_SERVER,
+};
+use embedded_hal_async::i2c::I2c;
+use picocalc_jig_host::{
+    discovery::{self, DiscoveryError},
+    keyboard::{KeyEvent, KeyState},
+    reconnect::{Connect, PoststationJig, Reconnecting, RetryPolicy, UsbJig},
+    transport::Transport,
+    Error, I2cDev, Jig,
+};
 use picocalc_jig_icd::*;
-use poststation_sdk::{connect, PoststationClient};
-use tokio::time::interval;
+use poststation_sdk::connect;
+use serde_json::{json, Value};
+use tokio::{sync::broadcast::error::RecvError, time::interval};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  1  the jig reported an error
+  2  bad arguments
+  3  the jig couldn't be found or reached";
 
-struct I2cDev {
-    serial: u64,
-    client: PoststationClient,
-    ctr: AtomicU32,
+#[derive(Parser)]
+#[command(about = "Talk to a PicoCalc jig", after_help = EXIT_CODES)]
+struct Cli {
+    /// Address of the poststation server
+    #[arg(long, global = true, default_value = DEFAULT_SERVER)]
+    server: String,
+    /// Talk to a jig plugged into this machine, without poststation
+    #[arg(long, global = true)]
+    usb: bool,
+    /// Serial number or poststation alias of the jig to use
+    #[arg(long, global = true)]
+    jig: Option<String>,
+    /// Print results as JSON, one object per line
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
 }
 
-#[derive(Debug)]
-enum HostI2CError {
-    ConnectionError,
-    DeviceError,
-    NotYetSupported,
+#[derive(Subcommand)]
+enum Command {
+    /// List the jigs that can be used
+    List,
+    #[command(flatten)]
+    Jig(JigCommand),
 }
 
-impl Error for HostI2CError {
-    fn kind(&self) -> embedded_hal_async::i2c::ErrorKind {
-        embedded_hal_async::i2c::ErrorKind::Other
-    }
+/// Commands run on one jig
+#[derive(Subcommand)]
+enum JigCommand {
+    /// Print the jig's unique ID
+    UniqueId,
+    /// Get or set the jig's LED
+    #[command(subcommand)]
+    Led(LedCommand),
+    /// Have the jig wait, printing how long it actually waited
+    Sleep { millis: u16 },
+    /// Transfers on the PicoCalc's I2C bus
+    #[command(subcommand)]
+    I2c(I2cCommand),
+    /// Print keys pressed on the PicoCalc's keyboard, until interrupted
+    Keyboard,
+    /// Reset the jig into the RP2040's USB bootloader
+    RebootToPicoboot,
 }
 
-impl ErrorType for I2cDev {
-    type Error = HostI2CError;
+#[derive(Subcommand)]
+enum LedCommand {
+    Get,
+    Set { state: LedArg },
 }
 
-impl I2c for I2cDev {
-    async fn transaction(
-        &mut self,
-        address: u8,
-        operations: &mut [Operation<'_>],
-    ) -> Result<(), Self::Error> {
-        // TODO: impl operations for real. For now, it'd probably be easy to just
-        // support read/write/write_then_read.
-        match operations {
-            [] => Ok(()),
-            [Operation::Read(buf)] => self.read(address, buf).await,
-            [Operation::Write(buf)] => self.write(address, buf).await,
-            [Operation::Write(tx), Operation::Read(rx)] => self.write_read(address, tx, rx).await,
-            _ => Err(HostI2CError::NotYetSupported),
-        }
-    }
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LedArg {
+    On,
+    Off,
+}
 
-    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
-        let Ok(res) = self
-            .client
-            .proxy_endpoint::<I2cReadEndpoint>(
-                self.serial,
-                self.ctr(),
-                &ReadCommand {
-                    addr: address,
-                    len: read.len() as u32,
-                },
-            )
-            .await
-        else {
-            return Err(HostI2CError::ConnectionError);
-        };
+/// Addresses are 7 bits, in decimal or with `0x`. Data is hex, e.g. `0a1b2c`
+#[derive(Subcommand)]
+enum I2cCommand {
+    Read {
+        #[arg(value_parser = parse_addr)]
+        addr: u8,
+        #[arg(value_parser = parse_len)]
+        len: usize,
+    },
+    Write {
+        #[arg(value_parser = parse_addr)]
+        addr: u8,
+        #[arg(value_parser = parse_data)]
+        data: Hex,
+    },
+    /// Write then read, joined by a repeated START
+    WriteRead {
+        #[arg(value_parser = parse_addr)]
+        addr: u8,
+        #[arg(value_parser = parse_data)]
+        data: Hex,
+        #[arg(value_parser = parse_len)]
+        len: usize,
+    },
+}
+
+#[derive(Clone)]
+struct Hex(Vec<u8>);
 
-        let Ok(data) = res else {
-            return Err(HostI2CError::DeviceError);
-        };
+fn parse_data(s: &str) -> Result<Hex, String> {
+    parse_hex(s).map(Hex)
+}
 
-        read.copy_from_slice(&data.data);
-        Ok(())
+/// How many bytes to read, at most what fits in the jig's bulk buffer
+fn parse_len(s: &str) -> Result<usize, String> {
+    match parse_num(s)? {
+        len @ 0..=I2C_BULK_LEN => Ok(len),
+        _ => Err(format!("at most {I2C_BULK_LEN} bytes can be read")),
     }
+}
 
-    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
-        let res = self
-            .client
-            .proxy_endpoint::<I2cWriteEndpoint>(
-                self.serial,
-                self.ctr(),
-                &WriteCommand {
-                    addr: address,
-                    data: write.to_vec(),
-                },
-            )
-            .await;
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
 
-        match res {
-            Ok(Ok(())) => Ok(()),
-            Ok(Err(I2cError)) => Err(HostI2CError::DeviceError),
-            Err(_) => Err(HostI2CError::ConnectionError),
+/// Why a command failed, which decides the exit code
+enum Failure {
+    /// The jig ran the request, and reported an error
+    Jig(Error),
+    /// The jig couldn't be found, or the request didn't reach it
+    Connection(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Failure::Jig(_) => ExitCode::from(1),
+            Failure::Connection(_) => ExitCode::from(3),
         }
     }
+}
 
-    async fn write_read(
-        &mut self,
-        address: u8,
-        write: &[u8],
-        read: &mut [u8],
-    ) -> Result<(), Self::Error> {
-        let res = self
-            .client
-            .proxy_endpoint::<I2cWriteReadEndpoint>(
-                self.serial,
-                self.ctr(),
-                &WriteReadCommand {
-                    addr: address,
-                    tx_data: write.to_vec(),
-                    rx_len: read.len() as u32,
-                },
-            )
-            .await;
-
-        match res {
-            Ok(Ok(resp)) => {
-                read.copy_from_slice(&resp.data);
-                Ok(())
-            }
-            Ok(Err(I2cError)) => Err(HostI2CError::DeviceError),
-            Err(_) => Err(HostI2CError::ConnectionError),
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Jig(e) => write!(f, "{e}"),
+            Failure::Connection(e) => write!(f, "{e}"),
         }
     }
 }
 
-impl I2cDev {
-    pub fn new(client: PoststationClient, serial: u64) -> Self {
-        Self {
-            serial,
-            client,
-            ctr: AtomicU32::new(0),
+impl From<Error> for Failure {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Transport(e) => Failure::Connection(e.to_string()),
+            e => Failure::Jig(e),
         }
     }
+}
 
-    #[inline(always)]
-    fn ctr(&self) -> u32 {
-        self.ctr.fetch_add(1, Ordering::Relaxed)
+impl From<DiscoveryError> for Failure {
+    fn from(value: DiscoveryError) -> Self {
+        Failure::Connection(value.to_string())
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), String> {
-    const SERIAL: u64 = 0xE66430A64B335337u64;
-    let client = connect("127.0.0.1:51837").await.unwrap();
-    let mut i2c = I2cDev::new(client, SERIAL);
-
-    // Use our client device as if it was a local I2C port with
-    // embedded-hal-async traits
-    let mut data = [0u8; 2];
-    // #define I2C_KBD_ADDR 0x1F
-    let addr = 0x1F;
+/// Print `value` in JSON mode, `text` otherwise
+fn print(json: bool, value: Value, text: impl fmt::Display) {
+    match json {
+        true => println!("{value}"),
+        false => println!("{text}"),
+    }
+}
 
-    let mut ticker = interval(Duration::from_millis(50));
-    let mut state = KeyState::default();
-    loop {
-        ticker.tick().await;
-        // Something?
-        i2c.write(addr, &[0x09]).await.unwrap();
-        tokio::time::sleep(Duration::from_millis(16)).await;
-        i2c.read(addr, &mut data).await.unwrap();
+/// Report an error the command carries on after, like [`main`] reports the
+/// one it stops at
+fn warn(json: bool, e: impl fmt::Display) {
+    match json {
+        true => println!("{}", json!({ "error": e.to_string() })),
+        false => eprintln!("{e}"),
+    }
+}
 
-        let rpt = state.update(data);
-        if let Some(rpt) = rpt {
-            if matches!(rpt.evt, KeyEvent::Hold(_)) {
-                continue;
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match json {
+                true => println!("{}", json!({ "error": e.to_string() })),
+                false => eprintln!("error: {e}"),
             }
-            println!("{rpt:02X?}");
+            e.exit_code()
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Key {
-    Char(char),
-    LeftDPad,
-    UpDPad,
-    DownDPad,
-    RightDPad,
-    Func1,
-    Func2,
-    Func3,
-    Func4,
-    Func5,
-    Func6,
-    Func7,
-    Func8,
-    Func9,
-    Func10,
-    Esc,
-    Tab,
-    CapsLk,
-    Del,
-    Back,
-    Brk,
-    Home,
-    End,
-    Enter,
-    Ins,
-    Other(u8),
+async fn run(cli: Cli) -> Result<(), Failure> {
+    let wanted = cli.jig.as_deref();
+    let cmd = match cli.command {
+        Command::List => return list(&cli.server, cli.usb, cli.json).await,
+        Command::Jig(cmd) => cmd,
+    };
+    let policy = match cmd {
+        // Monitors keep going through cable bumps and resets
+        JigCommand::Keyboard => RetryPolicy {
+            max_attempts: None,
+            ..Default::default()
+        },
+        _ => RetryPolicy::default(),
+    };
+    match cli.usb {
+        true => {
+            let jigs = discovery::usb_jigs().map_err(|e| DiscoveryError::Usb(e.to_string()))?;
+            let usb = UsbJig {
+                serial: discovery::select(&jigs, wanted)?,
+            };
+            let transport = Reconnecting::open(usb, policy)
+                .await
+                .map_err(Failure::Connection)?;
+            run_jig(Jig::new(transport), cmd, cli.json).await
+        }
+        false => {
+            let jig = discovery::poststation(&cli.server, wanted).await?;
+            let connect = PoststationJig {
+                addr: cli.server.clone(),
+                serial: jig.serial,
+            };
+            let transport = Reconnecting::new(connect, jig, policy);
+            run_jig(Jig::new(transport), cmd, cli.json).await
+        }
+    }
 }
 
-#[derive(Debug)]
-enum KeyEvent {
-    Press(Key),
-    Release(Key),
-    Hold(Key),
-    Other([u8; 2]),
+async fn list(server: &str, usb: bool, json: bool) -> Result<(), Failure> {
+    let jigs = match usb {
+        true => discovery::usb_jigs().map_err(|e| Failure::Connection(e.to_string()))?,
+        false => {
+            let client = connect(server)
+                .await
+                .map_err(|e| Failure::Connection(e.to_string()))?;
+            discovery::poststation_jigs(&client)
+                .await
+                .map_err(|e| Failure::Connection(e.to_string()))?
+        }
+    };
+    for jig in jigs {
+        let value = json!({
+            "serial": format!("{:016X}", jig.serial),
+            "name": jig.name,
+            "connected": jig.connected,
+        });
+        print(json, value, jig);
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct KeyReport {
-    ctrl: bool,
-    shift: bool,
-    alt: bool,
-    evt: KeyEvent,
+async fn run_jig<C: Connect>(
+    jig: Jig<Reconnecting<C>>,
+    cmd: JigCommand,
+    json: bool,
+) -> Result<(), Failure> {
+    match cmd {
+        JigCommand::UniqueId => {
+            let id = format!("{:016X}", jig.unique_id().await?);
+            print(json, json!({ "unique_id": id }), id);
+        }
+        JigCommand::Led(LedCommand::Get) => {
+            let on = jig.led().await?;
+            print(json, json!({ "led": on }), if on { "on" } else { "off" });
+        }
+        JigCommand::Led(LedCommand::Set { state }) => {
+            jig.set_led(state == LedArg::On).await?;
+            if json {
+                println!("{}", json!({ "led": state == LedArg::On }));
+            }
+        }
+        JigCommand::Sleep { millis } => {
+            let slept = jig.sleep(millis).await?;
+            print(json, json!({ "slept_ms": slept }), format!("{slept} ms"));
+        }
+        JigCommand::I2c(cmd) => i2c(I2cDev::new(jig), cmd, json).await?,
+        JigCommand::Keyboard => keyboard(jig, json).await?,
+        JigCommand::RebootToPicoboot => match jig.reboot_to_picoboot().await {
+            // The jig usually resets before it can reply
+            Ok(()) | Err(Error::Transport(_)) => {
+                if json {
+                    println!("{}", json!({ "rebooted": true }));
+                }
+            }
+            Err(e) => return Err(e.into()),
+        },
+    }
+    Ok(())
 }
 
-#[derive(Default)]
-struct KeyState {
-    ctrl: bool,
-    lshift: bool,
-    rshift: bool,
-    alt: bool,
+/// Long transfers go through the jig's bulk buffer, see [`I2cDev`]
+async fn i2c<T: Transport>(mut dev: I2cDev<T>, cmd: I2cCommand, json: bool) -> Result<(), Error> {
+    match cmd {
+        I2cCommand::Read { addr, len } => {
+            let mut data = vec![0; len];
+            dev.read(addr, &mut data).await?;
+            print(json, json!({ "data": hex(&data) }), hex(&data));
+        }
+        I2cCommand::Write { addr, data } => {
+            dev.write(addr, &data.0).await?;
+            if json {
+                println!("{}", json!({ "written": data.0.len() }));
+            }
+        }
+        I2cCommand::WriteRead { addr, data, len } => {
+            let mut read = vec![0; len];
+            dev.write_read(addr, &data.0, &mut read).await?;
+            print(json, json!({ "data": hex(&read) }), hex(&read));
+        }
+    }
+    Ok(())
 }
 
-const SPECIALS: &[(u8, Key)] = &[
-    (0xB4, Key::LeftDPad),
-    (0xB5, Key::UpDPad),
-    (0xB6, Key::DownDPad),
-    (0xB7, Key::RightDPad),
-    (0x81, Key::Func1),
-    (0x82, Key::Func2),
-    (0x83, Key::Func3),
-    (0x84, Key::Func4),
-    (0x85, Key::Func5),
-    (0x86, Key::Func6),
-    (0x87, Key::Func7),
-    (0x88, Key::Func8),
-    (0x89, Key::Func9),
-    (0x90, Key::Func10),
-    (0xB1, Key::Esc),
-    (0x09, Key::Tab),
-    (0xC1, Key::CapsLk),
-    (0xD4, Key::Del),
-    (0x08, Key::Back),
-    (0xD0, Key::Brk),
-    (0xD2, Key::Home),
-    (0xD5, Key::End),
-    (0x0A, Key::Enter),
-    (0xD1, Key::Ins),
-];
-
-impl KeyState {
-    fn report(&self, evt: KeyEvent) -> KeyReport {
-        KeyReport {
-            ctrl: self.ctrl,
-            shift: self.lshift | self.rshift,
-            alt: self.alt,
-            evt,
+async fn keyboard<C: Connect>(jig: Jig<Reconnecting<C>>, json: bool) -> Result<(), Error> {
+    let mut events = jig.transport().events();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => print(json, json!({ "connection": event }), event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
         }
+    });
+
+    let mut data = [0u8; 2];
+    // #define I2C_KBD_ADDR 0x1F
+    let addr = 0x1F;
+
+    // Read the southbridge firmware version (REG_ID_VER) in a single round trip
+    let version = [
+        ScriptOp::Write {
+            addr,
+            data: &[0x01],
+        },
+        ScriptOp::Delay { us: 16_000 },
+        ScriptOp::Read { addr, len: 2 },
+    ];
+    match jig.run_script(&version).await {
+        Ok(ScriptReport {
+            status: ScriptStatus::Completed,
+            data,
+            ..
+        }) => print(
+            json,
+            json!({ "version": hex(&data) }),
+            format!("Southbridge version: {data:02X?}"),
+        ),
+        Ok(rpt) => warn(
+            json,
+            format!(
+                "Version script stopped at step {}: {:?}",
+                rpt.step, rpt.status
+            ),
+        ),
+        Err(e) => warn(json, format!("Version script failed: {e}")),
     }
 
-    fn update(&mut self, data: [u8; 2]) -> Option<KeyReport> {
-        if data == [0, 0] {
-            return None;
-        }
-        match data {
-            [1, 0xA2] => {
-                self.lshift = true;
-                None
-            }
-            [3, 0xA2] => {
-                self.lshift = false;
-                None
-            }
-            [1, 0xA3] => {
-                self.rshift = true;
-                None
-            }
-            [3, 0xA3] => {
-                self.rshift = false;
-                None
-            }
-            [1, 0xA5] => {
-                self.ctrl = true;
-                None
-            }
-            [3, 0xA5] => {
-                self.ctrl = false;
-                None
-            }
-            [1, 0xA1] => {
-                self.alt = true;
-                None
-            }
-            [3, 0xA1] => {
-                self.alt = false;
-                None
-            }
-            [1, n] if n.is_ascii() && !n.is_ascii_control() => {
-                let ch: char = n.into();
-                Some(self.report(KeyEvent::Press(Key::Char(ch))))
-            }
-            [2, n] if n.is_ascii() && !n.is_ascii_control() => {
-                let ch: char = n.into();
-                Some(self.report(KeyEvent::Hold(Key::Char(ch))))
-            }
-            [3, n] if n.is_ascii() && !n.is_ascii_control() => {
-                let ch: char = n.into();
-                Some(self.report(KeyEvent::Release(Key::Char(ch))))
+    let mut ticker = interval(Duration::from_millis(50));
+    let mut state = KeyState::default();
+    loop {
+        ticker.tick().await;
+        // Something?
+        let res = jig
+            .write_delay_read(addr, &[0x09], Duration::from_millis(16), &mut data)
+            .await;
+        match res {
+            Ok(()) => {}
+            Err(Error::I2c(_)) => {
+                // The southbridge may be holding the bus, try to free it
+                let rec = jig.recover_bus().await?;
+                let stats = jig.bus_stats().await?;
+                let text = stats.iter().fold(
+                    format!("I2C error, recovered bus: {rec:?}"),
+                    |text, stats| format!("{text}\n  {stats:?}"),
+                );
+                print(json, json!({ "recovered": rec, "stats": stats }), text);
+                continue;
             }
-            [a, b] => {
-                if let Some(f) =
-                    SPECIALS
-                        .iter()
-                        .find_map(|(idx, k)| if b == *idx { Some(k) } else { None })
-                {
-                    match a {
-                        1 => Some(self.report(KeyEvent::Press(*f))),
-                        2 => Some(self.report(KeyEvent::Hold(*f))),
-                        3 => Some(self.report(KeyEvent::Release(*f))),
-                        _ => Some(self.report(KeyEvent::Other(data))),
-                    }
-                } else {
-                    match a {
-                        1 => Some(self.report(KeyEvent::Press(Key::Other(b)))),
-                        2 => Some(self.report(KeyEvent::Hold(Key::Other(b)))),
-                        3 => Some(self.report(KeyEvent::Release(Key::Other(b)))),
-                        _ => Some(self.report(KeyEvent::Other(data))),
-                    }
-                }
+            // The next request reconnects, reported by the events above
+            Err(Error::Transport(e)) if e.is_disconnect() => continue,
+            Err(e) => return Err(e),
+        }
+
+        let rpt = state.update(data);
+        if let Some(rpt) = rpt {
+            if matches!(rpt.evt, KeyEvent::Hold(_)) {
+                continue;
             }
+            print(json, json!(rpt), format!("{rpt:02X?}"));
         }
     }
 }
@@ -0,0 +1,143 @@
+//! The jig's dispatcher, served over postcard-rpc's in-memory channels
+
+use std::{future::Future, sync::Arc};
+
+use picocalc_jig_icd::*;
+use picocalc_jig_logic::{bulk::Bulk, poll::Jobs};
+use postcard_rpc::{
+    define_dispatch,
+    server::{
+        impls::test_channels::dispatch_impl::{WireRxBuf, WireRxImpl, WireSpawnImpl, WireTxImpl},
+        Server, SpawnContext,
+    },
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    handlers::*,
+    mock::{MockBus, MockSpi},
+    pool::{Pool, PoolFull},
+};
+
+/// How many of each I2C request can be in flight at the same time, as on the
+/// firmware. More than that are refused with `WireError::FailedToSpawn`
+pub const I2C_QUEUE_DEPTH: usize = 4;
+
+/// A pool for each spawn handler, sized as the firmware's task pools
+#[derive(Clone)]
+pub struct Pools {
+    pub sleep: Pool,
+    pub i2c_read: Pool,
+    pub i2c_write: Pool,
+    pub i2c_write_read: Pool,
+    pub i2c_write_delay_read: Pool,
+    pub i2c_bulk_transfer: Pool,
+    pub smbus: Pool,
+    pub i2c_script: Pool,
+}
+
+impl Default for Pools {
+    fn default() -> Self {
+        Self {
+            sleep: Pool::new(3),
+            i2c_read: Pool::new(I2C_QUEUE_DEPTH),
+            i2c_write: Pool::new(I2C_QUEUE_DEPTH),
+            i2c_write_read: Pool::new(I2C_QUEUE_DEPTH),
+            i2c_write_delay_read: Pool::new(I2C_QUEUE_DEPTH),
+            i2c_bulk_transfer: Pool::new(1),
+            smbus: Pool::new(I2C_QUEUE_DEPTH),
+            i2c_script: Pool::new(I2C_QUEUE_DEPTH),
+        }
+    }
+}
+
+/// Run a spawn handler's task on tokio, unless its pool was full
+pub fn spawn_fn<F>(_sp: &WireSpawnImpl, task: Result<F, PoolFull>) -> Result<(), PoolFull>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(task?);
+    Ok(())
+}
+
+/// What the firmware's `Context` holds, with mocks for the peripherals
+pub struct Context {
+    pub unique_id: u64,
+    pub led: bool,
+    pub bus: MockBus,
+    /// The bulk transfer buffer, [`I2C_BULK_LEN`] bytes, and its session
+    pub bulk: Arc<Mutex<Bulk<Vec<u8>>>>,
+    pub spi: MockSpi,
+    /// Polling jobs are kept, but never run
+    pub jobs: Jobs,
+    pub pools: Pools,
+}
+
+impl SpawnContext for Context {
+    type SpawnCtxt = TaskContext;
+
+    fn spawn_ctxt(&mut self) -> Self::SpawnCtxt {
+        TaskContext {
+            bus: self.bus.clone(),
+            bulk: self.bulk.clone(),
+            pools: self.pools.clone(),
+        }
+    }
+}
+
+pub struct TaskContext {
+    pub bus: MockBus,
+    pub bulk: Arc<Mutex<Bulk<Vec<u8>>>>,
+    pub pools: Pools,
+}
+
+pub type AppTx = WireTxImpl;
+pub type AppRx = WireRxImpl;
+pub type AppServer = Server<AppTx, AppRx, WireRxBuf, MyApp>;
+
+// The same table as the firmware's, less the endpoints that need the RP2040
+// itself. Those get `WireError::UnknownKey`
+define_dispatch! {
+    app: MyApp;
+    spawn_fn: spawn_fn;
+    tx_impl: AppTx;
+    spawn_impl: WireSpawnImpl;
+    context: Context;
+
+    endpoints: {
+        list: ENDPOINT_LIST;
+
+        | EndpointTy                | kind      | handler                       |
+        | ----------                | ----      | -------                       |
+        | GetUniqueIdEndpoint       | blocking  | unique_id                     |
+        | SleepEndpoint             | spawn     | sleep_handler                 |
+        | SetLedEndpoint            | blocking  | set_led                       |
+        | GetLedEndpoint            | blocking  | get_led                       |
+        | I2cReadEndpoint           | spawn     | i2c_read                      |
+        | I2cWriteEndpoint          | spawn     | i2c_write                     |
+        | I2cWriteReadEndpoint      | spawn     | i2c_write_read                |
+        | I2cWriteDelayReadEndpoint | spawn     | i2c_write_delay_read          |
+        | I2cBulkStageEndpoint      | blocking  | i2c_bulk_stage                |
+        | I2cBulkTransferEndpoint   | spawn     | i2c_bulk_transfer             |
+        | I2cBulkFetchEndpoint      | blocking  | i2c_bulk_fetch                |
+        | SmbusEndpoint             | spawn     | smbus                         |
+        | I2cScriptEndpoint         | spawn     | i2c_script                    |
+        | SpiConfigureEndpoint      | blocking  | spi_configure                 |
+        | SpiTransferEndpoint       | blocking  | spi_transfer                  |
+        | ControlPinWriteEndpoint   | blocking  | control_pin_write             |
+        | PollRegisterEndpoint      | blocking  | poll_register                 |
+        | PollListEndpoint          | blocking  | poll_list                     |
+        | PollCancelEndpoint        | blocking  | poll_cancel                   |
+    };
+
+    topics_in: {
+        list: TOPICS_IN_LIST;
+
+        | TopicTy                   | kind      | handler                       |
+        | ----------                | ----      | -------                       |
+    };
+
+    topics_out: {
+        list: TOPICS_OUT_LIST;
+    };
+}
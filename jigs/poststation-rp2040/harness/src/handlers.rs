@@ -0,0 +1,330 @@
+//! The firmware's handlers, against the mock peripherals
+//!
+//! These only unpack requests and pack replies, as the firmware's do. Anything
+//! more belongs in `picocalc-jig-logic`, so that both sides run it.
+
+use std::{future::Future, sync::OnceLock, time::Instant};
+
+use picocalc_jig_icd::*;
+use picocalc_jig_logic::{
+    i2c, script,
+    smbus::{self, Reply},
+};
+use postcard_rpc::{header::VarHeader, server::Sender};
+
+use crate::{
+    app::{AppTx, Context, TaskContext},
+    mock::MockBus,
+    pool::PoolFull,
+};
+
+pub fn unique_id(context: &mut Context, _header: VarHeader, _arg: ()) -> u64 {
+    context.unique_id
+}
+
+pub fn set_led(context: &mut Context, _header: VarHeader, arg: LedState) {
+    context.led = matches!(arg, LedState::On);
+}
+
+pub fn get_led(context: &mut Context, _header: VarHeader, _arg: ()) -> LedState {
+    match context.led {
+        true => LedState::On,
+        false => LedState::Off,
+    }
+}
+
+pub fn i2c_read(
+    context: TaskContext,
+    header: VarHeader,
+    arg: ReadCommand,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.i2c_read.clone();
+    pool.task(i2c_read_task(context, header, arg, sender))
+}
+
+async fn i2c_read_task(
+    mut context: TaskContext,
+    header: VarHeader,
+    arg: ReadCommand,
+    sender: Sender<AppTx>,
+) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = i2c::read(&mut context.bus, arg.addr, arg.len, &mut buf)
+        .await
+        .map(|data| ReadData {
+            data: data.to_vec(),
+        });
+    let _ = sender.reply::<I2cReadEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_write(
+    context: TaskContext,
+    header: VarHeader,
+    arg: WriteCommand,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.i2c_write.clone();
+    pool.task(i2c_write_task(context, header, arg, sender))
+}
+
+async fn i2c_write_task(
+    mut context: TaskContext,
+    header: VarHeader,
+    arg: WriteCommand,
+    sender: Sender<AppTx>,
+) {
+    let res = i2c::write(&mut context.bus, arg.addr, &arg.data).await;
+    let _ = sender.reply::<I2cWriteEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_write_read(
+    context: TaskContext,
+    header: VarHeader,
+    arg: WriteReadCommand,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.i2c_write_read.clone();
+    pool.task(i2c_write_read_task(context, header, arg, sender))
+}
+
+async fn i2c_write_read_task(
+    mut context: TaskContext,
+    header: VarHeader,
+    arg: WriteReadCommand,
+    sender: Sender<AppTx>,
+) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = i2c::write_read(
+        &mut context.bus,
+        arg.addr,
+        &arg.tx_data,
+        arg.rx_len,
+        &mut buf,
+    )
+    .await
+    .map(|data| ReadData {
+        data: data.to_vec(),
+    });
+    let _ = sender
+        .reply::<I2cWriteReadEndpoint>(header.seq_no, &res)
+        .await;
+}
+
+pub fn i2c_write_delay_read(
+    context: TaskContext,
+    header: VarHeader,
+    arg: WriteDelayReadCommand,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.i2c_write_delay_read.clone();
+    pool.task(i2c_write_delay_read_task(context, header, arg, sender))
+}
+
+async fn i2c_write_delay_read_task(
+    context: TaskContext,
+    header: VarHeader,
+    arg: WriteDelayReadCommand,
+    sender: Sender<AppTx>,
+) {
+    let mut bus = context.bus;
+    let mut delay = bus.clone();
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = i2c::write_delay_read(
+        &mut bus,
+        &mut delay,
+        arg.addr,
+        &arg.tx_data,
+        arg.delay_us,
+        arg.rx_len,
+        &mut buf,
+    )
+    .await
+    .map(|data| ReadData {
+        data: data.to_vec(),
+    });
+    let _ = sender
+        .reply::<I2cWriteDelayReadEndpoint>(header.seq_no, &res)
+        .await;
+}
+
+/// Milliseconds since the first bulk request, the jig's clock for sessions
+fn now_ms() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+pub fn i2c_bulk_stage(
+    context: &mut Context,
+    _header: VarHeader,
+    arg: BulkStage,
+) -> BulkStageResult {
+    let mut bulk = context.bulk.try_lock().map_err(|_| I2cError::Busy)?;
+    bulk.stage(arg.session, arg.offset, &arg.data, now_ms())
+}
+
+pub fn i2c_bulk_transfer(
+    context: TaskContext,
+    header: VarHeader,
+    arg: BulkTransfer,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.i2c_bulk_transfer.clone();
+    pool.task(i2c_bulk_transfer_task(context, header, arg, sender))
+}
+
+async fn i2c_bulk_transfer_task(
+    mut context: TaskContext,
+    header: VarHeader,
+    arg: BulkTransfer,
+    sender: Sender<AppTx>,
+) {
+    let mut bulk = context.bulk.lock().await;
+    let res = bulk.transfer(&mut context.bus, arg, now_ms).await;
+    drop(bulk);
+    let _ = sender
+        .reply::<I2cBulkTransferEndpoint>(header.seq_no, &res)
+        .await;
+}
+
+pub fn i2c_bulk_fetch(context: &mut Context, _header: VarHeader, arg: BulkFetch) -> ReadResult {
+    let mut bulk = context.bulk.try_lock().map_err(|_| I2cError::Busy)?;
+    let mut out = vec![0; I2C_CHUNK_LEN];
+    bulk.fetch(arg, &mut out, now_ms()).map(|data| ReadData {
+        data: data.to_vec(),
+    })
+}
+
+pub fn smbus(
+    context: TaskContext,
+    header: VarHeader,
+    arg: SmbusCommand,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.smbus.clone();
+    pool.task(smbus_task(context, header, arg, sender))
+}
+
+async fn smbus_task(
+    mut context: TaskContext,
+    header: VarHeader,
+    arg: SmbusCommand,
+    sender: Sender<AppTx>,
+) {
+    let res = run_smbus(&mut context.bus, &arg).await;
+    let _ = sender.reply::<SmbusEndpoint>(header.seq_no, &res).await;
+}
+
+async fn run_smbus(bus: &mut MockBus, arg: &SmbusCommand) -> SmbusResult {
+    let req = smbus::Request::new(arg.addr, &arg.op, arg.pec)?;
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    Ok(match smbus::run(bus, arg.addr, &req, &mut buf).await? {
+        Reply::Done => SmbusResponse::Done,
+        Reply::Byte(b) => SmbusResponse::Byte(b),
+        Reply::Word(w) => SmbusResponse::Word(w),
+        Reply::Block(data) => SmbusResponse::Block(data.to_vec()),
+    })
+}
+
+pub fn i2c_script(
+    context: TaskContext,
+    header: VarHeader,
+    arg: ScriptCommand,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.i2c_script.clone();
+    pool.task(i2c_script_task(context, header, arg, sender))
+}
+
+async fn i2c_script_task(
+    mut context: TaskContext,
+    header: VarHeader,
+    arg: ScriptCommand,
+    sender: Sender<AppTx>,
+) {
+    let mut delay = context.bus.clone();
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let outcome = script::run(&mut context.bus, &mut delay, &arg.program, &mut buf).await;
+    let report = ScriptReport {
+        status: outcome.status,
+        step: outcome.step,
+        data: buf[..outcome.len].to_vec(),
+    };
+    let _ = sender
+        .reply::<I2cScriptEndpoint>(header.seq_no, &report)
+        .await;
+}
+
+pub fn spi_configure(
+    _context: &mut Context,
+    _header: VarHeader,
+    _arg: SpiConfig,
+) -> SpiConfigResult {
+    Ok(())
+}
+
+pub fn spi_transfer(
+    context: &mut Context,
+    _header: VarHeader,
+    arg: SpiTransfer,
+) -> SpiTransferResult {
+    let len = arg.tx.len().max(arg.rx_len as usize);
+    if len > SPI_CHUNK_LEN {
+        return Err(SpiError::TooLong);
+    }
+    let mut buf = arg.tx;
+    buf.resize(len, 0);
+    context.spi.transfer(arg.port, &mut buf);
+    buf.truncate(arg.rx_len as usize);
+    Ok(ReadData { data: buf })
+}
+
+pub fn control_pin_write(context: &mut Context, _header: VarHeader, arg: ControlPinWrite) {
+    context.spi.write_pin(arg);
+}
+
+pub fn poll_register(
+    context: &mut Context,
+    _header: VarHeader,
+    arg: PollJob,
+) -> PollRegisterResult {
+    context.jobs.register(arg)
+}
+
+pub fn poll_list(context: &mut Context, _header: VarHeader, _arg: ()) -> PollJobList {
+    context.jobs.list()
+}
+
+pub fn poll_cancel(context: &mut Context, _header: VarHeader, arg: u32) -> PollCancelResult {
+    context.jobs.cancel(arg)
+}
+
+pub fn sleep_handler(
+    context: TaskContext,
+    header: VarHeader,
+    arg: SleepMillis,
+    sender: Sender<AppTx>,
+) -> Result<impl Future<Output = ()>, PoolFull> {
+    let pool = context.pools.sleep.clone();
+    pool.task(sleep_task(context, header, arg, sender))
+}
+
+async fn sleep_task(
+    _context: TaskContext,
+    header: VarHeader,
+    arg: SleepMillis,
+    sender: Sender<AppTx>,
+) {
+    let _ = sender.log_str("Starting sleep...").await;
+    let start = Instant::now();
+    tokio::time::sleep(std::time::Duration::from_millis(arg.millis.into())).await;
+    let _ = sender.log_str("Finished sleep").await;
+    let _ = sender
+        .reply::<SleepEndpoint>(
+            header.seq_no,
+            &SleptMillis {
+                millis: start.elapsed().as_millis() as u16,
+            },
+        )
+        .await;
+}
@@ -0,0 +1,131 @@
+//! The jig's dispatcher, running on the host against mock peripherals
+//!
+//! [`Jig::start`] serves the same endpoints as the firmware over postcard-rpc's
+//! in-memory channels, with a [`MockBus`] in place of the southbridge bus. The
+//! handlers share their logic with the firmware through `picocalc-jig-logic`,
+//! so tests talking to a [`Jig`] with a normal [`HostClient`] cover what runs
+//! on the device.
+//!
+//! The SPI endpoints are served against a [`MockSpi`]. Polling jobs can be
+//! registered, listed and cancelled, but aren't run, so nothing is published
+//! on `jig/poll/result`.
+//!
+//! # Not served yet
+//!
+//! These endpoints drive the RP2040's own peripherals and answer
+//! `WireError::UnknownKey` here, so nothing on the host covers them:
+//!
+//! - GPIO and ADC: `jig/gpio/*`, `jig/adc/*`
+//! - Logic capture: `jig/capture/*`
+//! - Target mode: `jig/target/*` and the `jig/target/key` topic
+//! - Bus tracing, statistics and recovery: `jig/sb/i2c/trace/enable`,
+//!   `jig/sb/i2c/stats`, `jig/sb/i2c/recover`
+//! - Rebooting to the bootloader
+//!
+//! Serving them is follow-up work: each needs its logic moved into
+//! `picocalc-jig-logic` behind a trait for its peripheral, with a mock here
+//! like [`MockBus`].
+//!
+//! ```
+//! use picocalc_jig_harness::Jig;
+//! use picocalc_jig_icd::*;
+//!
+//! # tokio_test();
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn tokio_test() {
+//! let jig = Jig::with_target(0x1F);
+//! jig.bus.poke(0x1F, 0x01, &[0x10]);
+//!
+//! let cmd = WriteReadCommand { addr: 0x1F, tx_data: vec![0x01], rx_len: 1, timeout_ms: None };
+//! let res = jig.client.send_resp::<I2cWriteReadEndpoint>(&cmd).await.unwrap();
+//! assert_eq!(res.unwrap().data, [0x10]);
+//! # }
+//! ```
+
+pub mod app;
+pub mod handlers;
+pub mod mock;
+pub mod pool;
+
+use std::sync::Arc;
+
+use picocalc_jig_icd::I2C_BULK_LEN;
+use picocalc_jig_logic::{bulk::Bulk, poll::Jobs};
+use postcard_rpc::{
+    header::{VarKeyKind, VarSeqKind},
+    host_client::{test_channels::new_from_channels, HostClient},
+    server::impls::test_channels::{
+        dispatch_impl::{new_server_stoppable, Settings, Stopper},
+        ChannelWireRx, ChannelWireSpawn, ChannelWireTx,
+    },
+    standard_icd::WireError,
+};
+use tokio::sync::{mpsc, Mutex};
+
+pub use mock::{MockBus, MockSpi};
+
+use crate::app::{Context, MyApp, Pools};
+
+/// Unique ID the harness reports
+pub const UNIQUE_ID: u64 = 0xE66430A64B335337;
+
+/// A running dispatcher, and a client connected to it
+pub struct Jig {
+    pub client: HostClient<WireError>,
+    /// The southbridge bus the handlers use, shared with the test
+    pub bus: MockBus,
+    /// The SPI ports the handlers use, shared with the test
+    pub spi: MockSpi,
+    stopper: Stopper,
+}
+
+impl Jig {
+    /// Start serving on the current tokio runtime
+    pub fn start() -> Self {
+        let bus = MockBus::new();
+        let spi = MockSpi::new();
+        let context = Context {
+            unique_id: UNIQUE_ID,
+            led: false,
+            bus: bus.clone(),
+            bulk: Arc::new(Mutex::new(Bulk::new(vec![0; I2C_BULK_LEN]))),
+            spi: spi.clone(),
+            jobs: Jobs::new(),
+            pools: Pools::default(),
+        };
+
+        let (client_tx, server_rx) = mpsc::channel(16);
+        let (server_tx, client_rx) = mpsc::channel(16);
+        let app = MyApp::new(context, ChannelWireSpawn);
+        let settings = Settings {
+            tx: ChannelWireTx::new(server_tx),
+            rx: ChannelWireRx::new(server_rx),
+            buf: 1024,
+            kkind: VarKeyKind::Key8,
+        };
+        let (mut server, stopper) = new_server_stoppable(app, settings);
+        tokio::spawn(async move { server.run().await });
+
+        let client = new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+        Self {
+            client,
+            bus,
+            spi,
+            stopper,
+        }
+    }
+
+    /// Start serving, with a target at `addr` on the southbridge bus
+    pub fn with_target(addr: u8) -> Self {
+        let jig = Self::start();
+        jig.bus.add_target(addr);
+        jig
+    }
+}
+
+impl Drop for Jig {
+    fn drop(&mut self) {
+        self.client.close();
+        self.stopper.stop();
+    }
+}
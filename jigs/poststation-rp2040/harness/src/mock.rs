@@ -0,0 +1,311 @@
+//! Mock peripherals standing in for the southbridge bus and the SPI ports
+//!
+//! [`MockBus`] implements [`I2c`], [`DelayNs`] and [`QuickCommand`], so it can
+//! be passed wherever the firmware passes its `BusGuard` and `Delay`. Targets
+//! are simple register files: a write sets the register pointer then stores
+//! any following bytes, and reads return bytes from the pointer on. Everything
+//! done on the bus is kept in a log for tests to check, and a test can
+//! [stretch](MockBus::stretch) the clock to keep transactions waiting.
+//!
+//! [`MockSpi`] loops each SPI port's output back to its input, and logs
+//! transfers along with the control pins that were low at the time.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use embedded_hal_async::{
+    delay::DelayNs,
+    i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation},
+};
+use picocalc_jig_icd::{ControlPin, ControlPinWrite, GpioLevel, I2cError, SpiPort};
+use picocalc_jig_logic::smbus::QuickCommand;
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+/// [`I2cError`], for [`embedded_hal_async::i2c`] users
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockError(pub I2cError);
+
+impl Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        match self.0 {
+            I2cError::Bus => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl From<MockError> for I2cError {
+    fn from(value: MockError) -> Self {
+        value.0
+    }
+}
+
+/// One operation of a logged transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockOp {
+    Write(Vec<u8>),
+    /// The data that was read
+    Read(Vec<u8>),
+}
+
+/// Something that happened on the bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Transaction {
+        addr: u8,
+        ops: Vec<MockOp>,
+        result: Result<(), I2cError>,
+    },
+    Quick {
+        addr: u8,
+        read: bool,
+        result: Result<(), I2cError>,
+    },
+    Delay {
+        us: u32,
+    },
+}
+
+struct Target {
+    regs: [u8; 256],
+    /// Register the next read or write starts at
+    pointer: u8,
+}
+
+impl Target {
+    fn write(&mut self, data: &[u8]) {
+        let Some((&reg, rest)) = data.split_first() else {
+            return;
+        };
+        self.pointer = reg;
+        for &b in rest {
+            self.regs[self.pointer as usize] = b;
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        for b in buf {
+            *b = self.regs[self.pointer as usize];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    targets: BTreeMap<u8, Target>,
+    /// Errors the next transactions fail with, in order
+    failures: Vec<I2cError>,
+    log: Vec<Event>,
+}
+
+impl State {
+    /// Whether the next transaction to `addr` should go through
+    fn check(&mut self, addr: u8) -> Result<(), I2cError> {
+        if !self.failures.is_empty() {
+            return Err(self.failures.remove(0));
+        }
+        match self.targets.contains_key(&addr) {
+            true => Ok(()),
+            false => Err(I2cError::Bus),
+        }
+    }
+}
+
+/// A bus of register file targets. Clones share the same bus
+#[derive(Clone, Default)]
+pub struct MockBus {
+    state: Arc<Mutex<State>>,
+    /// Held for writing while the clock is stretched
+    clock: Arc<RwLock<()>>,
+}
+
+impl MockBus {
+    /// A bus with no targets on it
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        f(&mut self.state.lock().unwrap())
+    }
+
+    /// Put a target at `addr`, with every register cleared
+    pub fn add_target(&self, addr: u8) {
+        let target = Target {
+            regs: [0; 256],
+            pointer: 0,
+        };
+        self.with_state(|s| s.targets.insert(addr, target));
+    }
+
+    pub fn remove_target(&self, addr: u8) {
+        self.with_state(|s| s.targets.remove(&addr));
+    }
+
+    /// Set registers of `addr` from `reg` on, without using the bus
+    pub fn poke(&self, addr: u8, reg: u8, data: &[u8]) {
+        self.with_state(|s| {
+            let target = s.targets.get_mut(&addr).expect("no target at this address");
+            for (i, &b) in data.iter().enumerate() {
+                target.regs[reg.wrapping_add(i as u8) as usize] = b;
+            }
+        })
+    }
+
+    /// Read `len` registers of `addr` from `reg` on, without using the bus
+    pub fn peek(&self, addr: u8, reg: u8, len: usize) -> Vec<u8> {
+        self.with_state(|s| {
+            let target = s.targets.get(&addr).expect("no target at this address");
+            (0..len)
+                .map(|i| target.regs[reg.wrapping_add(i as u8) as usize])
+                .collect()
+        })
+    }
+
+    /// Fail the next transaction with `err`, whoever it's for
+    pub fn fail_next(&self, err: I2cError) {
+        self.with_state(|s| s.failures.push(err))
+    }
+
+    /// Hold the clock low, like a target stretching it. Transactions wait
+    /// until the guard is dropped
+    pub fn stretch(&self) -> OwnedRwLockWriteGuard<()> {
+        self.clock
+            .clone()
+            .try_write_owned()
+            .expect("the clock is already stretched")
+    }
+
+    /// Take everything logged so far
+    pub fn take_log(&self) -> Vec<Event> {
+        self.with_state(|s| std::mem::take(&mut s.log))
+    }
+}
+
+impl ErrorType for MockBus {
+    type Error = MockError;
+}
+
+impl I2c for MockBus {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let _clock = self.clock.read().await;
+        self.with_state(|s| {
+            let result = s.check(address);
+            if result.is_ok() {
+                let target = s.targets.get_mut(&address).unwrap();
+                for op in operations.iter_mut() {
+                    match op {
+                        Operation::Write(data) => target.write(data),
+                        Operation::Read(buf) => target.read(buf),
+                    }
+                }
+            }
+            let ops = operations
+                .iter()
+                .map(|op| match op {
+                    Operation::Write(data) => MockOp::Write(data.to_vec()),
+                    Operation::Read(data) => MockOp::Read(data.to_vec()),
+                })
+                .collect();
+            s.log.push(Event::Transaction {
+                addr: address,
+                ops,
+                result,
+            });
+            result.map_err(MockError)
+        })
+    }
+}
+
+impl QuickCommand for MockBus {
+    async fn quick(&mut self, addr: u8, read: bool) -> Result<(), I2cError> {
+        let _clock = self.clock.read().await;
+        self.with_state(|s| {
+            let result = s.check(addr);
+            s.log.push(Event::Quick { addr, read, result });
+            result
+        })
+    }
+}
+
+/// Delays are logged rather than waited for, so tests run at full speed
+impl DelayNs for MockBus {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.delay_us(ns.div_ceil(1000)).await
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        self.with_state(|s| s.log.push(Event::Delay { us }));
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        self.delay_us(ms.saturating_mul(1000)).await
+    }
+}
+
+/// Something that happened on the SPI ports or their control pins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpiEvent {
+    Transfer {
+        port: SpiPort,
+        /// Everything clocked out, padding included
+        tx: Vec<u8>,
+        /// Control pins that were low, e.g. chip selects
+        low: Vec<ControlPin>,
+    },
+    Pin {
+        pin: ControlPin,
+        level: GpioLevel,
+    },
+}
+
+#[derive(Default)]
+struct SpiState {
+    low: Vec<ControlPin>,
+    log: Vec<SpiEvent>,
+}
+
+/// SPI ports with their output looped back. Clones share the same ports
+#[derive(Clone, Default)]
+pub struct MockSpi {
+    state: Arc<Mutex<SpiState>>,
+}
+
+impl MockSpi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clock out `buf`, reading the same bytes back into it
+    pub fn transfer(&self, port: SpiPort, buf: &mut [u8]) {
+        let mut s = self.state.lock().unwrap();
+        let low = s.low.clone();
+        s.log.push(SpiEvent::Transfer {
+            port,
+            tx: buf.to_vec(),
+            low,
+        });
+    }
+
+    pub fn write_pin(&self, ControlPinWrite { pin, level }: ControlPinWrite) {
+        let mut s = self.state.lock().unwrap();
+        s.low.retain(|&p| p != pin);
+        if level == GpioLevel::Low {
+            s.low.push(pin);
+        }
+        s.log.push(SpiEvent::Pin { pin, level });
+    }
+
+    /// Take everything logged so far
+    pub fn take_log(&self) -> Vec<SpiEvent> {
+        std::mem::take(&mut self.state.lock().unwrap().log)
+    }
+}
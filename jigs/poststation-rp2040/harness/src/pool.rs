@@ -0,0 +1,57 @@
+//! Task pools, refusing tasks once they're full like embassy's
+//!
+//! The firmware's spawn handlers each have a fixed number of task slots, and
+//! the dispatcher answers `WireError::FailedToSpawn` when they're all taken.
+//! A [`Pool`] gives the harness's handlers the same limits.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// A handler's tasks, at most `size` of them running at a time. Clones share
+/// the same slots
+#[derive(Clone)]
+pub struct Pool {
+    running: Arc<AtomicUsize>,
+    size: usize,
+}
+
+/// Every slot of the pool was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolFull;
+
+/// A taken slot, given back when the task ends
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Pool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            running: Arc::new(AtomicUsize::new(0)),
+            size,
+        }
+    }
+
+    /// `fut` as one of the pool's tasks, unless the pool is full
+    pub fn task<F: Future>(&self, fut: F) -> Result<impl Future<Output = F::Output>, PoolFull> {
+        self.running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.size).then_some(n + 1)
+            })
+            .map_err(|_| PoolFull)?;
+        let slot = Slot(self.running.clone());
+        Ok(async move {
+            let _slot = slot;
+            fut.await
+        })
+    }
+}
@@ -0,0 +1,60 @@
+//! Endpoints that don't touch the bus
+
+use picocalc_jig_harness::{Jig, UNIQUE_ID};
+use picocalc_jig_icd::*;
+use postcard_rpc::{host_client::HostErr, standard_icd::WireError};
+
+#[tokio::test]
+async fn unique_id() {
+    let jig = Jig::start();
+    let id = jig
+        .client
+        .send_resp::<GetUniqueIdEndpoint>(&())
+        .await
+        .unwrap();
+    assert_eq!(id, UNIQUE_ID);
+}
+
+#[tokio::test]
+async fn led() {
+    let jig = Jig::start();
+    let led = jig.client.send_resp::<GetLedEndpoint>(&()).await.unwrap();
+    assert!(matches!(led, LedState::Off));
+
+    jig.client
+        .send_resp::<SetLedEndpoint>(&LedState::On)
+        .await
+        .unwrap();
+    let led = jig.client.send_resp::<GetLedEndpoint>(&()).await.unwrap();
+    assert!(matches!(led, LedState::On));
+}
+
+#[tokio::test]
+async fn sleep() {
+    let jig = Jig::start();
+    let slept = jig
+        .client
+        .send_resp::<SleepEndpoint>(&SleepMillis { millis: 20 })
+        .await
+        .unwrap();
+    assert!(slept.millis >= 20);
+}
+
+#[tokio::test]
+async fn hardware_endpoints_are_unknown() {
+    let jig = Jig::start();
+    let unknown = |res| matches!(res, Err(HostErr::Wire(WireError::UnknownKey)));
+
+    let gpio = jig
+        .client
+        .send_resp::<GpioReadEndpoint>(&ExpansionPin::Gpio2);
+    assert!(unknown(gpio.await.map(drop)));
+    let capture = jig.client.send_resp::<CaptureStatusEndpoint>(&());
+    assert!(unknown(capture.await.map(drop)));
+    let target = jig.client.send_resp::<TargetStatusEndpoint>(&());
+    assert!(unknown(target.await.map(drop)));
+    let stats = jig.client.send_resp::<I2cBusStatsEndpoint>(&());
+    assert!(unknown(stats.await.map(drop)));
+    let recover = jig.client.send_resp::<I2cRecoverEndpoint>(&());
+    assert!(unknown(recover.await.map(drop)));
+}
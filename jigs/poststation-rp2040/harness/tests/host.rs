@@ -0,0 +1,174 @@
+//! The host library's I2C and SPI ports, against the harness over its in-memory
+//! channels
+
+use std::sync::Arc;
+
+use embedded_hal::{digital::OutputPin, i2c::I2c as _};
+use embedded_hal_async::{
+    i2c::I2c as _,
+    spi::{Operation, SpiBus, SpiDevice},
+};
+use picocalc_jig_harness::{mock::SpiEvent, Jig};
+use picocalc_jig_host::{
+    blocking::BlockingI2cDev,
+    spi::{ControlPinDev, SpiBusDev, SpiDev},
+    transport::DirectUsb,
+    Error, I2cDev,
+};
+use picocalc_jig_icd::*;
+
+const ADDR: u8 = 0x50;
+
+fn host(jig: &Jig) -> picocalc_jig_host::Jig<DirectUsb> {
+    let usb = DirectUsb {
+        client: jig.client.clone(),
+    };
+    picocalc_jig_host::Jig::new(usb)
+}
+
+fn i2c(jig: &Jig) -> I2cDev<DirectUsb> {
+    I2cDev::new(host(jig))
+}
+
+#[tokio::test]
+async fn async_port() {
+    let jig = Jig::with_target(ADDR);
+    let mut i2c = i2c(&jig);
+    assert_eq!(
+        i2c.jig().unique_id().await.unwrap(),
+        picocalc_jig_harness::UNIQUE_ID
+    );
+
+    i2c.write(ADDR, &[0x10, 1, 2, 3]).await.unwrap();
+    assert_eq!(jig.bus.peek(ADDR, 0x10, 3), [1, 2, 3]);
+
+    let mut buf = [0u8; 3];
+    i2c.write_read(ADDR, &[0x10], &mut buf).await.unwrap();
+    assert_eq!(buf, [1, 2, 3]);
+
+    jig.bus.remove_target(ADDR);
+    let res = i2c.read(ADDR, &mut buf).await;
+    assert!(matches!(res, Err(Error::I2c(I2cError::Bus))), "{res:?}");
+}
+
+#[tokio::test]
+async fn async_port_goes_through_the_bulk_buffer() {
+    let jig = Jig::with_target(ADDR);
+    let mut i2c = i2c(&jig);
+
+    // Register pointer, then every register
+    let mut write = vec![0x00];
+    write.extend(0..=255u8);
+    i2c.write(ADDR, &write).await.unwrap();
+    assert_eq!(jig.bus.peek(ADDR, 0xFF, 1), [0xFF]);
+
+    // The pointer wraps around after the last register
+    let mut read = [0u8; 300];
+    i2c.write_read(ADDR, &[0x00], &mut read).await.unwrap();
+    let expected: Vec<u8> = (0..=255u8).chain(0..44).collect();
+    assert_eq!(read[..], expected[..]);
+}
+
+#[test]
+fn blocking_port() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let jig = runtime.block_on(async { Jig::with_target(ADDR) });
+    let mut i2c = BlockingI2cDev::with_handle(runtime.handle().clone(), i2c(&jig));
+
+    i2c.write(ADDR, &[0x20, 0xAB]).unwrap();
+    let mut buf = [0u8; 1];
+    i2c.write_read(ADDR, &[0x20], &mut buf).unwrap();
+    assert_eq!(buf, [0xAB]);
+
+    // Shapes the jig can't make in one request
+    let res = i2c.transaction(
+        ADDR,
+        &mut [
+            embedded_hal::i2c::Operation::Write(&[0x20]),
+            embedded_hal::i2c::Operation::Write(&[0x21]),
+        ],
+    );
+    assert!(matches!(res, Err(Error::Unsupported)), "{res:?}");
+
+    drop(i2c);
+    runtime.block_on(async { drop(jig) });
+}
+
+#[tokio::test]
+async fn spi_bus_splits_long_transfers() {
+    let jig = Jig::start();
+    let mut spi = SpiBusDev::new(Arc::new(host(&jig)), SpiPort::Spi1);
+
+    // The ports loop back, so what's read is what was written, then padding
+    let write: Vec<u8> = (0..=255u8).chain(0..44).collect();
+    let mut read = [0xAAu8; 310];
+    spi.transfer(&mut read, &write).await.unwrap();
+    assert_eq!(read[..300], write[..]);
+    assert_eq!(read[300..], [0; 10]);
+
+    let log = jig.spi.take_log();
+    let sizes: Vec<_> = log
+        .iter()
+        .map(|ev| match ev {
+            SpiEvent::Transfer { port, tx, .. } => (*port, tx.len()),
+            ev => panic!("unexpected {ev:?}"),
+        })
+        .collect();
+    assert_eq!(sizes, [(SpiPort::Spi1, 256), (SpiPort::Spi1, 54)]);
+}
+
+#[tokio::test]
+async fn spi_device_holds_chip_select() {
+    let jig = Jig::start();
+    let mut sd = SpiDev::new(Arc::new(host(&jig)), SpiPort::Spi0, ControlPin::SdCs);
+
+    let mut buf = [0x12, 0x34];
+    sd.transaction(&mut [
+        Operation::Write(&[0x40]),
+        Operation::TransferInPlace(&mut buf),
+    ])
+    .await
+    .unwrap();
+    assert_eq!(buf, [0x12, 0x34]);
+
+    let pin = |level| SpiEvent::Pin {
+        pin: ControlPin::SdCs,
+        level,
+    };
+    let xfer = |tx: &[u8]| SpiEvent::Transfer {
+        port: SpiPort::Spi0,
+        tx: tx.to_vec(),
+        low: vec![ControlPin::SdCs],
+    };
+    assert_eq!(
+        jig.spi.take_log(),
+        [
+            pin(GpioLevel::Low),
+            xfer(&[0x40]),
+            xfer(&[0x12, 0x34]),
+            pin(GpioLevel::High),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn control_pins() {
+    let jig = Jig::start();
+    let mut rst = ControlPinDev::new(Arc::new(host(&jig)), ControlPin::LcdRst);
+
+    rst.set_low().unwrap();
+    rst.set(GpioLevel::High).await.unwrap();
+    assert_eq!(
+        jig.spi.take_log(),
+        [
+            SpiEvent::Pin {
+                pin: ControlPin::LcdRst,
+                level: GpioLevel::Low
+            },
+            SpiEvent::Pin {
+                pin: ControlPin::LcdRst,
+                level: GpioLevel::High
+            },
+        ]
+    );
+}
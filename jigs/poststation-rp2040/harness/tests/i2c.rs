@@ -0,0 +1,332 @@
+//! Plain and bulk I2C transfers
+
+use picocalc_jig_harness::{
+    mock::{Event, MockOp},
+    Jig,
+};
+use picocalc_jig_icd::*;
+
+const ADDR: u8 = 0x50;
+
+#[tokio::test]
+async fn write_then_read() {
+    let jig = Jig::with_target(ADDR);
+    let cmd = WriteCommand {
+        addr: ADDR,
+        data: vec![0x10, 1, 2, 3],
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cWriteEndpoint>(&cmd)
+        .await
+        .unwrap();
+    assert_eq!(res, Ok(()));
+    assert_eq!(jig.bus.peek(ADDR, 0x10, 3), [1, 2, 3]);
+
+    // The register pointer was left after the written bytes
+    jig.bus.poke(ADDR, 0x13, &[4, 5]);
+    let cmd = ReadCommand {
+        addr: ADDR,
+        len: 2,
+        timeout_ms: None,
+    };
+    let res = jig.client.send_resp::<I2cReadEndpoint>(&cmd).await.unwrap();
+    assert_eq!(res.unwrap().data, [4, 5]);
+}
+
+#[tokio::test]
+async fn write_read() {
+    let jig = Jig::with_target(ADDR);
+    jig.bus.poke(ADDR, 0x20, &[0xAA, 0xBB]);
+    let cmd = WriteReadCommand {
+        addr: ADDR,
+        tx_data: vec![0x20],
+        rx_len: 2,
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cWriteReadEndpoint>(&cmd)
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap().data, [0xAA, 0xBB]);
+    assert_eq!(
+        jig.bus.take_log(),
+        [Event::Transaction {
+            addr: ADDR,
+            ops: vec![MockOp::Write(vec![0x20]), MockOp::Read(vec![0xAA, 0xBB])],
+            result: Ok(()),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn write_delay_read() {
+    let jig = Jig::with_target(ADDR);
+    jig.bus.poke(ADDR, 0x30, &[0x42]);
+    let cmd = WriteDelayReadCommand {
+        addr: ADDR,
+        tx_data: vec![0x30],
+        delay_us: 500,
+        rx_len: 1,
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cWriteDelayReadEndpoint>(&cmd)
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap().data, [0x42]);
+    assert_eq!(
+        jig.bus.take_log(),
+        [
+            Event::Transaction {
+                addr: ADDR,
+                ops: vec![MockOp::Write(vec![0x30])],
+                result: Ok(()),
+            },
+            Event::Delay { us: 500 },
+            Event::Transaction {
+                addr: ADDR,
+                ops: vec![MockOp::Read(vec![0x42])],
+                result: Ok(()),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn missing_target() {
+    let jig = Jig::with_target(ADDR);
+    let cmd = ReadCommand {
+        addr: 0x51,
+        len: 1,
+        timeout_ms: None,
+    };
+    let res = jig.client.send_resp::<I2cReadEndpoint>(&cmd).await.unwrap();
+    assert_eq!(res.unwrap_err(), I2cError::Bus);
+}
+
+#[tokio::test]
+async fn bus_errors_are_reported() {
+    let jig = Jig::with_target(ADDR);
+    jig.bus.fail_next(I2cError::Timeout);
+    let cmd = WriteCommand {
+        addr: ADDR,
+        data: vec![0],
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cWriteEndpoint>(&cmd)
+        .await
+        .unwrap();
+    assert_eq!(res, Err(I2cError::Timeout));
+}
+
+#[tokio::test]
+async fn too_long() {
+    let jig = Jig::with_target(ADDR);
+    let cmd = ReadCommand {
+        addr: ADDR,
+        len: I2C_CHUNK_LEN as u32 + 1,
+        timeout_ms: None,
+    };
+    let res = jig.client.send_resp::<I2cReadEndpoint>(&cmd).await.unwrap();
+    assert_eq!(res.unwrap_err(), I2cError::TooLong);
+
+    let cmd = WriteCommand {
+        addr: ADDR,
+        data: vec![0; I2C_CHUNK_LEN + 1],
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cWriteEndpoint>(&cmd)
+        .await
+        .unwrap();
+    assert_eq!(res, Err(I2cError::TooLong));
+    assert!(jig.bus.take_log().is_empty());
+}
+
+#[tokio::test]
+async fn bulk() {
+    let jig = Jig::with_target(ADDR);
+    let regs: Vec<u8> = (0..=255).collect();
+    jig.bus.poke(ADDR, 0, &regs);
+
+    // Stage the register number, then read the whole register file back
+    let stage = BulkStage {
+        session: None,
+        offset: 0,
+        data: vec![0x00],
+    };
+    let session = jig
+        .client
+        .send_resp::<I2cBulkStageEndpoint>(&stage)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let transfer = BulkTransfer {
+        session,
+        addr: ADDR,
+        tx_len: 1,
+        rx_len: 512,
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkTransferEndpoint>(&transfer)
+        .await
+        .unwrap();
+    assert_eq!(res, Ok(()));
+
+    let mut read = vec![];
+    for offset in (1..513).step_by(I2C_CHUNK_LEN) {
+        let fetch = BulkFetch {
+            session,
+            offset,
+            len: I2C_CHUNK_LEN as u32,
+        };
+        let res = jig
+            .client
+            .send_resp::<I2cBulkFetchEndpoint>(&fetch)
+            .await
+            .unwrap();
+        read.extend(res.unwrap().data);
+    }
+    assert_eq!(read[..256], regs);
+    assert_eq!(read[256..], regs);
+}
+
+#[tokio::test]
+async fn bulk_out_of_range() {
+    let jig = Jig::with_target(ADDR);
+    let stage = BulkStage {
+        session: None,
+        offset: I2C_BULK_LEN as u32 - 1,
+        data: vec![1, 2],
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkStageEndpoint>(&stage)
+        .await
+        .unwrap();
+    assert_eq!(res, Err(I2cError::TooLong));
+
+    // Each error ends the session
+    let session = bulk_session(&jig).await;
+    let transfer = BulkTransfer {
+        session,
+        addr: ADDR,
+        tx_len: 1,
+        rx_len: I2C_BULK_LEN as u32,
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkTransferEndpoint>(&transfer)
+        .await
+        .unwrap();
+    assert_eq!(res, Err(I2cError::TooLong));
+
+    let fetch = BulkFetch {
+        session: bulk_session(&jig).await,
+        offset: 0,
+        len: I2C_CHUNK_LEN as u32 + 1,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkFetchEndpoint>(&fetch)
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap_err(), I2cError::TooLong);
+}
+
+/// Start a bulk session, staging nothing
+async fn bulk_session(jig: &Jig) -> BulkSession {
+    let stage = BulkStage {
+        session: None,
+        offset: 0,
+        data: vec![],
+    };
+    jig.client
+        .send_resp::<I2cBulkStageEndpoint>(&stage)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn bulk_buffer_belongs_to_one_session() {
+    let jig = Jig::with_target(ADDR);
+    jig.bus.poke(ADDR, 0, &[0xAA, 0xBB]);
+    let session = bulk_session(&jig).await;
+
+    // Nobody else can start one, or use the buffer
+    let stage = BulkStage {
+        session: None,
+        offset: 0,
+        data: vec![0x00],
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkStageEndpoint>(&stage)
+        .await
+        .unwrap();
+    assert_eq!(res, Err(I2cError::Busy));
+    let stage = BulkStage {
+        session: Some(session.wrapping_add(1)),
+        ..stage
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkStageEndpoint>(&stage)
+        .await
+        .unwrap();
+    assert_eq!(res, Err(I2cError::Busy));
+
+    let transfer = BulkTransfer {
+        session,
+        addr: ADDR,
+        tx_len: 0,
+        rx_len: 2,
+        timeout_ms: None,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkTransferEndpoint>(&transfer)
+        .await
+        .unwrap();
+    assert_eq!(res, Ok(()));
+
+    let fetch = BulkFetch {
+        session: session.wrapping_add(1),
+        offset: 0,
+        len: 2,
+    };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkFetchEndpoint>(&fetch)
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap_err(), I2cError::Busy);
+
+    // Fetching everything that was read ends the session
+    let fetch = BulkFetch { session, ..fetch };
+    let res = jig
+        .client
+        .send_resp::<I2cBulkFetchEndpoint>(&fetch)
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap().data, [0xAA, 0xBB]);
+    let res = jig
+        .client
+        .send_resp::<I2cBulkFetchEndpoint>(&fetch)
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap_err(), I2cError::Busy);
+    assert_ne!(bulk_session(&jig).await, session);
+}
@@ -0,0 +1,69 @@
+//! Registering polling jobs. The harness doesn't run them
+
+use picocalc_jig_harness::Jig;
+use picocalc_jig_icd::*;
+
+const JOB: PollJob = PollJob {
+    addr: 0x1F,
+    tx_len: 1,
+    tx_data: [0x09, 0, 0, 0],
+    rx_len: 1,
+    delay_us: 0,
+    interval_ms: 50,
+    only_on_change: true,
+};
+
+async fn register(jig: &Jig, job: PollJob) -> PollRegisterResult {
+    jig.client
+        .send_resp::<PollRegisterEndpoint>(&job)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn register_list_cancel() {
+    let jig = Jig::start();
+    let id = register(&jig, JOB).await.unwrap();
+
+    let list = jig.client.send_resp::<PollListEndpoint>(&()).await.unwrap();
+    let jobs: Vec<_> = list.iter().flatten().collect();
+    assert_eq!(jobs, [&PollJobInfo { id, job: JOB }]);
+
+    let cancel = jig.client.send_resp::<PollCancelEndpoint>(&id);
+    assert_eq!(cancel.await.unwrap(), Ok(()));
+    let cancel = jig.client.send_resp::<PollCancelEndpoint>(&id);
+    assert_eq!(cancel.await.unwrap(), Err(PollError::NoSuchJob));
+    let list = jig.client.send_resp::<PollListEndpoint>(&()).await.unwrap();
+    assert!(list.iter().all(Option::is_none));
+}
+
+#[tokio::test]
+async fn empty_jobs_are_invalid() {
+    let jig = Jig::start();
+    let no_write = PollJob { tx_len: 0, ..JOB };
+    assert_eq!(register(&jig, no_write).await, Err(PollError::InvalidJob));
+    let no_read = PollJob { rx_len: 0, ..JOB };
+    assert_eq!(register(&jig, no_read).await, Err(PollError::InvalidJob));
+    let never = PollJob {
+        interval_ms: 0,
+        ..JOB
+    };
+    assert_eq!(register(&jig, never).await, Err(PollError::InvalidJob));
+    let too_long = PollJob {
+        rx_len: MAX_POLL_RX as u8 + 1,
+        ..JOB
+    };
+    assert_eq!(register(&jig, too_long).await, Err(PollError::InvalidJob));
+
+    let list = jig.client.send_resp::<PollListEndpoint>(&()).await.unwrap();
+    assert!(list.iter().all(Option::is_none));
+}
+
+#[tokio::test]
+async fn too_many_jobs() {
+    let jig = Jig::start();
+    for _ in 0..MAX_POLL_JOBS {
+        register(&jig, JOB).await.unwrap();
+    }
+    assert_eq!(register(&jig, JOB).await, Err(PollError::Full));
+}
@@ -0,0 +1,205 @@
+//! The host library reconnecting to a jig that went away
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use picocalc_jig_harness::{app::I2C_QUEUE_DEPTH, Jig, MockBus, UNIQUE_ID};
+use picocalc_jig_host::{
+    reconnect::{Connect, ConnectionEvent, Reconnecting, RetryPolicy},
+    transport::{DirectUsb, TransportError},
+    Error,
+};
+use postcard_rpc::{host_client::HostErr, standard_icd::WireError};
+use tokio::{sync::broadcast, task::JoinSet};
+
+const ADDR: u8 = 0x50;
+
+/// Starts a new harness for each connection, like a jig coming back after a
+/// reset
+#[derive(Default)]
+struct Restart {
+    jigs: Mutex<Vec<Jig>>,
+    /// Fail to connect, like a jig that stays away
+    gone: AtomicBool,
+}
+
+impl Restart {
+    /// Cut the connection to the latest jig
+    fn unplug(&self) {
+        self.jigs.lock().unwrap().last().unwrap().client.close();
+    }
+
+    /// The latest jig's bus
+    fn bus(&self) -> MockBus {
+        self.jigs.lock().unwrap().last().unwrap().bus.clone()
+    }
+
+    fn connections(&self) -> usize {
+        self.jigs.lock().unwrap().len()
+    }
+}
+
+impl Connect for Restart {
+    type Transport = DirectUsb;
+
+    async fn connect(&self) -> Result<DirectUsb, String> {
+        if self.gone.load(Ordering::Relaxed) {
+            return Err("no jig".into());
+        }
+        let jig = Jig::with_target(ADDR);
+        let usb = DirectUsb {
+            client: jig.client.clone(),
+        };
+        self.jigs.lock().unwrap().push(jig);
+        Ok(usb)
+    }
+}
+
+async fn host(policy: RetryPolicy) -> picocalc_jig_host::Jig<Reconnecting<Restart>> {
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..policy
+    };
+    let transport = Reconnecting::open(Restart::default(), policy)
+        .await
+        .unwrap();
+    picocalc_jig_host::Jig::new(transport)
+}
+
+fn drain(events: &mut broadcast::Receiver<ConnectionEvent>) -> Vec<ConnectionEvent> {
+    std::iter::from_fn(|| events.try_recv().ok()).collect()
+}
+
+#[tokio::test]
+async fn idempotent_requests_are_sent_again() {
+    let jig = host(RetryPolicy::default()).await;
+    let mut events = jig.transport().events();
+
+    jig.transport().connector().unplug();
+    assert_eq!(jig.unique_id().await.unwrap(), UNIQUE_ID);
+    assert_eq!(jig.transport().connector().connections(), 2);
+
+    let events = drain(&mut events);
+    assert!(
+        matches!(
+            events.as_slice(),
+            [ConnectionEvent::Lost(_), ConnectionEvent::Reconnected]
+        ),
+        "{events:?}"
+    );
+}
+
+#[tokio::test]
+async fn other_requests_fail_and_the_next_reconnects() {
+    let jig = host(RetryPolicy::default()).await;
+    let mut buf = [0u8; 2];
+    jig.i2c_read(ADDR, &mut buf).await.unwrap();
+
+    jig.transport().connector().unplug();
+    let res = jig.i2c_read(ADDR, &mut buf).await;
+    assert!(
+        matches!(&res, Err(Error::Transport(e)) if e.is_disconnect()),
+        "{res:?}"
+    );
+    assert_eq!(jig.transport().connector().connections(), 1);
+
+    jig.i2c_read(ADDR, &mut buf).await.unwrap();
+    assert_eq!(jig.transport().connector().connections(), 2);
+}
+
+#[tokio::test]
+async fn retrying_can_be_turned_off() {
+    let jig = host(RetryPolicy {
+        retry_idempotent: false,
+        ..Default::default()
+    })
+    .await;
+
+    jig.transport().connector().unplug();
+    assert!(jig.unique_id().await.is_err());
+    assert_eq!(jig.unique_id().await.unwrap(), UNIQUE_ID);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let jig = host(RetryPolicy {
+        max_attempts: Some(3),
+        ..Default::default()
+    })
+    .await;
+    let mut events = jig.transport().events();
+
+    jig.transport()
+        .connector()
+        .gone
+        .store(true, Ordering::Relaxed);
+    jig.transport().connector().unplug();
+    let res = jig.unique_id().await;
+    assert!(
+        matches!(res, Err(Error::Transport(TransportError::Reconnect(_)))),
+        "{res:?}"
+    );
+
+    let events = drain(&mut events);
+    assert!(
+        matches!(
+            events.as_slice(),
+            [
+                ConnectionEvent::Lost(_),
+                ConnectionEvent::Failed { attempt: 1, .. },
+                ConnectionEvent::Failed { attempt: 2, .. },
+                ConnectionEvent::Failed { attempt: 3, .. },
+                ConnectionEvent::GaveUp,
+            ]
+        ),
+        "{events:?}"
+    );
+
+    // The jig is back, and the next request starts over
+    jig.transport()
+        .connector()
+        .gone
+        .store(false, Ordering::Relaxed);
+    assert_eq!(jig.unique_id().await.unwrap(), UNIQUE_ID);
+}
+
+#[tokio::test]
+async fn refused_requests_do_not_reconnect() {
+    let jig = Arc::new(host(RetryPolicy::default()).await);
+    let mut events = jig.transport().events();
+
+    // Keep the first requests on the bus, so the rest find no room
+    let clock = jig.transport().connector().bus().stretch();
+    let mut reads = JoinSet::new();
+    for _ in 0..I2C_QUEUE_DEPTH + 2 {
+        let jig = jig.clone();
+        reads.spawn(async move {
+            let mut buf = [0u8; 2];
+            jig.i2c_read(ADDR, &mut buf).await
+        });
+    }
+    for _ in 0..2 {
+        let res = reads.join_next().await.unwrap().unwrap();
+        assert!(
+            matches!(
+                &res,
+                Err(Error::Transport(TransportError::Usb(HostErr::Wire(
+                    WireError::FailedToSpawn
+                ))))
+            ),
+            "{res:?}"
+        );
+    }
+
+    drop(clock);
+    while let Some(res) = reads.join_next().await {
+        res.unwrap().unwrap();
+    }
+    assert_eq!(jig.transport().connector().connections(), 1);
+    assert!(drain(&mut events).is_empty());
+}
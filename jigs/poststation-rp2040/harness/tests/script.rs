@@ -0,0 +1,173 @@
+//! Scripts, run start to end by the jig
+
+use picocalc_jig_harness::{mock::Event, Jig, UNIQUE_ID};
+use picocalc_jig_icd::*;
+
+const ADDR: u8 = 0x1F;
+
+async fn run(jig: &Jig, ops: &[ScriptOp<'_>]) -> ScriptReport {
+    let mut program = vec![];
+    for op in ops {
+        program.extend(postcard::to_stdvec(op).unwrap());
+    }
+    let cmd = ScriptCommand {
+        program,
+        timeout_ms: None,
+    };
+    jig.client
+        .send_resp::<I2cScriptEndpoint>(&cmd)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn completes() {
+    let jig = Jig::with_target(ADDR);
+    jig.bus.poke(ADDR, 0x01, &[0x10]);
+    let report = run(
+        &jig,
+        &[
+            ScriptOp::Write {
+                addr: ADDR,
+                data: &[0x01],
+            },
+            ScriptOp::Read { addr: ADDR, len: 1 },
+            ScriptOp::Expect {
+                mask: 0xF0,
+                value: 0x10,
+            },
+            ScriptOp::Delay { us: 100 },
+        ],
+    )
+    .await;
+    assert_eq!(report.status, ScriptStatus::Completed);
+    assert_eq!(report.step, 4);
+    assert_eq!(report.data, [0x10]);
+    assert_eq!(jig.bus.take_log().last(), Some(&Event::Delay { us: 100 }));
+}
+
+#[tokio::test]
+async fn mismatch() {
+    let jig = Jig::with_target(ADDR);
+    let report = run(
+        &jig,
+        &[
+            ScriptOp::Read { addr: ADDR, len: 1 },
+            ScriptOp::Expect {
+                mask: 0xFF,
+                value: 0x01,
+            },
+        ],
+    )
+    .await;
+    assert_eq!(report.status, ScriptStatus::Mismatch);
+    assert_eq!(report.step, 1);
+    assert_eq!(report.data, [0x00]);
+}
+
+#[tokio::test]
+async fn loops() {
+    let jig = Jig::with_target(ADDR);
+    let regs = [1, 2, 3];
+    jig.bus.poke(ADDR, 0, &regs);
+    let report = run(
+        &jig,
+        &[
+            ScriptOp::Write {
+                addr: ADDR,
+                data: &[0],
+            },
+            ScriptOp::Read { addr: ADDR, len: 1 },
+            ScriptOp::Loop { step: 1, times: 3 },
+        ],
+    )
+    .await;
+    assert_eq!(report.status, ScriptStatus::Completed);
+    assert_eq!(report.data, regs);
+}
+
+#[tokio::test]
+async fn poll_timeout() {
+    let jig = Jig::with_target(ADDR);
+    let report = run(
+        &jig,
+        &[ScriptOp::PollBitsSet {
+            addr: ADDR,
+            reg: 0x04,
+            mask: 0x80,
+            interval_us: 1000,
+            attempts: 3,
+        }],
+    )
+    .await;
+    assert_eq!(report.status, ScriptStatus::PollTimeout);
+    let delays = jig
+        .bus
+        .take_log()
+        .into_iter()
+        .filter(|ev| matches!(ev, Event::Delay { us: 1000 }))
+        .count();
+    assert_eq!(delays, 2);
+}
+
+#[tokio::test]
+async fn bus_error_stops_the_script() {
+    let jig = Jig::with_target(ADDR);
+    let report = run(
+        &jig,
+        &[
+            ScriptOp::Read { addr: ADDR, len: 2 },
+            ScriptOp::Read { addr: 0x20, len: 1 },
+            ScriptOp::Read { addr: ADDR, len: 1 },
+        ],
+    )
+    .await;
+    assert_eq!(report.status, ScriptStatus::I2c(I2cError::Bus));
+    assert_eq!(report.step, 1);
+    assert_eq!(report.data.len(), 2);
+}
+
+#[tokio::test]
+async fn invalid_program() {
+    let jig = Jig::with_target(ADDR);
+    let report = run(&jig, &[ScriptOp::Loop { step: 1, times: 2 }]).await;
+    assert_eq!(report.status, ScriptStatus::InvalidProgram);
+    assert!(jig.bus.take_log().is_empty());
+}
+
+#[tokio::test]
+async fn loop_of_no_times_is_invalid() {
+    let jig = Jig::with_target(ADDR);
+    let report = run(
+        &jig,
+        &[
+            ScriptOp::Read { addr: ADDR, len: 1 },
+            ScriptOp::Loop { step: 0, times: 0 },
+        ],
+    )
+    .await;
+    assert_eq!(report.status, ScriptStatus::InvalidProgram);
+    assert_eq!(report.step, 1);
+    assert!(report.data.is_empty());
+    assert!(jig.bus.take_log().is_empty());
+}
+
+#[tokio::test]
+async fn other_requests_are_answered_while_a_script_runs() {
+    let jig = Jig::with_target(ADDR);
+    let clock = jig.bus.stretch();
+    let script = run(&jig, &[ScriptOp::Read { addr: ADDR, len: 1 }]);
+    tokio::pin!(script);
+
+    // The script is stuck on the stretched clock, the dispatcher isn't
+    let id = tokio::select! {
+        _ = &mut script => panic!("the script ran with the clock stretched"),
+        id = jig.client.send_resp::<GetUniqueIdEndpoint>(&()) => id.unwrap(),
+    };
+    assert_eq!(id, UNIQUE_ID);
+
+    drop(clock);
+    let report = script.await;
+    assert_eq!(report.status, ScriptStatus::Completed);
+    assert_eq!(report.data.len(), 1);
+}
@@ -0,0 +1,180 @@
+//! SMBus protocols, with and without PEC
+
+use picocalc_jig_harness::{
+    mock::{Event, MockOp},
+    Jig, UNIQUE_ID,
+};
+use picocalc_jig_icd::*;
+use picocalc_jig_logic::smbus::pec;
+
+const ADDR: u8 = 0x0B;
+
+async fn run(jig: &Jig, op: SmbusOp, pec: bool) -> SmbusResult {
+    let cmd = SmbusCommand {
+        addr: ADDR,
+        op,
+        pec,
+        timeout_ms: None,
+    };
+    jig.client.send_resp::<SmbusEndpoint>(&cmd).await.unwrap()
+}
+
+#[tokio::test]
+async fn quick() {
+    let jig = Jig::with_target(ADDR);
+    let res = run(&jig, SmbusOp::Quick { read: true }, true).await;
+    assert!(matches!(res, Ok(SmbusResponse::Done)));
+    assert_eq!(
+        jig.bus.take_log(),
+        [Event::Quick {
+            addr: ADDR,
+            read: true,
+            result: Ok(()),
+        }]
+    );
+
+    jig.bus.remove_target(ADDR);
+    let res = run(&jig, SmbusOp::Quick { read: false }, false).await;
+    assert_eq!(res.unwrap_err(), SmbusError::I2c(I2cError::Bus));
+}
+
+#[tokio::test]
+async fn bytes_and_words() {
+    let jig = Jig::with_target(ADDR);
+    let res = run(
+        &jig,
+        SmbusOp::WriteByte {
+            cmd: 0x01,
+            value: 0x5A,
+        },
+        false,
+    )
+    .await;
+    assert!(matches!(res, Ok(SmbusResponse::Done)));
+    assert_eq!(jig.bus.peek(ADDR, 0x01, 1), [0x5A]);
+
+    let res = run(&jig, SmbusOp::ReadByte { cmd: 0x01 }, false).await;
+    assert!(matches!(res, Ok(SmbusResponse::Byte(0x5A))));
+
+    let res = run(
+        &jig,
+        SmbusOp::WriteWord {
+            cmd: 0x02,
+            value: 0x1234,
+        },
+        false,
+    )
+    .await;
+    assert!(matches!(res, Ok(SmbusResponse::Done)));
+    assert_eq!(jig.bus.peek(ADDR, 0x02, 2), [0x34, 0x12]);
+
+    let res = run(&jig, SmbusOp::ReadWord { cmd: 0x02 }, false).await;
+    assert!(matches!(res, Ok(SmbusResponse::Word(0x1234))));
+}
+
+#[tokio::test]
+async fn blocks() {
+    let jig = Jig::with_target(ADDR);
+    let res = run(
+        &jig,
+        SmbusOp::BlockWrite {
+            cmd: 0x10,
+            data: vec![1, 2, 3],
+        },
+        false,
+    )
+    .await;
+    assert!(matches!(res, Ok(SmbusResponse::Done)));
+    assert_eq!(jig.bus.peek(ADDR, 0x10, 4), [3, 1, 2, 3]);
+
+    match run(&jig, SmbusOp::BlockRead { cmd: 0x10 }, false).await {
+        Ok(SmbusResponse::Block(data)) => assert_eq!(data, [1, 2, 3]),
+        res => panic!("unexpected {res:?}"),
+    }
+
+    let res = run(
+        &jig,
+        SmbusOp::BlockWrite {
+            cmd: 0x10,
+            data: vec![0; SMBUS_BLOCK_MAX + 1],
+        },
+        false,
+    )
+    .await;
+    assert_eq!(res.unwrap_err(), SmbusError::BlockTooLong);
+
+    jig.bus.poke(ADDR, 0x20, &[SMBUS_BLOCK_MAX as u8 + 1]);
+    let res = run(&jig, SmbusOp::BlockRead { cmd: 0x20 }, false).await;
+    assert_eq!(res.unwrap_err(), SmbusError::BlockTooLong);
+}
+
+#[tokio::test]
+async fn pec_on_writes() {
+    let jig = Jig::with_target(ADDR);
+    let res = run(
+        &jig,
+        SmbusOp::WriteByte {
+            cmd: 0x01,
+            value: 0x5A,
+        },
+        true,
+    )
+    .await;
+    assert!(matches!(res, Ok(SmbusResponse::Done)));
+    let crc = pec(ADDR, &[0x01, 0x5A], &[]);
+    assert_eq!(
+        jig.bus.take_log(),
+        [Event::Transaction {
+            addr: ADDR,
+            ops: vec![MockOp::Write(vec![0x01, 0x5A, crc])],
+            result: Ok(()),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn pec_on_reads() {
+    let jig = Jig::with_target(ADDR);
+    let crc = pec(ADDR, &[0x01], &[0x5A]);
+    jig.bus.poke(ADDR, 0x01, &[0x5A, crc]);
+    let res = run(&jig, SmbusOp::ReadByte { cmd: 0x01 }, true).await;
+    assert!(matches!(res, Ok(SmbusResponse::Byte(0x5A))));
+
+    jig.bus.poke(ADDR, 0x01, &[0x5A, !crc]);
+    let res = run(&jig, SmbusOp::ReadByte { cmd: 0x01 }, true).await;
+    assert_eq!(res.unwrap_err(), SmbusError::Pec);
+}
+
+#[tokio::test]
+async fn process_call() {
+    let jig = Jig::with_target(ADDR);
+    // The target answers from the registers after the word written
+    jig.bus.poke(ADDR, 0x07, &[0xCD, 0xAB]);
+    let res = run(
+        &jig,
+        SmbusOp::ProcessCall {
+            cmd: 0x05,
+            value: 0x1111,
+        },
+        false,
+    )
+    .await;
+    assert!(matches!(res, Ok(SmbusResponse::Word(0xABCD))));
+}
+
+#[tokio::test]
+async fn other_requests_are_answered_during_a_transfer() {
+    let jig = Jig::with_target(ADDR);
+    let clock = jig.bus.stretch();
+    let read = run(&jig, SmbusOp::ReadByte { cmd: 0x01 }, false);
+    tokio::pin!(read);
+
+    let id = tokio::select! {
+        _ = &mut read => panic!("the transfer ran with the clock stretched"),
+        id = jig.client.send_resp::<GetUniqueIdEndpoint>(&()) => id.unwrap(),
+    };
+    assert_eq!(id, UNIQUE_ID);
+
+    drop(clock);
+    assert!(matches!(read.await, Ok(SmbusResponse::Byte(_))));
+}
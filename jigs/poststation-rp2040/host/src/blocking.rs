@@ -0,0 +1,90 @@
+//! The southbridge bus as a blocking `embedded-hal` I2C port
+//!
+//! [`BlockingI2cDev`] drives an [`I2cDev`] to completion on a tokio runtime
+//! for each call, so drivers written against the blocking
+//! [`embedded_hal::i2c::I2c`] trait can use the jig too. The calls must not be
+//! made from inside that runtime: use [`BlockingI2cDev::new`] from a plain
+//! `main`, or [`BlockingI2cDev::with_handle`] from a thread spawned with
+//! [`tokio::task::spawn_blocking`].
+//!
+//! ```no_run
+//! use embedded_hal::i2c::I2c;
+//! use picocalc_jig_host::{blocking::BlockingI2cDev, transport::DirectUsb, I2cDev, Jig};
+//!
+//! # fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let runtime = tokio::runtime::Runtime::new()?;
+//! // The USB client's tasks are spawned on the runtime it's opened in
+//! let usb = runtime.block_on(async { DirectUsb::open(None) })?;
+//! let mut i2c = BlockingI2cDev::new(runtime, I2cDev::new(Jig::new(usb)));
+//!
+//! let mut version = [0u8; 2];
+//! i2c.write_read(0x1F, &[0x01], &mut version)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use embedded_hal::i2c::{ErrorType, I2c, Operation};
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+use tokio::runtime::{Handle, Runtime};
+
+use crate::{error::Error, i2c::I2cDev, transport::Transport};
+
+enum Rt {
+    Owned(Runtime),
+    Shared(Handle),
+}
+
+/// A blocking wrapper around an [`I2cDev`]
+pub struct BlockingI2cDev<T> {
+    dev: I2cDev<T>,
+    rt: Rt,
+}
+
+impl<T: Transport> BlockingI2cDev<T> {
+    /// Wrap `dev`, owning the runtime its transport was created in
+    pub fn new(runtime: Runtime, dev: I2cDev<T>) -> Self {
+        Self {
+            dev,
+            rt: Rt::Owned(runtime),
+        }
+    }
+
+    /// Wrap `dev`, running it on a runtime owned elsewhere
+    ///
+    /// The runtime must keep running (e.g. be multi-threaded) while calls are
+    /// blocked, as the transport's tasks are spawned on it.
+    pub fn with_handle(handle: Handle, dev: I2cDev<T>) -> Self {
+        Self {
+            dev,
+            rt: Rt::Shared(handle),
+        }
+    }
+
+    /// The async port, e.g. to reach the rest of the jig's endpoints
+    pub fn inner(&self) -> &I2cDev<T> {
+        &self.dev
+    }
+
+    pub fn into_inner(self) -> I2cDev<T> {
+        self.dev
+    }
+}
+
+impl<T: Transport> ErrorType for BlockingI2cDev<T> {
+    type Error = Error;
+}
+
+impl<T: Transport> I2c for BlockingI2cDev<T> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let Self { dev, rt } = self;
+        let fut = dev.transaction(address, operations);
+        match rt {
+            Rt::Owned(rt) => rt.block_on(fut),
+            Rt::Shared(handle) => handle.block_on(fut),
+        }
+    }
+}
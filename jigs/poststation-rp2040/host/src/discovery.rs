@@ -0,0 +1,296 @@
+//! Finding the jigs that are plugged in, and picking one
+//!
+//! Jigs are told apart from other poststation devices by their USB product
+//! string, [`USB_PRODUCT`]. [`select`] picks one by serial number or
+//! poststation alias, falling back to the [`JIG_ENV`] environment variable,
+//! then to the only jig connected. With several connected and a terminal to
+//! ask on, the user is prompted.
+//!
+//! ```no_run
+//! use picocalc_jig_host::{discovery, transport::Poststation, Jig};
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = poststation_sdk::connect("127.0.0.1:51837").await?;
+//! let jigs = discovery::poststation_jigs(&client).await?;
+//! let serial = discovery::select(&jigs, Some("quirky-toe-123"))?;
+//! let jig = Jig::new(Poststation::new(client, serial));
+//!
+//! // Or in one go, taking the jig from `PICOCALC_JIG` or the only one there
+//! let jig = Jig::new(discovery::poststation("127.0.0.1:51837", None).await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+use poststation_sdk::{connect, ClientError, PoststationClient};
+
+use crate::transport::{DirectUsb, Poststation, USB_PRODUCT};
+
+/// Environment variable naming the jig to use, when none is given
+pub const JIG_ENV: &str = "PICOCALC_JIG";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JigInfo {
+    pub serial: u64,
+    /// Alias given by poststation, e.g. `QUIRKY-TOE-123`
+    pub name: Option<String>,
+    pub connected: bool,
+}
+
+impl fmt::Display for JigInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016X}", self.serial)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({name})")?;
+        }
+        if !self.connected {
+            write!(f, " [disconnected]")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    /// The poststation server couldn't be reached
+    Server(String),
+    /// The USB devices couldn't be listed or opened
+    Usb(String),
+    /// No jig is connected
+    NoJigs,
+    /// No jig has this serial number or alias
+    NotFound(String),
+    /// The jig asked for is known to poststation, but not connected
+    NotConnected(JigInfo),
+    /// Several jigs are connected, and there was nobody to ask which to use
+    Ambiguous(Vec<JigInfo>),
+    /// Reading the user's choice failed
+    Prompt(io::Error),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::Server(e) => write!(f, "poststation: {e}"),
+            DiscoveryError::Usb(e) => write!(f, "usb: {e}"),
+            DiscoveryError::NoJigs => write!(f, "no jig connected"),
+            DiscoveryError::NotFound(s) => write!(f, "no jig matches '{s}'"),
+            DiscoveryError::NotConnected(jig) => write!(f, "jig {jig} is not connected"),
+            DiscoveryError::Ambiguous(jigs) => {
+                write!(
+                    f,
+                    "several jigs connected, pick one with {JIG_ENV} or its serial:"
+                )?;
+                jigs.iter().try_for_each(|jig| write!(f, "\n  {jig}"))
+            }
+            DiscoveryError::Prompt(e) => write!(f, "reading choice: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+/// Every jig poststation knows about, connected or not
+pub async fn poststation_jigs(client: &PoststationClient) -> Result<Vec<JigInfo>, ClientError> {
+    let devices = client.get_devices().await?;
+    Ok(devices
+        .into_iter()
+        .filter(|dev| dev.product.as_deref() == Some(USB_PRODUCT))
+        .map(|dev| JigInfo {
+            serial: dev.serial,
+            name: Some(dev.name),
+            connected: dev.is_connected,
+        })
+        .collect())
+}
+
+/// Every jig plugged into this machine
+pub fn usb_jigs() -> io::Result<Vec<JigInfo>> {
+    Ok(nusb::list_devices()?
+        .filter(|dev| dev.product_string() == Some(USB_PRODUCT))
+        .filter_map(|dev| u64::from_str_radix(dev.serial_number()?, 16).ok())
+        .map(|serial| JigInfo {
+            serial,
+            name: None,
+            connected: true,
+        })
+        .collect())
+}
+
+/// Connect to the poststation server at `addr`, and [`select`] a jig on it
+pub async fn poststation(addr: &str, wanted: Option<&str>) -> Result<Poststation, DiscoveryError> {
+    let client = connect(addr)
+        .await
+        .map_err(|e| DiscoveryError::Server(e.to_string()))?;
+    let jigs = poststation_jigs(&client)
+        .await
+        .map_err(|e| DiscoveryError::Server(e.to_string()))?;
+    let serial = select(&jigs, wanted)?;
+    Ok(Poststation::new(client, serial))
+}
+
+/// [`select`] a jig plugged into this machine, and open it
+pub fn usb(wanted: Option<&str>) -> Result<DirectUsb, DiscoveryError> {
+    let jigs = usb_jigs().map_err(|e| DiscoveryError::Usb(e.to_string()))?;
+    let serial = select(&jigs, wanted)?;
+    DirectUsb::open(Some(serial)).map_err(DiscoveryError::Usb)
+}
+
+/// Whether `jig` is the one `wanted` names, by hex serial number (with or
+/// without `0x`) or alias, ignoring case
+pub fn matches(jig: &JigInfo, wanted: &str) -> bool {
+    let hex = wanted.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(hex, 16).is_ok_and(|s| s == jig.serial)
+        || jig
+            .name
+            .as_deref()
+            .is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+}
+
+/// Pick the serial number of the jig to use out of `jigs`
+///
+/// `wanted` is a serial number or alias, see [`matches`]. Without it,
+/// [`JIG_ENV`] is used if set, then the only connected jig. If several are
+/// connected and stdin is a terminal, the user is asked to choose.
+pub fn select(jigs: &[JigInfo], wanted: Option<&str>) -> Result<u64, DiscoveryError> {
+    let env = std::env::var(JIG_ENV).ok();
+    choose(jigs, wanted.or(env.as_deref()), io::stdin().is_terminal())
+}
+
+/// [`select`], once the environment has been looked at
+fn choose(jigs: &[JigInfo], wanted: Option<&str>, ask: bool) -> Result<u64, DiscoveryError> {
+    if let Some(wanted) = wanted {
+        let jig = jigs
+            .iter()
+            .find(|jig| matches(jig, wanted))
+            .ok_or_else(|| DiscoveryError::NotFound(wanted.into()))?;
+        return match jig.connected {
+            true => Ok(jig.serial),
+            false => Err(DiscoveryError::NotConnected(jig.clone())),
+        };
+    }
+
+    let connected: Vec<_> = jigs.iter().filter(|jig| jig.connected).cloned().collect();
+    match connected.as_slice() {
+        [] => Err(DiscoveryError::NoJigs),
+        [jig] => Ok(jig.serial),
+        _ if ask => prompt(&connected),
+        _ => Err(DiscoveryError::Ambiguous(connected)),
+    }
+}
+
+/// Ask which of `jigs` to use, by number, serial number or alias
+fn prompt(jigs: &[JigInfo]) -> Result<u64, DiscoveryError> {
+    let mut stderr = io::stderr();
+    for (i, jig) in jigs.iter().enumerate() {
+        let _ = writeln!(stderr, "{:>3}: {jig}", i + 1);
+    }
+    let mut line = String::new();
+    loop {
+        let _ = write!(stderr, "Which jig? ");
+        let _ = stderr.flush();
+        line.clear();
+        if io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(DiscoveryError::Prompt)?
+            == 0
+        {
+            return Err(DiscoveryError::Ambiguous(jigs.to_vec()));
+        }
+        let answer = line.trim();
+        let by_index = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| jigs.get(i.checked_sub(1)?));
+        if let Some(jig) = by_index.or_else(|| jigs.iter().find(|jig| matches(jig, answer))) {
+            return Ok(jig.serial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jig(serial: u64, name: &str, connected: bool) -> JigInfo {
+        JigInfo {
+            serial,
+            name: Some(name.into()),
+            connected,
+        }
+    }
+
+    fn jigs() -> Vec<JigInfo> {
+        vec![
+            jig(0xE66430A64B335337, "QUIRKY-TOE-123", true),
+            jig(0x0123456789ABCDEF, "SLEEPY-EAR-456", false),
+            jig(0xFEDCBA9876543210, "HAPPY-LEG-789", true),
+        ]
+    }
+
+    #[test]
+    fn matches_serials_with_or_without_0x() {
+        let jig = &jigs()[0];
+        assert!(matches(jig, "E66430A64B335337"));
+        assert!(matches(jig, "e66430a64b335337"));
+        assert!(matches(jig, "0xE66430A64B335337"));
+        assert!(matches(jig, "0Xe66430a64b335337"));
+        assert!(!matches(jig, "0x0123456789ABCDEF"));
+        assert!(!matches(jig, "0x"));
+    }
+
+    #[test]
+    fn matches_aliases_ignoring_case() {
+        let jig = &jigs()[0];
+        assert!(matches(jig, "QUIRKY-TOE-123"));
+        assert!(matches(jig, "quirky-toe-123"));
+        assert!(!matches(jig, "quirky-toe"));
+        assert!(!matches(
+            &JigInfo {
+                name: None,
+                ..jig.clone()
+            },
+            "quirky-toe-123"
+        ));
+    }
+
+    #[test]
+    fn picks_the_jig_asked_for() {
+        let jigs = jigs();
+        let serial = choose(&jigs, Some("happy-leg-789"), false).unwrap();
+        assert_eq!(serial, 0xFEDCBA9876543210);
+        let serial = choose(&jigs, Some("0xe66430a64b335337"), false).unwrap();
+        assert_eq!(serial, 0xE66430A64B335337);
+
+        let res = choose(&jigs, Some("grumpy-nose-000"), false);
+        assert!(matches!(res, Err(DiscoveryError::NotFound(s)) if s == "grumpy-nose-000"));
+    }
+
+    #[test]
+    fn disconnected_jigs_are_not_picked() {
+        let jigs = jigs();
+        let res = choose(&jigs, Some("sleepy-ear-456"), false);
+        assert!(matches!(res, Err(DiscoveryError::NotConnected(jig)) if jig == jigs[1]));
+
+        // The only connected jig is used, whatever else poststation knows of
+        let serial = choose(&jigs[..2], None, false).unwrap();
+        assert_eq!(serial, 0xE66430A64B335337);
+        let res = choose(&jigs[1..2], None, false);
+        assert!(matches!(res, Err(DiscoveryError::NoJigs)));
+    }
+
+    #[test]
+    fn several_connected_jigs_are_ambiguous() {
+        let jigs = jigs();
+        let res = choose(&jigs, None, false);
+        let Err(DiscoveryError::Ambiguous(connected)) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(connected, [jigs[0].clone(), jigs[2].clone()]);
+    }
+}
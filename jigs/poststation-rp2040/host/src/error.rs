@@ -0,0 +1,125 @@
+//! Everything a request to the jig can fail with
+
+use std::fmt;
+
+use embedded_hal_async::i2c::{ErrorKind, NoAcknowledgeSource};
+use picocalc_jig_icd::{
+    AdcError, CaptureError, GpioError, I2cError, PollError, SmbusError, SpiError,
+};
+
+use crate::transport::TransportError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request didn't reach the jig, or its response didn't come back
+    Transport(TransportError),
+    /// The jig ran the request, and reported an error
+    I2c(I2cError),
+    Smbus(SmbusError),
+    Poll(PollError),
+    Gpio(GpioError),
+    Adc(AdcError),
+    Capture(CaptureError),
+    Spi(SpiError),
+    /// The request can't be made by the jig, e.g. an I2C transaction with
+    /// more operations than it supports
+    Unsupported,
+    /// The jig answered with the wrong kind of data
+    BadResponse,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "{e}"),
+            Error::I2c(e) => write!(f, "jig: I2C {e:?}"),
+            Error::Smbus(e) => write!(f, "jig: SMBus {e:?}"),
+            Error::Poll(e) => write!(f, "jig: polling {e:?}"),
+            Error::Gpio(e) => write!(f, "jig: GPIO {e:?}"),
+            Error::Adc(e) => write!(f, "jig: ADC {e:?}"),
+            Error::Capture(e) => write!(f, "jig: capture {e:?}"),
+            Error::Spi(e) => write!(f, "jig: SPI {e:?}"),
+            Error::Unsupported => write!(f, "not supported by the jig"),
+            Error::BadResponse => write!(f, "jig: unexpected response"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl embedded_hal_async::i2c::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            // The jig can't tell a NACK from other bus errors
+            Error::I2c(I2cError::Bus) => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_hal::spi::Error for Error {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        embedded_hal::spi::ErrorKind::Other
+    }
+}
+
+impl embedded_hal::digital::Error for Error {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+impl From<TransportError> for Error {
+    fn from(value: TransportError) -> Self {
+        Error::Transport(value)
+    }
+}
+
+impl From<I2cError> for Error {
+    fn from(value: I2cError) -> Self {
+        Error::I2c(value)
+    }
+}
+
+impl From<SmbusError> for Error {
+    fn from(value: SmbusError) -> Self {
+        Error::Smbus(value)
+    }
+}
+
+impl From<PollError> for Error {
+    fn from(value: PollError) -> Self {
+        Error::Poll(value)
+    }
+}
+
+impl From<GpioError> for Error {
+    fn from(value: GpioError) -> Self {
+        Error::Gpio(value)
+    }
+}
+
+impl From<AdcError> for Error {
+    fn from(value: AdcError) -> Self {
+        Error::Adc(value)
+    }
+}
+
+impl From<CaptureError> for Error {
+    fn from(value: CaptureError) -> Self {
+        Error::Capture(value)
+    }
+}
+
+impl From<SpiError> for Error {
+    fn from(value: SpiError) -> Self {
+        Error::Spi(value)
+    }
+}
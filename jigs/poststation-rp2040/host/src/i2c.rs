@@ -0,0 +1,80 @@
+//! The southbridge bus, as a local `embedded-hal-async` I2C port
+
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation};
+use picocalc_jig_icd::I2C_CHUNK_LEN;
+
+use crate::{error::Error, jig::Jig, transport::Transport};
+
+/// The jig's southbridge bus, usable by any `embedded-hal-async` driver
+///
+/// Transfers longer than [`I2C_CHUNK_LEN`] go through the jig's bulk buffer.
+/// Transactions are limited to a read, a write, or a write then a read, others
+/// fail with [`Error::Unsupported`].
+pub struct I2cDev<T> {
+    jig: Jig<T>,
+}
+
+impl<T: Transport> I2cDev<T> {
+    pub fn new(jig: Jig<T>) -> Self {
+        Self { jig }
+    }
+
+    /// The rest of the jig's endpoints
+    pub fn jig(&self) -> &Jig<T> {
+        &self.jig
+    }
+
+    pub fn jig_mut(&mut self) -> &mut Jig<T> {
+        &mut self.jig
+    }
+
+    pub fn into_inner(self) -> Jig<T> {
+        self.jig
+    }
+}
+
+impl<T: Transport> ErrorType for I2cDev<T> {
+    type Error = Error;
+}
+
+impl<T: Transport> I2c for I2cDev<T> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        match operations {
+            [] => Ok(()),
+            [Operation::Read(buf)] => self.read(address, buf).await,
+            [Operation::Write(buf)] => self.write(address, buf).await,
+            [Operation::Write(tx), Operation::Read(rx)] => self.write_read(address, tx, rx).await,
+            _ => Err(Error::Unsupported),
+        }
+    }
+
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        match read.len() > I2C_CHUNK_LEN {
+            true => self.jig.i2c_bulk(address, &[], read).await,
+            false => self.jig.i2c_read(address, read).await,
+        }
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        match write.len() > I2C_CHUNK_LEN {
+            true => self.jig.i2c_bulk(address, write, &mut []).await,
+            false => self.jig.i2c_write(address, write).await,
+        }
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        match write.len() > I2C_CHUNK_LEN || read.len() > I2C_CHUNK_LEN {
+            true => self.jig.i2c_bulk(address, write, read).await,
+            false => self.jig.i2c_write_read(address, write, read).await,
+        }
+    }
+}
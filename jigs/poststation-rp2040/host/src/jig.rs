@@ -0,0 +1,494 @@
+//! A connection to the jig, with a method for each of its endpoints
+
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use picocalc_jig_icd::*;
+use postcard_rpc::{Endpoint, Topic};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::Error,
+    transport::{Subscription, Transport},
+};
+
+/// The jig, reached over any [`Transport`]
+///
+/// Methods return [`Error::Transport`] when the request or its response was
+/// lost, and the matching [`Error`] variant when the jig reported an error.
+pub struct Jig<T> {
+    transport: T,
+    ctr: AtomicU32,
+    timeout_ms: Option<u32>,
+}
+
+fn millis(d: Duration) -> u32 {
+    d.as_millis().try_into().unwrap_or(u32::MAX)
+}
+
+/// Copy what the jig read into `out`, which it should have filled exactly
+fn copy_read(out: &mut [u8], data: &[u8]) -> Result<(), Error> {
+    if out.len() != data.len() {
+        return Err(Error::BadResponse);
+    }
+    out.copy_from_slice(data);
+    Ok(())
+}
+
+impl<T: Transport> Jig<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            ctr: AtomicU32::new(0),
+            timeout_ms: None,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Timeout for each I2C transfer made from now on, or `None` to use the
+    /// jig's default ([`DEFAULT_I2C_TIMEOUT_MS`])
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout_ms = timeout.map(millis);
+    }
+
+    /// Send a request to any endpoint, for those without a method here
+    pub async fn request<E>(&self, req: &E::Request) -> Result<E::Response, Error>
+    where
+        E: Endpoint,
+        E::Request: Serialize,
+        E::Response: DeserializeOwned,
+    {
+        Ok(self.transport.request::<E>(self.ctr(), req).await?)
+    }
+
+    /// Publish on any of the jig's incoming topics
+    pub async fn publish<P>(&self, msg: &P::Message) -> Result<(), Error>
+    where
+        P: Topic,
+        P::Message: Serialize,
+    {
+        Ok(self.transport.publish::<P>(self.ctr(), msg).await?)
+    }
+
+    /// Subscribe to any of the jig's outgoing topics
+    pub async fn subscribe<P>(&self) -> Result<Subscription<P>, Error>
+    where
+        P: Topic,
+        P::Message: DeserializeOwned,
+    {
+        Ok(self.transport.subscribe::<P>().await?)
+    }
+
+    // DEVICE
+
+    pub async fn unique_id(&self) -> Result<u64, Error> {
+        self.request::<GetUniqueIdEndpoint>(&()).await
+    }
+
+    /// Reset the jig into the RP2040's USB bootloader
+    ///
+    /// The jig usually resets before it can reply, so this tends to fail with
+    /// [`Error::Transport`] even when it worked.
+    pub async fn reboot_to_picoboot(&self) -> Result<(), Error> {
+        self.request::<RebootToPicoBoot>(&()).await
+    }
+
+    /// Have the jig wait, returning how long it actually waited
+    pub async fn sleep(&self, millis: u16) -> Result<u16, Error> {
+        let slept = self
+            .request::<SleepEndpoint>(&SleepMillis { millis })
+            .await?;
+        Ok(slept.millis)
+    }
+
+    pub async fn set_led(&self, on: bool) -> Result<(), Error> {
+        let state = match on {
+            true => LedState::On,
+            false => LedState::Off,
+        };
+        self.request::<SetLedEndpoint>(&state).await
+    }
+
+    pub async fn led(&self) -> Result<bool, Error> {
+        let state = self.request::<GetLedEndpoint>(&()).await?;
+        Ok(matches!(state, LedState::On))
+    }
+
+    // I2C
+
+    /// Read into `read`, which must be at most [`I2C_CHUNK_LEN`] bytes
+    pub async fn i2c_read(&self, addr: u8, read: &mut [u8]) -> Result<(), Error> {
+        let cmd = ReadCommand {
+            addr,
+            len: read.len() as u32,
+            timeout_ms: self.timeout_ms,
+        };
+        let res = self.request::<I2cReadEndpoint>(&cmd).await??;
+        copy_read(read, &res.data)
+    }
+
+    /// Write `write`, which must be at most [`I2C_CHUNK_LEN`] bytes
+    pub async fn i2c_write(&self, addr: u8, write: &[u8]) -> Result<(), Error> {
+        let cmd = WriteCommand {
+            addr,
+            data: write.to_vec(),
+            timeout_ms: self.timeout_ms,
+        };
+        Ok(self.request::<I2cWriteEndpoint>(&cmd).await??)
+    }
+
+    /// Write then read, joined by a repeated START. Both must be at most
+    /// [`I2C_CHUNK_LEN`] bytes
+    pub async fn i2c_write_read(
+        &self,
+        addr: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Error> {
+        let cmd = WriteReadCommand {
+            addr,
+            tx_data: write.to_vec(),
+            rx_len: read.len() as u32,
+            timeout_ms: self.timeout_ms,
+        };
+        let res = self.request::<I2cWriteReadEndpoint>(&cmd).await??;
+        copy_read(read, &res.data)
+    }
+
+    /// Write, wait `delay` on the jig, then read
+    ///
+    /// Unlike separate writes and reads, the delay doesn't depend on USB and
+    /// scheduling latency on the host.
+    pub async fn write_delay_read(
+        &self,
+        addr: u8,
+        write: &[u8],
+        delay: Duration,
+        read: &mut [u8],
+    ) -> Result<(), Error> {
+        let cmd = WriteDelayReadCommand {
+            addr,
+            tx_data: write.to_vec(),
+            delay_us: delay.as_micros().try_into().unwrap_or(u32::MAX),
+            rx_len: read.len() as u32,
+            timeout_ms: self.timeout_ms,
+        };
+        let res = self.request::<I2cWriteDelayReadEndpoint>(&cmd).await??;
+        copy_read(read, &res.data)
+    }
+
+    /// Copy `data` into the jig's bulk buffer at `offset` for `session`, or a
+    /// new session, which is returned
+    pub async fn bulk_stage(
+        &self,
+        session: Option<BulkSession>,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<BulkSession, Error> {
+        let stage = BulkStage {
+            session,
+            offset: offset as u32,
+            data: data.to_vec(),
+        };
+        Ok(self.request::<I2cBulkStageEndpoint>(&stage).await??)
+    }
+
+    /// Run a transfer on the bus from the jig's bulk buffer, see [`BulkTransfer`]
+    pub async fn bulk_transfer(
+        &self,
+        session: BulkSession,
+        addr: u8,
+        tx_len: usize,
+        rx_len: usize,
+    ) -> Result<(), Error> {
+        let xfer = BulkTransfer {
+            session,
+            addr,
+            tx_len: tx_len as u32,
+            rx_len: rx_len as u32,
+            timeout_ms: self.timeout_ms,
+        };
+        Ok(self.request::<I2cBulkTransferEndpoint>(&xfer).await??)
+    }
+
+    /// Read back `out.len()` bytes of the jig's bulk buffer from `offset`
+    pub async fn bulk_fetch(
+        &self,
+        session: BulkSession,
+        offset: usize,
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        let fetch = BulkFetch {
+            session,
+            offset: offset as u32,
+            len: out.len() as u32,
+        };
+        let res = self.request::<I2cBulkFetchEndpoint>(&fetch).await??;
+        copy_read(out, &res.data)
+    }
+
+    /// Write then read transfers of up to [`I2C_BULK_LEN`] bytes in total,
+    /// through the jig's bulk buffer
+    ///
+    /// Fails with [`I2cError::Busy`] while another client is using the buffer.
+    pub async fn i2c_bulk(&self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), Error> {
+        if write.len() + read.len() > I2C_BULK_LEN {
+            return Err(Error::I2c(I2cError::TooLong));
+        }
+        // Staging the first chunk, or nothing, starts the session
+        let mut chunks = write.chunks(I2C_CHUNK_LEN);
+        let session = self
+            .bulk_stage(None, 0, chunks.next().unwrap_or_default())
+            .await?;
+        for (i, chunk) in chunks.enumerate() {
+            self.bulk_stage(Some(session), (i + 1) * I2C_CHUNK_LEN, chunk)
+                .await?;
+        }
+        self.bulk_transfer(session, addr, write.len(), read.len())
+            .await?;
+        // What was read is stored right after what was written
+        for (i, chunk) in read.chunks_mut(I2C_CHUNK_LEN).enumerate() {
+            self.bulk_fetch(session, write.len() + i * I2C_CHUNK_LEN, chunk)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn smbus(&self, addr: u8, op: SmbusOp, pec: bool) -> Result<SmbusResponse, Error> {
+        let cmd = SmbusCommand {
+            addr,
+            op,
+            pec,
+            timeout_ms: self.timeout_ms,
+        };
+        Ok(self.request::<SmbusEndpoint>(&cmd).await??)
+    }
+
+    /// Run `ops` on the jig in one request, with no other bus traffic in between
+    ///
+    /// A script that stops early is not an error, check the report's status.
+    pub async fn run_script(&self, ops: &[ScriptOp<'_>]) -> Result<ScriptReport, Error> {
+        let mut program = Vec::new();
+        for op in ops {
+            program = postcard::to_extend(op, program).expect("script ops always serialize");
+        }
+        let cmd = ScriptCommand {
+            program,
+            timeout_ms: self.timeout_ms,
+        };
+        self.request::<I2cScriptEndpoint>(&cmd).await
+    }
+
+    /// Turn publishing of bus transactions on [`I2cTraceTopic`] on or off
+    pub async fn set_trace(&self, enabled: bool) -> Result<(), Error> {
+        self.request::<I2cTraceEndpoint>(&enabled).await
+    }
+
+    /// How the jig's users of the southbridge bus have been getting on
+    pub async fn bus_stats(&self) -> Result<I2cBusStats, Error> {
+        self.request::<I2cBusStatsEndpoint>(&()).await
+    }
+
+    /// Ask the jig to un-stick the bus, e.g. after an [`I2cError::Bus`]
+    pub async fn recover_bus(&self) -> Result<I2cRecovery, Error> {
+        Ok(self.request::<I2cRecoverEndpoint>(&()).await??)
+    }
+
+    // TARGET MODE
+
+    /// Start or stop answering as the southbridge
+    pub async fn set_target_mode(&self, enabled: bool) -> Result<(), Error> {
+        self.request::<TargetModeEndpoint>(&enabled).await
+    }
+
+    pub async fn target_status(&self) -> Result<TargetStatus, Error> {
+        self.request::<TargetStatusEndpoint>(&()).await
+    }
+
+    /// Queue a key event for the firmware under test, in target mode
+    pub async fn send_target_key(&self, key: TargetKey) -> Result<(), Error> {
+        self.publish::<TargetKeyTopic>(&key).await
+    }
+
+    // POLLING
+
+    /// Register a polling job, returning its ID
+    pub async fn poll_register(&self, job: PollJob) -> Result<u32, Error> {
+        Ok(self.request::<PollRegisterEndpoint>(&job).await??)
+    }
+
+    pub async fn poll_list(&self) -> Result<Vec<PollJobInfo>, Error> {
+        let list = self.request::<PollListEndpoint>(&()).await?;
+        Ok(list.into_iter().flatten().collect())
+    }
+
+    pub async fn poll_cancel(&self, id: u32) -> Result<(), Error> {
+        Ok(self.request::<PollCancelEndpoint>(&id).await??)
+    }
+
+    // GPIO
+
+    pub async fn gpio_configure(&self, config: GpioConfig) -> Result<(), Error> {
+        Ok(self.request::<GpioConfigureEndpoint>(&config).await??)
+    }
+
+    pub async fn gpio_read(&self, pin: ExpansionPin) -> Result<GpioLevel, Error> {
+        Ok(self.request::<GpioReadEndpoint>(&pin).await??)
+    }
+
+    pub async fn gpio_write(&self, pin: ExpansionPin, level: GpioLevel) -> Result<(), Error> {
+        Ok(self
+            .request::<GpioWriteEndpoint>(&GpioWrite { pin, level })
+            .await??)
+    }
+
+    /// Start (or with `edge: None`, stop) publishing edges of `pin` on
+    /// [`GpioEdgeTopic`]
+    pub async fn gpio_subscribe(
+        &self,
+        pin: ExpansionPin,
+        edge: Option<GpioEdge>,
+    ) -> Result<(), Error> {
+        Ok(self
+            .request::<GpioSubscribeEndpoint>(&GpioSubscribe { pin, edge })
+            .await??)
+    }
+
+    // ADC
+
+    pub async fn adc_read(&self, channel: AdcChannel) -> Result<AdcSample, Error> {
+        Ok(self.request::<AdcReadEndpoint>(&channel).await??)
+    }
+
+    /// Start (or with `interval: None`, stop) publishing samples of `channel`
+    /// on [`AdcSampleTopic`]
+    pub async fn adc_stream(
+        &self,
+        channel: AdcChannel,
+        interval: Option<Duration>,
+    ) -> Result<(), Error> {
+        let stream = AdcStream {
+            channel,
+            interval_ms: interval.map(millis),
+        };
+        Ok(self.request::<AdcStreamEndpoint>(&stream).await??)
+    }
+
+    // LOGIC ANALYZER
+
+    pub async fn capture_start(&self, config: CaptureConfig) -> Result<(), Error> {
+        Ok(self.request::<CaptureStartEndpoint>(&config).await??)
+    }
+
+    pub async fn capture_cancel(&self) -> Result<(), Error> {
+        self.request::<CaptureCancelEndpoint>(&()).await
+    }
+
+    pub async fn capture_status(&self) -> Result<CaptureStatus, Error> {
+        self.request::<CaptureStatusEndpoint>(&()).await
+    }
+
+    /// Fetch `len` bytes of a completed capture from `offset`, at most
+    /// [`I2C_CHUNK_LEN`] at a time
+    pub async fn capture_fetch(&self, offset: u32, len: u32) -> Result<Vec<u8>, Error> {
+        let res = self
+            .request::<CaptureFetchEndpoint>(&CaptureFetch { offset, len })
+            .await??;
+        Ok(res.data)
+    }
+
+    // SPI
+
+    pub async fn spi_configure(
+        &self,
+        port: SpiPort,
+        frequency_hz: u32,
+        mode: SpiMode,
+    ) -> Result<(), Error> {
+        let cfg = SpiConfig {
+            port,
+            frequency_hz,
+            mode,
+        };
+        Ok(self.request::<SpiConfigureEndpoint>(&cfg).await??)
+    }
+
+    /// Clock out `write`, then zeros until `read` is full too. At most
+    /// [`SPI_CHUNK_LEN`] bytes are clocked
+    pub async fn spi_transfer(
+        &self,
+        port: SpiPort,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Error> {
+        let xfer = SpiTransfer {
+            port,
+            tx: write.to_vec(),
+            rx_len: read.len() as u32,
+        };
+        let res = self.request::<SpiTransferEndpoint>(&xfer).await??;
+        copy_read(read, &res.data)
+    }
+
+    pub async fn control_pin(&self, pin: ControlPin, level: GpioLevel) -> Result<(), Error> {
+        self.request::<ControlPinWriteEndpoint>(&ControlPinWrite { pin, level })
+            .await
+    }
+
+    // TOPICS
+
+    /// Edges of the pins enabled with [`Jig::gpio_subscribe`]
+    pub async fn gpio_edges(&self) -> Result<Subscription<GpioEdgeTopic>, Error> {
+        self.subscribe::<GpioEdgeTopic>().await
+    }
+
+    /// Samples of the channels enabled with [`Jig::adc_stream`]
+    pub async fn adc_samples(&self) -> Result<Subscription<AdcSampleTopic>, Error> {
+        self.subscribe::<AdcSampleTopic>().await
+    }
+
+    /// Results of the jobs registered with [`Jig::poll_register`]
+    pub async fn poll_results(&self) -> Result<Subscription<PollResultTopic>, Error> {
+        self.subscribe::<PollResultTopic>().await
+    }
+
+    /// Bus transactions, while enabled with [`Jig::set_trace`]
+    pub async fn trace_events(&self) -> Result<Subscription<I2cTraceTopic>, Error> {
+        self.subscribe::<I2cTraceTopic>().await
+    }
+
+    #[inline(always)]
+    fn ctr(&self) -> u32 {
+        self.ctr.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_of_the_wrong_length_are_bad_responses() {
+        let mut out = [0u8; 3];
+        assert!(copy_read(&mut out, &[1, 2, 3]).is_ok());
+        assert_eq!(out, [1, 2, 3]);
+        assert!(matches!(
+            copy_read(&mut out, &[1, 2]),
+            Err(Error::BadResponse)
+        ));
+        assert!(matches!(
+            copy_read(&mut out, &[1, 2, 3, 4]),
+            Err(Error::BadResponse)
+        ));
+        assert_eq!(out, [1, 2, 3]);
+    }
+}
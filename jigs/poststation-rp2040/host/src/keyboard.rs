@@ -0,0 +1,138 @@
+//! Decoding the southbridge's key FIFO (register `0x09`)
+
+use picocalc_jig_icd::KeyEventState;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum Key {
+    Char(char),
+    LeftDPad,
+    UpDPad,
+    DownDPad,
+    RightDPad,
+    Func1,
+    Func2,
+    Func3,
+    Func4,
+    Func5,
+    Func6,
+    Func7,
+    Func8,
+    Func9,
+    Func10,
+    Esc,
+    Tab,
+    CapsLk,
+    Del,
+    Back,
+    Brk,
+    Home,
+    End,
+    Enter,
+    Ins,
+    Other(u8),
+}
+
+#[derive(Debug, Serialize)]
+pub enum KeyEvent {
+    Press(Key),
+    Release(Key),
+    Hold(Key),
+    Other([u8; 2]),
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyReport {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub evt: KeyEvent,
+}
+
+#[derive(Default)]
+pub struct KeyState {
+    ctrl: bool,
+    lshift: bool,
+    rshift: bool,
+    alt: bool,
+}
+
+// Key codes of the modifier keys
+pub const KEY_ALT: u8 = 0xA1;
+pub const KEY_LSHIFT: u8 = 0xA2;
+pub const KEY_RSHIFT: u8 = 0xA3;
+pub const KEY_CTRL: u8 = 0xA5;
+
+pub const SPECIALS: &[(u8, Key)] = &[
+    (0xB4, Key::LeftDPad),
+    (0xB5, Key::UpDPad),
+    (0xB6, Key::DownDPad),
+    (0xB7, Key::RightDPad),
+    (0x81, Key::Func1),
+    (0x82, Key::Func2),
+    (0x83, Key::Func3),
+    (0x84, Key::Func4),
+    (0x85, Key::Func5),
+    (0x86, Key::Func6),
+    (0x87, Key::Func7),
+    (0x88, Key::Func8),
+    (0x89, Key::Func9),
+    (0x90, Key::Func10),
+    (0xB1, Key::Esc),
+    (0x09, Key::Tab),
+    (0xC1, Key::CapsLk),
+    (0xD4, Key::Del),
+    (0x08, Key::Back),
+    (0xD0, Key::Brk),
+    (0xD2, Key::Home),
+    (0xD5, Key::End),
+    (0x0A, Key::Enter),
+    (0xD1, Key::Ins),
+];
+
+impl KeyState {
+    fn report(&self, evt: KeyEvent) -> KeyReport {
+        KeyReport {
+            ctrl: self.ctrl,
+            shift: self.lshift | self.rshift,
+            alt: self.alt,
+            evt,
+        }
+    }
+
+    /// Track the modifiers, and decode anything else into a report
+    pub fn update(&mut self, data: [u8; 2]) -> Option<KeyReport> {
+        if data == [0, 0] {
+            return None;
+        }
+        let [state, code] = data;
+        let Ok(state) = KeyEventState::try_from(state) else {
+            return Some(self.report(KeyEvent::Other(data)));
+        };
+
+        let modifier = match code {
+            KEY_ALT => Some(&mut self.alt),
+            KEY_LSHIFT => Some(&mut self.lshift),
+            KEY_RSHIFT => Some(&mut self.rshift),
+            KEY_CTRL => Some(&mut self.ctrl),
+            _ => None,
+        };
+        match (state, modifier) {
+            (KeyEventState::Pressed, Some(held)) => *held = true,
+            (KeyEventState::Released, Some(held)) => *held = false,
+            _ => {
+                let key = match SPECIALS.iter().find(|(c, _)| *c == code) {
+                    Some((_, key)) => *key,
+                    None if code.is_ascii() && !code.is_ascii_control() => Key::Char(code.into()),
+                    None => Key::Other(code),
+                };
+                return Some(self.report(match state {
+                    KeyEventState::Pressed => KeyEvent::Press(key),
+                    KeyEventState::Hold => KeyEvent::Hold(key),
+                    KeyEventState::Released => KeyEvent::Release(key),
+                }));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,43 @@
+//! Host-side access to the PicoCalc jig
+//!
+//! [`Jig`] is a connection to the jig over a poststation server or directly
+//! over USB (see [`transport`]), with a method for each of its endpoints and
+//! topics. [`I2cDev`] wraps it as an `embedded-hal-async` I2C port, so drivers
+//! can be run against the southbridge bus from the host, and
+//! [`blocking::BlockingI2cDev`] does the same for blocking `embedded-hal`
+//! drivers. [`smbus`] speaks the SMBus protocols on the same bus, and the LCD
+//! and SD card buses are reached through [`spi`]. Which jig to talk to is
+//! worked out by [`discovery`], and [`reconnect`] keeps talking to it through
+//! disconnects and resets.
+//!
+//! ```no_run
+//! use embedded_hal_async::i2c::I2c;
+//! use picocalc_jig_host::{transport::DirectUsb, I2cDev, Jig};
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let jig = Jig::new(DirectUsb::open(None)?);
+//! println!("jig {:016X}", jig.unique_id().await?);
+//!
+//! let mut i2c = I2cDev::new(jig);
+//! let mut version = [0u8; 2];
+//! i2c.write_read(0x1F, &[0x01], &mut version).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod blocking;
+pub mod discovery;
+pub mod error;
+pub mod i2c;
+pub mod jig;
+pub mod keyboard;
+pub mod reconnect;
+pub mod session;
+pub mod sim;
+pub mod smbus;
+pub mod spi;
+pub mod transport;
+
+pub use error::Error;
+pub use i2c::I2cDev;
+pub use jig::Jig;
@@ -0,0 +1,331 @@
+//! Surviving cable bumps and resets of the jig
+//!
+//! [`Reconnecting`] is a [`Transport`] that opens a new connection to the jig
+//! with a [`Connect`] once the old one is lost, backing off between attempts
+//! as set by a [`RetryPolicy`]. A request cut off by the disconnect fails,
+//! unless it is safe to send twice (see [`idempotent`]), in which case it is
+//! sent again on the new connection. Each change is reported as a
+//! [`ConnectionEvent`].
+//!
+//! A reset loses whatever was set up on the jig, such as GPIO configuration
+//! and polling jobs, and subscriptions end with the connection they were made
+//! on. Both have to be made again after [`ConnectionEvent::Reconnected`].
+//!
+//! ```no_run
+//! use picocalc_jig_host::{
+//!     reconnect::{Reconnecting, RetryPolicy, UsbJig},
+//!     Jig,
+//! };
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let usb = UsbJig {
+//!     serial: 0xE66430A64B335337,
+//! };
+//! let jig = Jig::new(Reconnecting::open(usb, RetryPolicy::default()).await?);
+//! let mut events = jig.transport().events();
+//! tokio::spawn(async move {
+//!     while let Ok(event) = events.recv().await {
+//!         eprintln!("{event}");
+//!     }
+//! });
+//!
+//! // Sent again after a reset, rather than failing
+//! let id = jig.unique_id().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use picocalc_jig_icd::*;
+use postcard_rpc::{Endpoint, Topic};
+use poststation_sdk::connect;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::{
+    discovery,
+    transport::{DirectUsb, Poststation, Subscription, Transport, TransportError},
+};
+
+/// Events buffered per [`Reconnecting::events`] receiver before the oldest
+/// are dropped
+const EVENT_DEPTH: usize = 16;
+
+/// Opens connections to one jig
+#[allow(async_fn_in_trait)]
+pub trait Connect {
+    type Transport: Transport;
+
+    async fn connect(&self) -> Result<Self::Transport, String>;
+}
+
+/// The jig with this serial number, plugged into this machine
+#[derive(Debug, Clone)]
+pub struct UsbJig {
+    pub serial: u64,
+}
+
+impl Connect for UsbJig {
+    type Transport = DirectUsb;
+
+    async fn connect(&self) -> Result<DirectUsb, String> {
+        DirectUsb::open(Some(self.serial))
+    }
+}
+
+/// The jig with this serial number, on the poststation server at `addr`
+#[derive(Debug, Clone)]
+pub struct PoststationJig {
+    pub addr: String,
+    pub serial: u64,
+}
+
+impl Connect for PoststationJig {
+    type Transport = Poststation;
+
+    /// Fails until the server has the jig connected again
+    async fn connect(&self) -> Result<Poststation, String> {
+        let client = connect(self.addr.as_str())
+            .await
+            .map_err(|e| e.to_string())?;
+        let jigs = discovery::poststation_jigs(&client)
+            .await
+            .map_err(|e| e.to_string())?;
+        match jigs.iter().find(|jig| jig.serial == self.serial) {
+            Some(jig) if jig.connected => Ok(Poststation::new(client, self.serial)),
+            _ => Err(format!("jig {:016X} is not connected", self.serial)),
+        }
+    }
+}
+
+/// How hard [`Reconnecting`] tries
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Wait before the first attempt to reconnect, doubled after each failure
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts
+    pub max_backoff: Duration,
+    /// Attempts before giving up, or `None` to keep trying. At least one is
+    /// made
+    pub max_attempts: Option<u32>,
+    /// Send requests cut off by a disconnect again, if they are [`idempotent`]
+    pub retry_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    /// About 15 seconds of attempts, enough for the jig to reset and enumerate
+    /// again
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            max_attempts: Some(10),
+            retry_idempotent: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum ConnectionEvent {
+    /// The connection was lost, with the error that showed it
+    Lost(String),
+    /// An attempt to reconnect failed, counting from 1
+    Failed { attempt: u32, error: String },
+    /// A new connection is open
+    Reconnected,
+    /// The policy's attempts ran out. Requests fail with
+    /// [`TransportError::Reconnect`], and the next one starts over
+    GaveUp,
+}
+
+impl fmt::Display for ConnectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionEvent::Lost(e) => write!(f, "connection lost: {e}"),
+            ConnectionEvent::Failed { attempt, error } => {
+                write!(f, "reconnecting, attempt {attempt} failed: {error}")
+            }
+            ConnectionEvent::Reconnected => write!(f, "reconnected"),
+            ConnectionEvent::GaveUp => write!(f, "gave up reconnecting"),
+        }
+    }
+}
+
+/// Whether the endpoint at `path` leaves the jig the same whether it ran once
+/// or twice, so is safe to send again when its response was lost
+///
+/// Bus transfers aren't, as reading some registers (e.g. the key FIFO) changes
+/// them. Neither are the bulk buffer's, which a reset empties.
+pub fn idempotent(path: &str) -> bool {
+    [
+        GetUniqueIdEndpoint::PATH,
+        SleepEndpoint::PATH,
+        SetLedEndpoint::PATH,
+        GetLedEndpoint::PATH,
+        I2cTraceEndpoint::PATH,
+        I2cBusStatsEndpoint::PATH,
+        TargetModeEndpoint::PATH,
+        TargetStatusEndpoint::PATH,
+        PollListEndpoint::PATH,
+        GpioConfigureEndpoint::PATH,
+        GpioReadEndpoint::PATH,
+        GpioWriteEndpoint::PATH,
+        AdcReadEndpoint::PATH,
+        CaptureStatusEndpoint::PATH,
+        SpiConfigureEndpoint::PATH,
+        ControlPinWriteEndpoint::PATH,
+    ]
+    .contains(&path)
+}
+
+/// A connection to the jig that is opened again when lost
+///
+/// Reconnecting happens on the first request after the loss, which waits for
+/// it. Concurrent requests wait for the same attempts.
+pub struct Reconnecting<C: Connect> {
+    connect: C,
+    policy: RetryPolicy,
+    /// `None` once the connection is lost, until it is opened again
+    current: Mutex<Option<Arc<C::Transport>>>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl<C: Connect> Reconnecting<C> {
+    /// Use `transport`, until it is lost and `connect` opens another
+    pub fn new(connect: C, transport: C::Transport, policy: RetryPolicy) -> Self {
+        Self {
+            connect,
+            policy,
+            current: Mutex::new(Some(Arc::new(transport))),
+            events: broadcast::channel(EVENT_DEPTH).0,
+        }
+    }
+
+    /// Connect for the first time, failing straight away if the jig isn't
+    /// there
+    pub async fn open(connect: C, policy: RetryPolicy) -> Result<Self, String> {
+        let transport = connect.connect().await?;
+        Ok(Self::new(connect, transport, policy))
+    }
+
+    pub fn connector(&self) -> &C {
+        &self.connect
+    }
+
+    /// Receive every [`ConnectionEvent`] from now on
+    pub fn events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: ConnectionEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// The open connection, reconnecting first if it was lost
+    async fn transport(&self) -> Result<Arc<C::Transport>, TransportError> {
+        let mut current = self.current.lock().await;
+        if let Some(transport) = &*current {
+            return Ok(transport.clone());
+        }
+
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+            match self.connect.connect().await {
+                Ok(transport) => {
+                    let transport = Arc::new(transport);
+                    *current = Some(transport.clone());
+                    self.notify(ConnectionEvent::Reconnected);
+                    return Ok(transport);
+                }
+                Err(error) => {
+                    self.notify(ConnectionEvent::Failed {
+                        attempt,
+                        error: error.clone(),
+                    });
+                    if self.policy.max_attempts.is_some_and(|max| attempt >= max) {
+                        self.notify(ConnectionEvent::GaveUp);
+                        return Err(TransportError::Reconnect(error));
+                    }
+                }
+            }
+            backoff = (backoff * 2).min(self.policy.max_backoff);
+        }
+    }
+
+    /// Note that `transport` was lost, unless it was replaced already
+    async fn lost(&self, transport: &Arc<C::Transport>, error: &TransportError) {
+        let mut current = self.current.lock().await;
+        if current.as_ref().is_some_and(|t| Arc::ptr_eq(t, transport)) {
+            *current = None;
+            self.notify(ConnectionEvent::Lost(error.to_string()));
+        }
+    }
+}
+
+impl<C: Connect> Transport for Reconnecting<C> {
+    async fn request<E>(&self, seq_no: u32, req: &E::Request) -> Result<E::Response, TransportError>
+    where
+        E: Endpoint,
+        E::Request: Serialize,
+        E::Response: DeserializeOwned,
+    {
+        let mut retry = self.policy.retry_idempotent && idempotent(E::PATH);
+        loop {
+            let transport = self.transport().await?;
+            match transport.request::<E>(seq_no, req).await {
+                Err(e) if e.is_disconnect() => {
+                    self.lost(&transport, &e).await;
+                    if !retry {
+                        return Err(e);
+                    }
+                    // Only once, a jig that keeps dropping out is reported
+                    retry = false;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Never sent again, the message may have arrived before the loss
+    async fn publish<T>(&self, seq_no: u32, msg: &T::Message) -> Result<(), TransportError>
+    where
+        T: Topic,
+        T::Message: Serialize,
+    {
+        let transport = self.transport().await?;
+        let res = transport.publish::<T>(seq_no, msg).await;
+        if let Err(e) = &res {
+            if e.is_disconnect() {
+                self.lost(&transport, e).await;
+            }
+        }
+        res
+    }
+
+    /// The subscription ends with the connection it was made on
+    async fn subscribe<T>(&self) -> Result<Subscription<T>, TransportError>
+    where
+        T: Topic,
+        T::Message: DeserializeOwned,
+    {
+        let mut retry = self.policy.retry_idempotent;
+        loop {
+            let transport = self.transport().await?;
+            match transport.subscribe::<T>().await {
+                Err(e) if e.is_disconnect() => {
+                    self.lost(&transport, &e).await;
+                    if !retry {
+                        return Err(e);
+                    }
+                    retry = false;
+                }
+                res => return res,
+            }
+        }
+    }
+}
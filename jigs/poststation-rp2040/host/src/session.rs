@@ -0,0 +1,490 @@
+//! Recording and replaying I2C sessions
+//!
+//! [`Recorder`] wraps any [`I2c`] implementation (e.g. the jig's
+//! [`I2cDev`](crate::I2cDev)) and logs every transaction, with what was read
+//! and how it ended, as one line of JSON. [`Replay`] serves a recorded session
+//! back without any hardware, so drivers can be tested in CI. If the driver
+//! doesn't make the same requests as when the session was recorded, the
+//! replay fails with [`ReplayError::Diverged`].
+//!
+//! ```no_run
+//! use picocalc_jig_host::session::Replay;
+//! use embedded_hal_async::i2c::I2c;
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut i2c = Replay::load("keyboard.jsonl")?;
+//! let mut data = [0u8; 2];
+//! i2c.write_read(0x1F, &[0x09], &mut data).await?;
+//! i2c.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use serde::{Deserialize, Serialize};
+
+/// One operation of a recorded transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedOp {
+    Write(Vec<u8>),
+    /// The data that was read
+    Read(Vec<u8>),
+}
+
+/// One operation of a transaction as requested, before it ran
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestOp {
+    Write(Vec<u8>),
+    /// The number of bytes to read
+    Read(usize),
+}
+
+/// [`ErrorKind`], in a form that can be saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedErrorKind {
+    Bus,
+    ArbitrationLoss,
+    NoAcknowledgeAddress,
+    NoAcknowledgeData,
+    NoAcknowledgeUnknown,
+    Overrun,
+    Other,
+}
+
+impl From<ErrorKind> for RecordedErrorKind {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Bus => Self::Bus,
+            ErrorKind::ArbitrationLoss => Self::ArbitrationLoss,
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address) => Self::NoAcknowledgeAddress,
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data) => Self::NoAcknowledgeData,
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown) => Self::NoAcknowledgeUnknown,
+            ErrorKind::Overrun => Self::Overrun,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl From<RecordedErrorKind> for ErrorKind {
+    fn from(kind: RecordedErrorKind) -> Self {
+        match kind {
+            RecordedErrorKind::Bus => Self::Bus,
+            RecordedErrorKind::ArbitrationLoss => Self::ArbitrationLoss,
+            RecordedErrorKind::NoAcknowledgeAddress => {
+                Self::NoAcknowledge(NoAcknowledgeSource::Address)
+            }
+            RecordedErrorKind::NoAcknowledgeData => Self::NoAcknowledge(NoAcknowledgeSource::Data),
+            RecordedErrorKind::NoAcknowledgeUnknown => {
+                Self::NoAcknowledge(NoAcknowledgeSource::Unknown)
+            }
+            RecordedErrorKind::Overrun => Self::Overrun,
+            RecordedErrorKind::Other => Self::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedError {
+    pub kind: RecordedErrorKind,
+    /// The error's `Debug` output, for humans
+    pub message: String,
+}
+
+/// One line of a recorded session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub addr: u8,
+    pub ops: Vec<RecordedOp>,
+    /// Set if the transaction failed, in which case the data read is meaningless
+    pub error: Option<RecordedError>,
+}
+
+impl Transaction {
+    /// What was asked for, without the data read
+    pub fn request(&self) -> Vec<RequestOp> {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                RecordedOp::Write(data) => RequestOp::Write(data.clone()),
+                RecordedOp::Read(data) => RequestOp::Read(data.len()),
+            })
+            .collect()
+    }
+}
+
+fn request(operations: &[Operation<'_>]) -> Vec<RequestOp> {
+    operations
+        .iter()
+        .map(|op| match op {
+            Operation::Write(data) => RequestOp::Write(data.to_vec()),
+            Operation::Read(data) => RequestOp::Read(data.len()),
+        })
+        .collect()
+}
+
+/// Logs every transaction made through `inner` to `out`
+pub struct Recorder<I, W: Write = File> {
+    inner: I,
+    out: W,
+    /// The first error writing the log, see [`Recorder::finish`]
+    io_error: Option<io::Error>,
+}
+
+impl<I: I2c> Recorder<I> {
+    /// Record to a new file at `path`, replacing any existing one
+    pub fn create(inner: I, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(inner, File::create(path)?))
+    }
+}
+
+impl<I: I2c, W: Write> Recorder<I, W> {
+    pub fn new(inner: I, out: W) -> Self {
+        Self {
+            inner,
+            out,
+            io_error: None,
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Stop recording, giving back `inner` if the whole log was written
+    pub fn finish(mut self) -> io::Result<I> {
+        match self.io_error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush().map(|()| self.inner),
+        }
+    }
+
+    fn log(&mut self, trans: &Transaction) {
+        if self.io_error.is_some() {
+            return;
+        }
+        let res = serde_json::to_string(trans)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.out, "{line}"));
+        self.io_error = res.err();
+    }
+}
+
+impl<I: I2c, W: Write> ErrorType for Recorder<I, W> {
+    type Error = I::Error;
+}
+
+impl<I: I2c, W: Write> I2c for Recorder<I, W> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let res = self.inner.transaction(address, operations).await;
+        let trans = Transaction {
+            addr: address,
+            ops: operations
+                .iter()
+                .map(|op| match op {
+                    Operation::Write(data) => RecordedOp::Write(data.to_vec()),
+                    Operation::Read(data) => RecordedOp::Read(data.to_vec()),
+                })
+                .collect(),
+            error: res.as_ref().err().map(|e| RecordedError {
+                kind: e.kind().into(),
+                message: format!("{e:?}"),
+            }),
+        };
+        self.log(&trans);
+        res
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Transaction `index` isn't the one that was recorded
+    Diverged {
+        index: usize,
+        expected_addr: u8,
+        expected: Vec<RequestOp>,
+        actual_addr: u8,
+        actual: Vec<RequestOp>,
+    },
+    /// More transactions were made than were recorded
+    Exhausted { index: usize },
+    /// The transaction failed when it was recorded
+    Recorded(RecordedError),
+    /// [`Replay::finish`] was called before every transaction was replayed
+    Unfinished { remaining: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Diverged {
+                index,
+                expected_addr,
+                expected,
+                actual_addr,
+                actual,
+            } => write!(
+                f,
+                "transaction {index} diverged: expected 0x{expected_addr:02X} {expected:02X?}, got 0x{actual_addr:02X} {actual:02X?}"
+            ),
+            ReplayError::Exhausted { index } => write!(f, "transaction {index} wasn't recorded"),
+            ReplayError::Recorded(e) => write!(f, "recorded error: {}", e.message),
+            ReplayError::Unfinished { remaining } => write!(f, "{remaining} recorded transactions weren't replayed"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl Error for ReplayError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            ReplayError::Recorded(e) => e.kind.into(),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Serves the responses of a recorded session
+pub struct Replay {
+    transactions: Vec<Transaction>,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(transactions: Vec<Transaction>) -> Self {
+        Self {
+            transactions,
+            next: 0,
+        }
+    }
+
+    /// Load a session written by a [`Recorder`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut transactions = vec![];
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            transactions.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self::new(transactions))
+    }
+
+    /// Number of recorded transactions not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.transactions.len() - self.next
+    }
+
+    /// Check that the whole session was replayed
+    pub fn finish(self) -> Result<(), ReplayError> {
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(ReplayError::Unfinished { remaining }),
+        }
+    }
+}
+
+impl ErrorType for Replay {
+    type Error = ReplayError;
+}
+
+impl I2c for Replay {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let index = self.next;
+        let trans = self
+            .transactions
+            .get(index)
+            .ok_or(ReplayError::Exhausted { index })?;
+        let actual = request(operations);
+        let expected = trans.request();
+        if trans.addr != address || actual != expected {
+            return Err(ReplayError::Diverged {
+                index,
+                expected_addr: trans.addr,
+                expected,
+                actual_addr: address,
+                actual,
+            });
+        }
+        if let Some(e) = &trans.error {
+            self.next += 1;
+            return Err(ReplayError::Recorded(e.clone()));
+        }
+        for (op, rec) in operations.iter_mut().zip(&trans.ops) {
+            if let (Operation::Read(buf), RecordedOp::Read(data)) = (op, rec) {
+                // Compared with the requests above already, but a bad
+                // session must not panic
+                if buf.len() != data.len() {
+                    return Err(ReplayError::Diverged {
+                        index,
+                        expected_addr: trans.addr,
+                        expected,
+                        actual_addr: address,
+                        actual,
+                    });
+                }
+                buf.copy_from_slice(data);
+            }
+        }
+        self.next += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimError, SimSouthbridge};
+    use picocalc_jig_icd::{REG_ID_FIF, REG_ID_VER, SB_I2C_ADDR};
+
+    /// Reads the version, a key and something that isn't there, returning
+    /// what was read
+    async fn session<I: I2c>(i2c: &mut I) -> ([u8; 2], [u8; 2], Result<(), I::Error>) {
+        let mut version = [0u8; 2];
+        i2c.write_read(SB_I2C_ADDR, &[REG_ID_VER], &mut version)
+            .await
+            .unwrap();
+        let mut key = [0u8; 2];
+        i2c.write_read(SB_I2C_ADDR, &[REG_ID_FIF], &mut key)
+            .await
+            .unwrap();
+        let missing = i2c.write(0x50, &[0x00]).await;
+        (version, key, missing)
+    }
+
+    async fn record() -> Vec<Transaction> {
+        let sb = SimSouthbridge::new();
+        sb.press(b'a');
+        let mut log = vec![];
+        let mut recorder = Recorder::new(sb, &mut log);
+        let (_, _, missing) = session(&mut recorder).await;
+        assert_eq!(missing, Err(SimError::NoDevice));
+        recorder.finish().unwrap();
+
+        String::from_utf8(log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replays_what_was_recorded() {
+        let transactions = record().await;
+        assert_eq!(transactions.len(), 3);
+
+        let mut replay = Replay::new(transactions);
+        let (version, key, missing) = session(&mut replay).await;
+        assert_eq!(version, [REG_ID_VER, 0x10]);
+        assert_eq!(key, [1, b'a']);
+        let e = missing.unwrap_err();
+        assert!(matches!(e, ReplayError::Recorded(_)), "{e:?}");
+        assert_eq!(
+            e.kind(),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+        replay.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn changed_requests_diverge() {
+        let transactions = record().await;
+        let mut data = [0u8; 2];
+
+        // Address
+        let mut replay = Replay::new(transactions.clone());
+        let res = replay.write_read(0x1E, &[REG_ID_VER], &mut data).await;
+        assert!(
+            matches!(
+                res,
+                Err(ReplayError::Diverged {
+                    index: 0,
+                    actual_addr: 0x1E,
+                    ..
+                })
+            ),
+            "{res:?}"
+        );
+
+        // Operation
+        let mut replay = Replay::new(transactions.clone());
+        let res = replay.read(SB_I2C_ADDR, &mut data).await;
+        assert!(
+            matches!(res, Err(ReplayError::Diverged { index: 0, .. })),
+            "{res:?}"
+        );
+
+        // Length read
+        let mut replay = Replay::new(transactions.clone());
+        let res = replay
+            .write_read(SB_I2C_ADDR, &[REG_ID_VER], &mut [0u8; 3])
+            .await;
+        assert!(
+            matches!(res, Err(ReplayError::Diverged { index: 0, .. })),
+            "{res:?}"
+        );
+
+        // A diverged transaction isn't used up
+        replay
+            .write_read(SB_I2C_ADDR, &[REG_ID_VER], &mut data)
+            .await
+            .unwrap();
+        assert_eq!(replay.remaining(), 2);
+    }
+
+    #[tokio::test]
+    async fn edited_lengths_diverge() {
+        let mut transactions = record().await;
+        transactions[0].ops[1] = RecordedOp::Read(vec![0; 3]);
+
+        let mut replay = Replay::new(transactions);
+        let res = replay
+            .write_read(SB_I2C_ADDR, &[REG_ID_VER], &mut [0u8; 2])
+            .await;
+        assert!(
+            matches!(res, Err(ReplayError::Diverged { index: 0, .. })),
+            "{res:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn more_or_fewer_transactions() {
+        let transactions = record().await;
+
+        let mut replay = Replay::new(transactions.clone());
+        let _ = session(&mut replay).await;
+        let res = replay.write(SB_I2C_ADDR, &[REG_ID_VER]).await;
+        assert!(
+            matches!(res, Err(ReplayError::Exhausted { index: 3 })),
+            "{res:?}"
+        );
+
+        let mut replay = Replay::new(transactions);
+        let mut data = [0u8; 2];
+        replay
+            .write_read(SB_I2C_ADDR, &[REG_ID_VER], &mut data)
+            .await
+            .unwrap();
+        let res = replay.finish();
+        assert!(
+            matches!(res, Err(ReplayError::Unfinished { remaining: 2 })),
+            "{res:?}"
+        );
+    }
+}
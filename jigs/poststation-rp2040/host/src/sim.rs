@@ -0,0 +1,335 @@
+//! A software model of the southbridge, for testing without a PicoCalc
+//!
+//! [`SimSouthbridge`] implements [`I2c`] and answers at [`SB_I2C_ADDR`] like the
+//! real one: a write selects a register (setting it too if the top bit of the
+//! register number is set), and reads return `[reg, value]`, or `[state, key]`
+//! from the key FIFO. Clones share the same state, so a test can keep one to
+//! script key presses while a driver uses the other.
+//!
+//! ```
+//! use picocalc_jig_host::{keyboard::{KeyEvent, KeyState}, sim::SimSouthbridge};
+//! use picocalc_jig_icd::{REG_ID_FIF, SB_I2C_ADDR};
+//! use embedded_hal_async::i2c::I2c;
+//!
+//! # tokio_test();
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn tokio_test() {
+//! let sb = SimSouthbridge::new();
+//! let mut i2c = sb.clone();
+//! sb.type_text("hi");
+//!
+//! let mut state = KeyState::default();
+//! let mut data = [0u8; 2];
+//! i2c.write_read(SB_I2C_ADDR, &[REG_ID_FIF], &mut data).await.unwrap();
+//! let rpt = state.update(data).unwrap();
+//! assert!(matches!(rpt.evt, KeyEvent::Press(_)));
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use picocalc_jig_icd::{
+    KeyEventState, REG_ID_BAT, REG_ID_BK2, REG_ID_BKL, REG_ID_FIF, REG_ID_KEY, REG_ID_RST,
+    REG_ID_VER, REG_WRITE_MASK, SB_I2C_ADDR, SB_REGISTERS, TARGET_KEY_FIFO_LEN,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimError {
+    /// Nothing answers at this address
+    NoDevice,
+}
+
+impl Error for SimError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+    }
+}
+
+struct State {
+    regs: [u8; SB_REGISTERS],
+    /// Register selected by the last write
+    selected: u8,
+    fifo: VecDeque<[u8; 2]>,
+    /// Key events dropped because the FIFO was full
+    overflows: usize,
+}
+
+impl State {
+    fn write(&mut self, data: &[u8]) {
+        let Some((&reg, rest)) = data.split_first() else {
+            return;
+        };
+        self.selected = reg & !REG_WRITE_MASK;
+        if reg & REG_WRITE_MASK == 0 {
+            return;
+        }
+        if let (Some(&value), Some(slot)) =
+            (rest.first(), self.regs.get_mut(self.selected as usize))
+        {
+            *slot = value;
+        }
+        if self.selected == REG_ID_RST {
+            self.fifo.clear();
+        }
+    }
+
+    fn read(&mut self) -> [u8; 2] {
+        match self.selected {
+            REG_ID_FIF => self.fifo.pop_front().unwrap_or([0, 0]),
+            REG_ID_KEY => [REG_ID_KEY, self.fifo.len() as u8 & 0x1F],
+            reg => [reg, self.regs.get(reg as usize).copied().unwrap_or(0)],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SimSouthbridge {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for SimSouthbridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimSouthbridge {
+    /// A southbridge at firmware version 1.0, with a full battery
+    pub fn new() -> Self {
+        let mut regs = [0; SB_REGISTERS];
+        regs[REG_ID_VER as usize] = 0x10;
+        regs[REG_ID_BAT as usize] = 100;
+        let state = State {
+            regs,
+            selected: 0,
+            fifo: VecDeque::new(),
+            overflows: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        f(&mut self.state.lock().unwrap())
+    }
+
+    /// Queue a raw `[state, key]` FIFO entry
+    pub fn push(&self, state: u8, key: u8) {
+        self.with_state(|s| match s.fifo.len() < TARGET_KEY_FIFO_LEN {
+            true => s.fifo.push_back([state, key]),
+            false => s.overflows += 1,
+        })
+    }
+
+    pub fn press(&self, key: u8) {
+        self.push(KeyEventState::Pressed as u8, key)
+    }
+
+    pub fn hold(&self, key: u8) {
+        self.push(KeyEventState::Hold as u8, key)
+    }
+
+    pub fn release(&self, key: u8) {
+        self.push(KeyEventState::Released as u8, key)
+    }
+
+    /// Press and release `key`
+    pub fn tap(&self, key: u8) {
+        self.press(key);
+        self.release(key);
+    }
+
+    /// Press `modifier`, tap `key`, then release `modifier`, e.g. for Ctrl-C
+    pub fn chord(&self, modifier: u8, key: u8) {
+        self.press(modifier);
+        self.tap(key);
+        self.release(modifier);
+    }
+
+    /// Tap each character of `text` in turn
+    pub fn type_text(&self, text: &str) {
+        for b in text.bytes() {
+            self.tap(b);
+        }
+    }
+
+    /// Key events waiting to be read
+    pub fn queued(&self) -> usize {
+        self.with_state(|s| s.fifo.len())
+    }
+
+    /// Key events dropped because the FIFO was full
+    pub fn overflows(&self) -> usize {
+        self.with_state(|s| s.overflows)
+    }
+
+    pub fn register(&self, reg: u8) -> u8 {
+        self.with_state(|s| s.regs.get(reg as usize).copied().unwrap_or(0))
+    }
+
+    pub fn set_register(&self, reg: u8, value: u8) {
+        self.with_state(|s| {
+            if let Some(slot) = s.regs.get_mut(reg as usize) {
+                *slot = value;
+            }
+        })
+    }
+
+    /// Battery charge, in percent
+    pub fn set_battery(&self, percent: u8) {
+        self.set_register(REG_ID_BAT, percent)
+    }
+
+    /// Brightness of the screen and keyboard backlights, as last written by
+    /// the driver
+    pub fn backlights(&self) -> (u8, u8) {
+        (self.register(REG_ID_BKL), self.register(REG_ID_BK2))
+    }
+}
+
+impl ErrorType for SimSouthbridge {
+    type Error = SimError;
+}
+
+impl I2c for SimSouthbridge {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if address != SB_I2C_ADDR {
+            return Err(SimError::NoDevice);
+        }
+        self.with_state(|s| {
+            for op in operations {
+                match op {
+                    Operation::Write(data) => s.write(data),
+                    Operation::Read(buf) => {
+                        let reply = s.read();
+                        buf.fill(0);
+                        let n = buf.len().min(reply.len());
+                        buf[..n].copy_from_slice(&reply[..n]);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::{Key, KeyEvent, KeyReport, KeyState, KEY_CTRL, KEY_LSHIFT};
+
+    /// Read the key FIFO until it's empty, decoding each entry
+    async fn reports(sb: &mut SimSouthbridge) -> Vec<KeyReport> {
+        let mut state = KeyState::default();
+        let mut reports = vec![];
+        while sb.queued() > 0 {
+            let mut data = [0u8; 2];
+            sb.write_read(SB_I2C_ADDR, &[REG_ID_FIF], &mut data)
+                .await
+                .unwrap();
+            reports.extend(state.update(data));
+        }
+        reports
+    }
+
+    #[tokio::test]
+    async fn chords_set_modifiers() {
+        let mut sb = SimSouthbridge::new();
+        sb.chord(KEY_CTRL, b'c');
+        sb.chord(KEY_LSHIFT, b'A');
+        sb.tap(b'a');
+
+        let reports = reports(&mut sb).await;
+        let mods: Vec<_> = reports.iter().map(|r| (r.ctrl, r.shift)).collect();
+        assert_eq!(
+            mods,
+            [
+                (true, false),
+                (true, false),
+                (false, true),
+                (false, true),
+                (false, false),
+                (false, false),
+            ]
+        );
+        assert!(matches!(reports[0].evt, KeyEvent::Press(Key::Char('c'))));
+        assert!(matches!(reports[2].evt, KeyEvent::Press(Key::Char('A'))));
+    }
+
+    #[tokio::test]
+    async fn hold_and_release_are_reported() {
+        let mut sb = SimSouthbridge::new();
+        sb.press(0xB5);
+        sb.hold(0xB5);
+        sb.release(0xB5);
+
+        let reports = reports(&mut sb).await;
+        assert!(
+            matches!(
+                reports.as_slice(),
+                [
+                    KeyReport {
+                        evt: KeyEvent::Press(Key::UpDPad),
+                        ..
+                    },
+                    KeyReport {
+                        evt: KeyEvent::Hold(Key::UpDPad),
+                        ..
+                    },
+                    KeyReport {
+                        evt: KeyEvent::Release(Key::UpDPad),
+                        ..
+                    },
+                ]
+            ),
+            "{reports:?}"
+        );
+    }
+
+    #[test]
+    fn full_fifo_counts_overflows() {
+        let sb = SimSouthbridge::new();
+        for _ in 0..TARGET_KEY_FIFO_LEN + 3 {
+            sb.press(b'x');
+        }
+        assert_eq!(sb.queued(), TARGET_KEY_FIFO_LEN);
+        assert_eq!(sb.overflows(), 3);
+    }
+
+    #[tokio::test]
+    async fn reset_clears_the_fifo() {
+        let mut sb = SimSouthbridge::new();
+        sb.type_text("hello");
+        assert_eq!(sb.queued(), 10);
+
+        sb.write(SB_I2C_ADDR, &[REG_ID_RST | REG_WRITE_MASK, 1])
+            .await
+            .unwrap();
+        assert_eq!(sb.queued(), 0);
+        let mut data = [0u8; 2];
+        sb.write_read(SB_I2C_ADDR, &[REG_ID_FIF], &mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, [0, 0]);
+    }
+
+    #[tokio::test]
+    async fn only_answers_at_its_address() {
+        let mut sb = SimSouthbridge::new();
+        let mut data = [0u8; 2];
+        let res = sb
+            .write_read(SB_I2C_ADDR + 1, &[REG_ID_VER], &mut data)
+            .await;
+        assert_eq!(res, Err(SimError::NoDevice));
+        assert_eq!(data, [0, 0]);
+    }
+}
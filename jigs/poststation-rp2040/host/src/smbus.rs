@@ -0,0 +1,113 @@
+//! The southbridge bus, spoken to with the SMBus protocols
+
+use picocalc_jig_icd::{SmbusOp, SmbusResponse};
+
+use crate::{error::Error, jig::Jig, transport::Transport};
+
+/// The SMBus protocols, with the device address given per transfer
+#[allow(async_fn_in_trait)]
+pub trait Smbus {
+    type Error;
+
+    async fn quick(&mut self, addr: u8, read: bool) -> Result<(), Self::Error>;
+    async fn send_byte(&mut self, addr: u8, byte: u8) -> Result<(), Self::Error>;
+    async fn receive_byte(&mut self, addr: u8) -> Result<u8, Self::Error>;
+    async fn write_byte(&mut self, addr: u8, cmd: u8, value: u8) -> Result<(), Self::Error>;
+    async fn read_byte(&mut self, addr: u8, cmd: u8) -> Result<u8, Self::Error>;
+    async fn write_word(&mut self, addr: u8, cmd: u8, value: u16) -> Result<(), Self::Error>;
+    async fn read_word(&mut self, addr: u8, cmd: u8) -> Result<u16, Self::Error>;
+    async fn process_call(&mut self, addr: u8, cmd: u8, value: u16) -> Result<u16, Self::Error>;
+    async fn block_write(&mut self, addr: u8, cmd: u8, data: &[u8]) -> Result<(), Self::Error>;
+    async fn block_read(&mut self, addr: u8, cmd: u8) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// The jig's southbridge bus, with each transfer made by the jig
+pub struct SmbusDev<T> {
+    jig: Jig<T>,
+    /// Use Packet Error Checking on every transfer
+    pec: bool,
+}
+
+impl<T: Transport> SmbusDev<T> {
+    pub fn new(jig: Jig<T>, pec: bool) -> Self {
+        Self { jig, pec }
+    }
+
+    /// The rest of the jig's endpoints
+    pub fn jig(&self) -> &Jig<T> {
+        &self.jig
+    }
+
+    async fn done(&mut self, addr: u8, op: SmbusOp) -> Result<(), Error> {
+        match self.jig.smbus(addr, op, self.pec).await? {
+            SmbusResponse::Done => Ok(()),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    async fn byte(&mut self, addr: u8, op: SmbusOp) -> Result<u8, Error> {
+        match self.jig.smbus(addr, op, self.pec).await? {
+            SmbusResponse::Byte(b) => Ok(b),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    async fn word(&mut self, addr: u8, op: SmbusOp) -> Result<u16, Error> {
+        match self.jig.smbus(addr, op, self.pec).await? {
+            SmbusResponse::Word(w) => Ok(w),
+            _ => Err(Error::BadResponse),
+        }
+    }
+}
+
+impl<T: Transport> Smbus for SmbusDev<T> {
+    type Error = Error;
+
+    async fn quick(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        self.done(addr, SmbusOp::Quick { read }).await
+    }
+
+    async fn send_byte(&mut self, addr: u8, byte: u8) -> Result<(), Error> {
+        self.done(addr, SmbusOp::SendByte(byte)).await
+    }
+
+    async fn receive_byte(&mut self, addr: u8) -> Result<u8, Error> {
+        self.byte(addr, SmbusOp::ReceiveByte).await
+    }
+
+    async fn write_byte(&mut self, addr: u8, cmd: u8, value: u8) -> Result<(), Error> {
+        self.done(addr, SmbusOp::WriteByte { cmd, value }).await
+    }
+
+    async fn read_byte(&mut self, addr: u8, cmd: u8) -> Result<u8, Error> {
+        self.byte(addr, SmbusOp::ReadByte { cmd }).await
+    }
+
+    async fn write_word(&mut self, addr: u8, cmd: u8, value: u16) -> Result<(), Error> {
+        self.done(addr, SmbusOp::WriteWord { cmd, value }).await
+    }
+
+    async fn read_word(&mut self, addr: u8, cmd: u8) -> Result<u16, Error> {
+        self.word(addr, SmbusOp::ReadWord { cmd }).await
+    }
+
+    async fn process_call(&mut self, addr: u8, cmd: u8, value: u16) -> Result<u16, Error> {
+        self.word(addr, SmbusOp::ProcessCall { cmd, value }).await
+    }
+
+    async fn block_write(&mut self, addr: u8, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        let data = data.to_vec();
+        self.done(addr, SmbusOp::BlockWrite { cmd, data }).await
+    }
+
+    async fn block_read(&mut self, addr: u8, cmd: u8) -> Result<Vec<u8>, Error> {
+        match self
+            .jig
+            .smbus(addr, SmbusOp::BlockRead { cmd }, self.pec)
+            .await?
+        {
+            SmbusResponse::Block(data) => Ok(data),
+            _ => Err(Error::BadResponse),
+        }
+    }
+}
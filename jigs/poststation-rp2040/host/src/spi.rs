@@ -0,0 +1,203 @@
+//! The LCD and SD card buses, as local `embedded-hal` SPI ports and pins
+//!
+//! [`SpiBusDev`] is one of the jig's SPI ports, [`SpiDev`] adds a chip select
+//! to it, and [`ControlPinDev`] drives the chip selects and the LCD's DC and
+//! RST lines directly. They share one [`Jig`], so e.g. a display driver can be
+//! handed the SPI device and its DC and RST pins at the same time.
+//!
+//! Every transfer and pin change is a request, so chip selects are held for
+//! as long as the host takes to make them.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use embedded_hal_async::spi::SpiDevice;
+//! use picocalc_jig_host::{spi::SpiDev, transport::DirectUsb, Jig};
+//! use picocalc_jig_icd::{ControlPin, SpiMode, SpiPort};
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let jig = Arc::new(Jig::new(DirectUsb::open(None)?));
+//! jig.spi_configure(SpiPort::Spi0, 400_000, SpiMode::Mode0).await?;
+//!
+//! let mut sd = SpiDev::new(jig.clone(), SpiPort::Spi0, ControlPin::SdCs);
+//! let mut r1 = [0u8; 8];
+//! sd.transfer(&mut r1, &[0x40, 0, 0, 0, 0, 0x95]).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use embedded_hal::digital::{self, OutputPin};
+use embedded_hal_async::spi::{self, Operation, SpiBus, SpiDevice};
+use picocalc_jig_icd::{ControlPin, GpioLevel, SpiPort, SPI_CHUNK_LEN};
+use tokio::runtime::Handle;
+
+use crate::{error::Error, jig::Jig, transport::Transport};
+
+/// One of the jig's SPI ports
+pub struct SpiBusDev<T> {
+    jig: Arc<Jig<T>>,
+    port: SpiPort,
+}
+
+impl<T: Transport> SpiBusDev<T> {
+    /// Use `port` as it is configured, see [`Jig::spi_configure`]
+    pub fn new(jig: Arc<Jig<T>>, port: SpiPort) -> Self {
+        Self { jig, port }
+    }
+
+    pub fn jig(&self) -> &Arc<Jig<T>> {
+        &self.jig
+    }
+}
+
+impl<T: Transport> spi::ErrorType for SpiBusDev<T> {
+    type Error = Error;
+}
+
+impl<T: Transport> SpiBus for SpiBusDev<T> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for chunk in words.chunks_mut(SPI_CHUNK_LEN) {
+            self.jig.spi_transfer(self.port, &[], chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for chunk in words.chunks(SPI_CHUNK_LEN) {
+            self.jig.spi_transfer(self.port, chunk, &mut []).await?;
+        }
+        Ok(())
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let len = read.len().max(write.len());
+        for start in (0..len).step_by(SPI_CHUNK_LEN) {
+            let end = (start + SPI_CHUNK_LEN).min(len);
+            // The longer of the two fills the chunk, the jig pads the other
+            let tx = &write[start.min(write.len())..end.min(write.len())];
+            let rx_len = read.len();
+            let rx = &mut read[start.min(rx_len)..end.min(rx_len)];
+            self.jig.spi_transfer(self.port, tx, rx).await?;
+        }
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for chunk in words.chunks_mut(SPI_CHUNK_LEN) {
+            let tx = chunk.to_vec();
+            self.jig.spi_transfer(self.port, &tx, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Transfers are complete once the jig replies
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A device on one of the jig's SPI ports, selected by one of its
+/// [`ControlPin`]s
+pub struct SpiDev<T> {
+    bus: SpiBusDev<T>,
+    cs: ControlPin,
+}
+
+impl<T: Transport> SpiDev<T> {
+    pub fn new(jig: Arc<Jig<T>>, port: SpiPort, cs: ControlPin) -> Self {
+        Self {
+            bus: SpiBusDev::new(jig, port),
+            cs,
+        }
+    }
+
+    pub fn jig(&self) -> &Arc<Jig<T>> {
+        self.bus.jig()
+    }
+
+    async fn run(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Error> {
+        for op in operations {
+            match op {
+                Operation::Read(buf) => self.bus.read(buf).await?,
+                Operation::Write(buf) => self.bus.write(buf).await?,
+                Operation::Transfer(read, write) => self.bus.transfer(read, write).await?,
+                Operation::TransferInPlace(buf) => self.bus.transfer_in_place(buf).await?,
+                // Requests take longer than this anyway, but there's no
+                // harm in making sure
+                Operation::DelayNs(ns) => {
+                    tokio::time::sleep(Duration::from_nanos((*ns).into())).await
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> spi::ErrorType for SpiDev<T> {
+    type Error = Error;
+}
+
+impl<T: Transport> SpiDevice for SpiDev<T> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let jig = self.bus.jig.clone();
+        jig.control_pin(self.cs, GpioLevel::Low).await?;
+        let res = self.run(operations).await;
+        // Release the device even if the transfers failed
+        let deselect = jig.control_pin(self.cs, GpioLevel::High).await;
+        res.and(deselect)
+    }
+}
+
+/// One of the jig's [`ControlPin`]s
+///
+/// [`OutputPin`] is blocking, so it waits for the jig's reply on the tokio
+/// runtime the pin was created in. From async code, that must be a
+/// multi-threaded runtime; the async [`ControlPinDev::set`] works on any.
+pub struct ControlPinDev<T> {
+    jig: Arc<Jig<T>>,
+    pin: ControlPin,
+    handle: Handle,
+}
+
+impl<T: Transport> ControlPinDev<T> {
+    /// Must be called from inside a tokio runtime
+    pub fn new(jig: Arc<Jig<T>>, pin: ControlPin) -> Self {
+        Self {
+            jig,
+            pin,
+            handle: Handle::current(),
+        }
+    }
+
+    pub async fn set(&self, level: GpioLevel) -> Result<(), Error> {
+        self.jig.control_pin(self.pin, level).await
+    }
+
+    fn set_blocking(&self, level: GpioLevel) -> Result<(), Error> {
+        let fut = self.set(level);
+        match Handle::try_current() {
+            // Called from a task, let the runtime know this thread is busy
+            Ok(_) => tokio::task::block_in_place(|| self.handle.block_on(fut)),
+            Err(_) => self.handle.block_on(fut),
+        }
+    }
+}
+
+impl<T: Transport> digital::ErrorType for ControlPinDev<T> {
+    type Error = Error;
+}
+
+impl<T: Transport> OutputPin for ControlPinDev<T> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_blocking(GpioLevel::Low)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_blocking(GpioLevel::High)
+    }
+}
@@ -0,0 +1,324 @@
+//! How the host tools reach the jig
+//!
+//! [`Transport`] sends requests to the jig's endpoints, publishes to its
+//! incoming topics and subscribes to its outgoing ones. It is implemented by
+//! [`Poststation`], going through a poststation server, and by [`DirectUsb`],
+//! which talks to the jig over USB bulk transfers itself, so the tools work
+//! without a server running. Either can be wrapped in
+//! [`Reconnecting`](crate::reconnect::Reconnecting) to survive the jig going
+//! away for a while.
+//!
+//! ```no_run
+//! use picocalc_jig_host::transport::{DirectUsb, Transport};
+//! use picocalc_jig_icd::GetUniqueIdEndpoint;
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let jig = DirectUsb::open(None)?;
+//! let id = jig.request::<GetUniqueIdEndpoint>(0, &()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use postcard_rpc::{
+    header::{VarSeq, VarSeqKind},
+    host_client::{HostClient, HostErr, MultiSubRxError, MultiSubscription},
+    standard_icd::{WireError, ERROR_PATH},
+    Endpoint, Topic,
+};
+use poststation_sdk::{ClientError, PoststationClient, StreamListener};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// USB product string of the jig's firmware
+pub const USB_PRODUCT: &str = "poststation-pico";
+
+// poststation-sdk only passes on what went wrong past the server as text, so
+// these are matched against what it builds in `PoststationClient::proxy_endpoint`
+// and the topic streams (poststation-sdk 0.4.1, src/lib.rs)
+
+/// Start of the `ClientError::Remote` message for `ProxyResponse::OtherErr`,
+/// built as `format!("Other Server Err: '{e}'")`: the server couldn't get an
+/// answer from the jig. When the jig answers with an error, the message is
+/// built as `format!("WireErr: {body:?}")` instead
+const SERVER_ERR: &str = "Other Server Err:";
+
+/// The `ClientError::Server` message for `TopicStreamResult::DeviceDisconnected`,
+/// built as `ClientError::Server("Device Disconnected".into())`
+const STREAM_DISCONNECTED: &str = "Device Disconnected";
+
+/// Messages buffered per subscription over USB before the oldest are dropped
+const SUBSCRIPTION_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum TransportError {
+    Poststation(ClientError),
+    Usb(HostErr<WireError>),
+    /// The jig went away, and couldn't be reconnected, see
+    /// [`Reconnecting`](crate::reconnect::Reconnecting)
+    Reconnect(String),
+}
+
+impl TransportError {
+    /// Whether the connection to the jig was lost, rather than a request
+    /// failing on one that works
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            // The server went away, or couldn't reach the jig. Wire errors
+            // from the jig itself are `Remote` too, but don't count
+            TransportError::Poststation(e) => match e {
+                ClientError::ConnectionClosed => true,
+                ClientError::Remote(msg) => msg.starts_with(SERVER_ERR),
+                ClientError::Server(msg) => msg == STREAM_DISCONNECTED,
+                _ => false,
+            },
+            TransportError::Usb(e) => matches!(e, HostErr::Closed),
+            TransportError::Reconnect(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Poststation(e) => write!(f, "poststation: {e}"),
+            TransportError::Usb(HostErr::Wire(e)) => write!(f, "usb: jig replied {e:?}"),
+            TransportError::Usb(HostErr::BadResponse) => write!(f, "usb: bad response"),
+            TransportError::Usb(HostErr::Postcard(e)) => write!(f, "usb: {e}"),
+            TransportError::Usb(HostErr::Closed) => write!(f, "usb: connection closed"),
+            TransportError::Reconnect(e) => write!(f, "reconnecting: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// A connection to one jig
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    /// Send `req` to endpoint `E`, and wait for the response
+    ///
+    /// `seq_no` tells concurrent requests apart, where the transport doesn't
+    /// number them itself.
+    async fn request<E>(
+        &self,
+        seq_no: u32,
+        req: &E::Request,
+    ) -> Result<E::Response, TransportError>
+    where
+        E: Endpoint,
+        E::Request: Serialize,
+        E::Response: DeserializeOwned;
+
+    /// Send `msg` to the jig on topic `T`
+    async fn publish<T>(&self, seq_no: u32, msg: &T::Message) -> Result<(), TransportError>
+    where
+        T: Topic,
+        T::Message: Serialize;
+
+    /// Receive the messages the jig publishes on topic `T` from now on
+    async fn subscribe<T>(&self) -> Result<Subscription<T>, TransportError>
+    where
+        T: Topic,
+        T::Message: DeserializeOwned;
+}
+
+/// Messages published by the jig on topic `T`
+pub struct Subscription<T>
+where
+    T: Topic,
+    T::Message: DeserializeOwned,
+{
+    inner: SubscriptionInner<T>,
+}
+
+enum SubscriptionInner<T>
+where
+    T: Topic,
+    T::Message: DeserializeOwned,
+{
+    Poststation(StreamListener<T>),
+    Usb(MultiSubscription<T::Message>),
+}
+
+impl<T> Subscription<T>
+where
+    T: Topic,
+    T::Message: DeserializeOwned,
+{
+    /// Wait for the next message, or `None` once the connection is closed
+    ///
+    /// Messages missed because they weren't received quickly enough are
+    /// skipped.
+    pub async fn recv(&mut self) -> Option<T::Message> {
+        match &mut self.inner {
+            SubscriptionInner::Poststation(sub) => sub.recv().await,
+            SubscriptionInner::Usb(sub) => loop {
+                match sub.recv().await {
+                    Ok(msg) => return Some(msg),
+                    Err(MultiSubRxError::Lagged(_)) => continue,
+                    Err(MultiSubRxError::IoClosed) => return None,
+                }
+            },
+        }
+    }
+}
+
+/// A jig attached to a poststation server
+pub struct Poststation {
+    pub client: PoststationClient,
+    pub serial: u64,
+}
+
+impl Poststation {
+    pub fn new(client: PoststationClient, serial: u64) -> Self {
+        Self { client, serial }
+    }
+}
+
+impl Transport for Poststation {
+    async fn request<E>(&self, seq_no: u32, req: &E::Request) -> Result<E::Response, TransportError>
+    where
+        E: Endpoint,
+        E::Request: Serialize,
+        E::Response: DeserializeOwned,
+    {
+        self.client
+            .proxy_endpoint::<E>(self.serial, seq_no, req)
+            .await
+            .map_err(TransportError::Poststation)
+    }
+
+    async fn publish<T>(&self, seq_no: u32, msg: &T::Message) -> Result<(), TransportError>
+    where
+        T: Topic,
+        T::Message: Serialize,
+    {
+        self.client
+            .publish_topic::<T>(self.serial, seq_no, msg)
+            .await
+            .map_err(TransportError::Poststation)
+    }
+
+    async fn subscribe<T>(&self) -> Result<Subscription<T>, TransportError>
+    where
+        T: Topic,
+        T::Message: DeserializeOwned,
+    {
+        let sub = self
+            .client
+            .stream_topic::<T>(self.serial)
+            .await
+            .map_err(TransportError::Poststation)?;
+        Ok(Subscription {
+            inner: SubscriptionInner::Poststation(sub),
+        })
+    }
+}
+
+/// A jig plugged into this machine, used without a poststation server
+pub struct DirectUsb {
+    pub client: HostClient<WireError>,
+}
+
+impl DirectUsb {
+    /// Open the jig with the given serial number, or the first one found
+    pub fn open(serial: Option<u64>) -> Result<Self, String> {
+        let serial = serial.map(|s| format!("{s:016X}"));
+        let client = HostClient::try_new_raw_nusb(
+            |dev| {
+                dev.product_string() == Some(USB_PRODUCT)
+                    && serial
+                        .as_deref()
+                        .is_none_or(|s| dev.serial_number() == Some(s))
+            },
+            ERROR_PATH,
+            8,
+            VarSeqKind::Seq4,
+        )?;
+        Ok(Self { client })
+    }
+}
+
+impl Transport for DirectUsb {
+    /// The client numbers requests itself, `seq_no` is ignored
+    async fn request<E>(
+        &self,
+        _seq_no: u32,
+        req: &E::Request,
+    ) -> Result<E::Response, TransportError>
+    where
+        E: Endpoint,
+        E::Request: Serialize,
+        E::Response: DeserializeOwned,
+    {
+        self.client
+            .send_resp::<E>(req)
+            .await
+            .map_err(TransportError::Usb)
+    }
+
+    async fn publish<T>(&self, seq_no: u32, msg: &T::Message) -> Result<(), TransportError>
+    where
+        T: Topic,
+        T::Message: Serialize,
+    {
+        self.client
+            .publish::<T>(VarSeq::Seq4(seq_no), msg)
+            .await
+            .map_err(|_| TransportError::Usb(HostErr::Closed))
+    }
+
+    async fn subscribe<T>(&self) -> Result<Subscription<T>, TransportError>
+    where
+        T: Topic,
+        T::Message: DeserializeOwned,
+    {
+        let sub = self
+            .client
+            .subscribe_multi::<T>(SUBSCRIPTION_DEPTH)
+            .await
+            .map_err(|_| TransportError::Usb(HostErr::Closed))?;
+        Ok(Subscription {
+            inner: SubscriptionInner::Usb(sub),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poststation(e: ClientError) -> TransportError {
+        TransportError::Poststation(e)
+    }
+
+    /// What poststation-sdk 0.4.1 builds for `ProxyResponse::OtherErr(e)`
+    fn server_err(e: &str) -> ClientError {
+        ClientError::Remote(format!("{SERVER_ERR} '{e}'"))
+    }
+
+    /// What poststation-sdk 0.4.1 builds for `ProxyResponse::WireErr { body, .. }`
+    fn wire_err(body: WireError) -> ClientError {
+        ClientError::Remote(format!("WireErr: {body:?}"))
+    }
+
+    #[test]
+    fn lost_connections_are_disconnects() {
+        assert!(poststation(ClientError::ConnectionClosed).is_disconnect());
+        assert!(poststation(server_err("Device not connected")).is_disconnect());
+        assert!(poststation(server_err("Request timed out")).is_disconnect());
+        let e = ClientError::Server(STREAM_DISCONNECTED.into());
+        assert!(poststation(e).is_disconnect());
+        assert!(TransportError::Usb(HostErr::Closed).is_disconnect());
+    }
+
+    #[test]
+    fn errors_from_the_jig_are_not_disconnects() {
+        assert!(!poststation(wire_err(WireError::FailedToSpawn)).is_disconnect());
+        assert!(!poststation(wire_err(WireError::UnknownKey)).is_disconnect());
+        assert!(!poststation(ClientError::Encoding).is_disconnect());
+        let e = TransportError::Usb(HostErr::Wire(WireError::FailedToSpawn));
+        assert!(!e.is_disconnect());
+    }
+}
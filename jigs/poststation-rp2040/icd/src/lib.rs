@@ -20,12 +20,25 @@ pub enum LedState {
     On,
 }
 
+/// Timeout used for I2C transfers that don't specify one
+pub const DEFAULT_I2C_TIMEOUT_MS: u32 = 500;
+/// Longest transfer a single read or write request can make, longer ones go
+/// through the bulk buffer
+pub const I2C_CHUNK_LEN: usize = 256;
+/// Size of the jig's bulk transfer buffer
+pub const I2C_BULK_LEN: usize = 8 * 1024;
+/// How long a bulk session can go unused before the buffer is given to someone
+/// else, see [`BulkStage`]
+pub const BULK_SESSION_TIMEOUT_MS: u32 = 2_000;
+
 // READ
 
 #[derive(Debug, Serialize, Deserialize, Schema)]
 pub struct ReadCommand {
     pub addr: u8,
     pub len: u32,
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
 }
 
 #[cfg(not(feature = "use-std"))]
@@ -53,6 +66,8 @@ pub type ReadResult = Result<ReadData, I2cError>;
 pub struct WriteCommand<'a> {
     pub addr: u8,
     pub data: &'a [u8],
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
 }
 
 #[cfg(feature = "use-std")]
@@ -60,6 +75,8 @@ pub struct WriteCommand<'a> {
 pub struct WriteCommand {
     pub addr: u8,
     pub data: Vec<u8>,
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
 }
 
 pub type WriteResult = Result<(), I2cError>;
@@ -72,6 +89,8 @@ pub struct WriteReadCommand<'a> {
     pub addr: u8,
     pub tx_data: &'a [u8],
     pub rx_len: u32,
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
 }
 
 #[cfg(feature = "use-std")]
@@ -80,10 +99,915 @@ pub struct WriteReadCommand {
     pub addr: u8,
     pub tx_data: Vec<u8>,
     pub rx_len: u32,
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+// WRITE, DELAY, THEN READ
+
+/// Write, wait on the device, then read, as two separate transfers
+///
+/// Used for targets that need time to prepare a response, without depending on
+/// the host's timing between requests.
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct WriteDelayReadCommand<'a> {
+    pub addr: u8,
+    pub tx_data: &'a [u8],
+    pub delay_us: u32,
+    pub rx_len: u32,
+    /// Applies to each transfer, defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct WriteDelayReadCommand {
+    pub addr: u8,
+    pub tx_data: Vec<u8>,
+    pub delay_us: u32,
+    pub rx_len: u32,
+    /// Applies to each transfer, defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum I2cError {
+    /// The transfer failed on the bus, e.g. the target didn't ACK
+    Bus,
+    /// The transfer didn't complete in time, the I2C peripheral has been reset
+    Timeout,
+    /// The transfer doesn't fit in the jig's buffers
+    TooLong,
+    /// The bulk buffer is held by another session, or the session has ended
+    Busy,
+    /// The jig is impersonating the southbridge, see [`TargetModeEndpoint`]
+    TargetMode,
+}
+
+// BULK TRANSFERS
+//
+// Transfers longer than [`I2C_CHUNK_LEN`] are made in three steps: the data to
+// write is staged into the bulk buffer in chunks, the transfer runs on the bus
+// in one go, then the data read is fetched back in chunks.
+//
+// The buffer belongs to one session at a time. The first stage starts it, and
+// the steps after it name it, other sessions get [`I2cError::Busy`]. It ends
+// once everything read has been fetched, when a step fails, or after
+// [`BULK_SESSION_TIMEOUT_MS`] without being used.
+
+/// Names the session holding the bulk buffer
+pub type BulkSession = u32;
+
+/// Copy `data` into the bulk buffer at `offset`
+///
+/// Without a `session` a new one is started, staging nothing if the transfer
+/// only reads.
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct BulkStage<'a> {
+    pub session: Option<BulkSession>,
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct BulkStage {
+    pub session: Option<BulkSession>,
+    pub offset: u32,
+    pub data: Vec<u8>,
+}
+
+/// The session the data was staged for
+pub type BulkStageResult = Result<BulkSession, I2cError>;
+
+/// Write the first `tx_len` bytes of the bulk buffer to `addr`, then read
+/// `rx_len` bytes, storing them right after the written data
+///
+/// Either length may be zero. When both are used, they are joined by a
+/// repeated START.
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct BulkTransfer {
+    pub session: BulkSession,
+    pub addr: u8,
+    pub tx_len: u32,
+    pub rx_len: u32,
+    /// Defaults to twice the time the transfer takes at the bus speed, plus
+    /// [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+/// Fetch `len` bytes of the bulk buffer, starting at `offset`
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct BulkFetch {
+    pub session: BulkSession,
+    pub offset: u32,
+    pub len: u32,
+}
+
+// BUS RECOVERY
+
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct I2cRecovery {
+    /// SDA was held low before recovery started
+    pub sda_was_stuck: bool,
+    /// SCL was held low before recovery started, which clocking can't fix
+    pub scl_was_stuck: bool,
+    /// Number of SCL pulses sent before SDA was released
+    pub clocks: u8,
+    /// SDA was high after the STOP, meaning the bus is usable again
+    pub sda_released: bool,
+}
+
+pub type I2cRecoverResult = Result<I2cRecovery, I2cError>;
+
+// BUS STATISTICS
+
+/// Number of entries in [`I2cBusUser`]
+pub const I2C_BUS_USERS: usize = 3;
+
+/// Everything sharing the southbridge bus on the jig
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum I2cBusUser {
+    /// Passthrough and script requests from the host
+    Rpc,
+    /// Polling jobs, see [`PollJob`]
+    Poll,
+    /// Target mode, holding the bus while enabled, see [`TargetModeEndpoint`]
+    Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub struct I2cUserStats {
+    pub user: I2cBusUser,
+    /// Number of times the bus was taken
+    pub locks: u32,
+    pub transfers: u32,
+    /// Transfers that failed, including timeouts
+    pub errors: u32,
+    pub timeouts: u32,
+    /// Total time spent waiting for other users to release the bus
+    pub wait_us: u64,
+    /// Longest single wait for the bus
+    pub max_wait_us: u64,
+    /// Total time spent holding the bus
+    pub hold_us: u64,
+}
+
+/// Statistics for each [`I2cBusUser`], since the jig was reset
+pub type I2cBusStats = [I2cUserStats; I2C_BUS_USERS];
+
+// BUS TRACING
+
+/// Bytes kept in each direction of a traced transaction
+pub const I2C_TRACE_DATA_MAX: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum I2cDirection {
+    Read,
+    Write,
+    WriteRead,
+}
+
+/// A transaction on the southbridge bus, published on [`I2cTraceTopic`] while
+/// tracing is enabled
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct I2cTraceEvent<'a> {
+    pub user: I2cBusUser,
+    pub addr: u8,
+    pub dir: I2cDirection,
+    /// The first [`I2C_TRACE_DATA_MAX`] bytes written
+    pub write: &'a [u8],
+    pub write_len: u32,
+    /// The first [`I2C_TRACE_DATA_MAX`] bytes read
+    pub read: &'a [u8],
+    pub read_len: u32,
+    pub result: Result<(), I2cError>,
+    /// Device uptime when the transaction started
+    pub start_us: u64,
+    pub duration_us: u32,
+    /// Events dropped before this one, because the host wasn't keeping up
+    pub dropped: u32,
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct I2cTraceEvent {
+    pub user: I2cBusUser,
+    pub addr: u8,
+    pub dir: I2cDirection,
+    /// The first [`I2C_TRACE_DATA_MAX`] bytes written
+    pub write: Vec<u8>,
+    pub write_len: u32,
+    /// The first [`I2C_TRACE_DATA_MAX`] bytes read
+    pub read: Vec<u8>,
+    pub read_len: u32,
+    pub result: Result<(), I2cError>,
+    /// Device uptime when the transaction started
+    pub start_us: u64,
+    pub duration_us: u32,
+    /// Events dropped before this one, because the host wasn't keeping up
+    pub dropped: u32,
+}
+
+// SMBUS
+
+/// Longest block in an SMBus block read or write
+pub const SMBUS_BLOCK_MAX: usize = 32;
+
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub enum SmbusOp<'a> {
+    /// Only the R/W bit is sent, with no data
+    Quick {
+        read: bool,
+    },
+    SendByte(u8),
+    ReceiveByte,
+    WriteByte {
+        cmd: u8,
+        value: u8,
+    },
+    ReadByte {
+        cmd: u8,
+    },
+    WriteWord {
+        cmd: u8,
+        value: u16,
+    },
+    ReadWord {
+        cmd: u8,
+    },
+    /// Write a word, then read one back in the same transfer
+    ProcessCall {
+        cmd: u8,
+        value: u16,
+    },
+    BlockWrite {
+        cmd: u8,
+        data: &'a [u8],
+    },
+    /// The jig reads a full [`SMBUS_BLOCK_MAX`] bytes, as it can't stop early
+    /// once it has seen the byte count
+    BlockRead {
+        cmd: u8,
+    },
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Clone, Serialize, Deserialize, Schema)]
+pub enum SmbusOp {
+    /// Only the R/W bit is sent, with no data
+    Quick {
+        read: bool,
+    },
+    SendByte(u8),
+    ReceiveByte,
+    WriteByte {
+        cmd: u8,
+        value: u8,
+    },
+    ReadByte {
+        cmd: u8,
+    },
+    WriteWord {
+        cmd: u8,
+        value: u16,
+    },
+    ReadWord {
+        cmd: u8,
+    },
+    /// Write a word, then read one back in the same transfer
+    ProcessCall {
+        cmd: u8,
+        value: u16,
+    },
+    BlockWrite {
+        cmd: u8,
+        data: Vec<u8>,
+    },
+    /// The jig reads a full [`SMBUS_BLOCK_MAX`] bytes, as it can't stop early
+    /// once it has seen the byte count
+    BlockRead {
+        cmd: u8,
+    },
+}
+
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct SmbusCommand<'a> {
+    pub addr: u8,
+    #[serde(borrow)]
+    pub op: SmbusOp<'a>,
+    /// Append a Packet Error Checking byte to writes, and check it on reads.
+    /// Ignored for quick commands
+    pub pec: bool,
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct SmbusCommand {
+    pub addr: u8,
+    pub op: SmbusOp,
+    /// Append a Packet Error Checking byte to writes, and check it on reads.
+    /// Ignored for quick commands
+    pub pec: bool,
+    /// Defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub enum SmbusResponse<'a> {
+    /// Reply to ops that only write
+    Done,
+    Byte(u8),
+    Word(u16),
+    Block(&'a [u8]),
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub enum SmbusResponse {
+    /// Reply to ops that only write
+    Done,
+    Byte(u8),
+    Word(u16),
+    Block(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum SmbusError {
+    I2c(I2cError),
+    /// The PEC byte read didn't match the data
+    Pec,
+    /// A block was longer than [`SMBUS_BLOCK_MAX`] bytes
+    BlockTooLong,
+}
+
+#[cfg(not(feature = "use-std"))]
+pub type SmbusResult<'a> = Result<SmbusResponse<'a>, SmbusError>;
+
+#[cfg(feature = "use-std")]
+pub type SmbusResult = Result<SmbusResponse, SmbusError>;
+
+// SCRIPTS
+
+/// Longest program accepted by [`I2cScriptEndpoint`], in steps
+pub const MAX_SCRIPT_OPS: usize = 64;
+/// Longest program accepted by [`I2cScriptEndpoint`], in bytes
+pub const MAX_SCRIPT_LEN: usize = 512;
+
+/// A single step of a southbridge I2C script
+///
+/// A program is a sequence of postcard-encoded ops, one after another. Steps are
+/// numbered from zero in program order.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub enum ScriptOp<'a> {
+    /// Write `data` to `addr`
+    Write { addr: u8, data: &'a [u8] },
+    /// Read `len` bytes from `addr`, appending them to the report
+    Read { addr: u8, len: u8 },
+    /// Wait on the device
+    Delay { us: u32 },
+    /// Write `reg` to `addr` then read one byte back, until all bits of `mask`
+    /// are set. The byte is not added to the report
+    PollBitsSet {
+        addr: u8,
+        reg: u8,
+        mask: u8,
+        interval_us: u32,
+        attempts: u32,
+    },
+    /// Go back to `step` until the steps in between have run `times` times in
+    /// total. `step` must not be after the loop itself, and `times` must not be
+    /// 0, as the steps have run once by the time the loop is reached
+    Loop { step: u16, times: u32 },
+    /// Stop with [`ScriptStatus::Mismatch`] unless the last byte read (by a
+    /// `Read` or `PollBitsSet`), masked with `mask`, equals `value`
+    Expect { mask: u8, value: u8 },
+}
+
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct ScriptCommand<'a> {
+    /// Encoded [`ScriptOp`]s
+    pub program: &'a [u8],
+    /// Applies to each transfer, defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct ScriptCommand {
+    /// Encoded [`ScriptOp`]s
+    pub program: Vec<u8>,
+    /// Applies to each transfer, defaults to [`DEFAULT_I2C_TIMEOUT_MS`]
+    pub timeout_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum ScriptStatus {
+    /// Every step ran
+    Completed,
+    /// An `Expect` step didn't match, or nothing had been read yet
+    Mismatch,
+    /// A `PollBitsSet` step ran out of attempts
+    PollTimeout,
+    /// A transfer failed. Reads that don't fit in the report, [`I2C_CHUNK_LEN`]
+    /// bytes, fail with [`I2cError::TooLong`]
+    I2c(I2cError),
+    /// The program couldn't be decoded, is longer than [`MAX_SCRIPT_LEN`] bytes
+    /// or [`MAX_SCRIPT_OPS`] steps, or has a loop that goes forward or runs no
+    /// times
+    InvalidProgram,
+}
+
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct ScriptReport<'a> {
+    pub status: ScriptStatus,
+    /// The step the script stopped at, or the number of steps if it completed
+    pub step: u16,
+    /// Everything read by `Read` steps, in order
+    pub data: &'a [u8],
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct ScriptReport {
+    pub status: ScriptStatus,
+    /// The step the script stopped at, or the number of steps if it completed
+    pub step: u16,
+    /// Everything read by `Read` steps, in order
+    pub data: Vec<u8>,
+}
+
+// POLLING JOBS
+
+/// Number of polling jobs the jig can run at once
+pub const MAX_POLL_JOBS: usize = 8;
+/// Longest write a polling job can make
+pub const MAX_POLL_TX: usize = 4;
+/// Longest read a polling job can make
+pub const MAX_POLL_RX: usize = 32;
+
+/// Every `interval_ms`, write `tx_data[..tx_len]` to `addr` then read `rx_len`
+/// bytes, publishing the result on [`PollResultTopic`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub struct PollJob {
+    pub addr: u8,
+    pub tx_len: u8,
+    pub tx_data: [u8; MAX_POLL_TX],
+    pub rx_len: u8,
+    /// Wait between the write and the read, or 0 to use a repeated START
+    pub delay_us: u32,
+    pub interval_ms: u32,
+    /// Only publish results that differ from the previous one
+    pub only_on_change: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub struct PollJobInfo {
+    pub id: u32,
+    pub job: PollJob,
+}
+
+/// Every registered job, in no particular order
+pub type PollJobList = [Option<PollJobInfo>; MAX_POLL_JOBS];
+
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct PollResult<'a> {
+    /// The ID returned when the job was registered
+    pub id: u32,
+    /// Device uptime when the read completed
+    pub timestamp_us: u64,
+    #[serde(borrow)]
+    pub data: ReadResult<'a>,
+}
+
+#[cfg(feature = "use-std")]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct PollResult {
+    /// The ID returned when the job was registered
+    pub id: u32,
+    /// Device uptime when the read completed
+    pub timestamp_us: u64,
+    pub data: ReadResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum PollError {
+    /// [`MAX_POLL_JOBS`] jobs are already registered
+    Full,
+    /// The job writes or reads nothing or too much, or has a zero interval
+    InvalidJob,
+    /// There is no job with this ID
+    NoSuchJob,
+}
+
+pub type PollRegisterResult = Result<u32, PollError>;
+pub type PollCancelResult = Result<(), PollError>;
+
+// GPIO
+
+/// GPIOs brought out to the J702/J703 expansion headers
+///
+/// Only these pins may be controlled over RPC, everything else on the Pico
+/// header is wired to PicoCalc hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum ExpansionPin {
+    /// UART0_TX, J702-3
+    Gpio0,
+    /// UART0_RX, J702-2
+    Gpio1,
+    /// RAM_TX, J703-2
+    Gpio2,
+    /// RAM_RX, J703-3
+    Gpio3,
+    /// RAM_IO2, J703-4
+    Gpio4,
+    /// RAM_IO3, J703-5
+    Gpio5,
+    /// RAM_SCK, J703-6
+    Gpio21,
+    /// GP28, J703-7
+    Gpio28,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum GpioLevel {
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum GpioPull {
+    None,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum GpioMode {
+    /// Release the pin, leaving it disconnected (and GP28 free for the ADC)
+    Disabled,
+    Input,
+    /// Drive the pin, starting at the given level
+    Output(GpioLevel),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct GpioConfig {
+    pub pin: ExpansionPin,
+    pub mode: GpioMode,
+    pub pull: GpioPull,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct GpioWrite {
+    pub pin: ExpansionPin,
+    pub level: GpioLevel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum GpioEdge {
+    Rising,
+    Falling,
+    Any,
+}
+
+/// Start (or with `edge: None`, stop) publishing edges of `pin` on [`GpioEdgeTopic`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct GpioSubscribe {
+    pub pin: ExpansionPin,
+    pub edge: Option<GpioEdge>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct GpioEdgeEvent {
+    pub pin: ExpansionPin,
+    /// Level of the pin after the edge
+    pub level: GpioLevel,
+    /// Device uptime when the edge was seen
+    pub timestamp_us: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub enum GpioError {
+    /// The pin has not been configured yet
+    NotConfigured,
+    /// The pin has not been configured as an output
+    NotAnOutput,
+    /// The pin is currently used by something else, e.g. the ADC
+    PinInUse,
+}
+
+pub type GpioResult = Result<(), GpioError>;
+pub type GpioReadResult = Result<GpioLevel, GpioError>;
+
+// ADC
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum AdcChannel {
+    /// GP28/A2, J703-7. Only usable while the pin is not configured as a GPIO
+    Gpio28,
+    /// The Pico's VSYS rail, in volts
+    Vsys,
+    /// The RP2040's internal temperature sensor, in degrees C
+    Temperature,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct AdcSample {
+    pub channel: AdcChannel,
+    /// Volts, or degrees C for [`AdcChannel::Temperature`]
+    pub value: f32,
+    /// Device uptime when the sample was taken
+    pub timestamp_us: u64,
+}
+
+/// Start (or with `interval_ms: None`, stop) publishing samples of `channel`
+/// on [`AdcSampleTopic`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct AdcStream {
+    pub channel: AdcChannel,
+    pub interval_ms: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub enum AdcError {
+    /// GP28 is currently configured as a GPIO
+    PinInUse,
+    /// The ADC reported a conversion error
+    ConversionFailed,
+}
+
+pub type AdcReadResult = Result<AdcSample, AdcError>;
+pub type AdcStreamResult = Result<(), AdcError>;
+
+// LOGIC ANALYZER
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum CaptureTrigger {
+    /// Start sampling immediately
+    Immediate,
+    /// Start sampling once `gpio` is high
+    High(u8),
+    /// Start sampling once `gpio` is low
+    Low(u8),
+    /// Start sampling on a rising edge of `gpio`
+    Rising(u8),
+    /// Start sampling on a falling edge of `gpio`
+    Falling(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub struct CaptureConfig {
+    /// Lowest GPIO to sample
+    pub first_gpio: u8,
+    /// Number of consecutive GPIOs to sample. Each sample is stored as
+    /// 1, 2 or 4 little-endian bytes, whichever fits `gpio_count` bits
+    pub gpio_count: u8,
+    /// Requested sample rate, the rate actually used is reported in [`CaptureStatus`]
+    pub sample_rate_hz: u32,
+    /// Number of samples to take after the trigger
+    pub samples: u32,
+    pub trigger: CaptureTrigger,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum CaptureState {
+    /// No capture has been taken
+    Idle,
+    /// Waiting for the trigger
+    Armed,
+    /// Triggered, waiting for the buffer to fill
+    Running,
+    /// The capture is complete and can be fetched
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct CaptureStatus {
+    pub state: CaptureState,
+    pub config: Option<CaptureConfig>,
+    /// Sample rate after rounding to the PIO clock divider
+    pub actual_rate_hz: u32,
+}
+
+/// Fetch `len` bytes of a completed capture, starting at `offset`
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct CaptureFetch {
+    pub offset: u32,
+    pub len: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub enum CaptureError {
+    /// The configuration can't be captured, e.g. too many samples or an
+    /// unreachable sample rate
+    InvalidConfig,
+    /// A capture is already armed or running
+    Busy,
+    /// There is no completed capture to fetch
+    NoCapture,
+    /// The requested range is outside of the capture, or too long for one chunk
+    OutOfRange,
+}
+
+pub type CaptureStartResult = Result<(), CaptureError>;
+
+#[cfg(not(feature = "use-std"))]
+pub type CaptureFetchResult<'a> = Result<ReadData<'a>, CaptureError>;
+
+#[cfg(feature = "use-std")]
+pub type CaptureFetchResult = Result<ReadData, CaptureError>;
+
+// SOUTHBRIDGE REGISTERS
+//
+// As numbered by the PicoCalc keyboard firmware. Registers are read as
+// `[reg, value]`, or `[state, key]` from the key FIFO (`REG_ID_FIF`), and
+// written by setting the top bit of the register number.
+
+/// The southbridge's address, also answered by the jig in target mode
+pub const SB_I2C_ADDR: u8 = 0x1F;
+
+/// Firmware version
+pub const REG_ID_VER: u8 = 0x01;
+pub const REG_ID_CFG: u8 = 0x02;
+/// Interrupt status
+pub const REG_ID_INT: u8 = 0x03;
+/// Number of events in the key FIFO, in the low 5 bits
+pub const REG_ID_KEY: u8 = 0x04;
+/// Screen backlight
+pub const REG_ID_BKL: u8 = 0x05;
+/// Key debounce time
+pub const REG_ID_DEB: u8 = 0x06;
+/// Key poll frequency
+pub const REG_ID_FRQ: u8 = 0x07;
+/// Written to reset the southbridge
+pub const REG_ID_RST: u8 = 0x08;
+/// The key FIFO, each read pops one event
+pub const REG_ID_FIF: u8 = 0x09;
+/// Keyboard backlight
+pub const REG_ID_BK2: u8 = 0x0A;
+/// Battery charge, in percent
+pub const REG_ID_BAT: u8 = 0x0B;
+/// Set in the register number for writes
+pub const REG_WRITE_MASK: u8 = 0x80;
+/// Register numbers are below this, once [`REG_WRITE_MASK`] is cleared
+pub const SB_REGISTERS: usize = 16;
+
+// TARGET MODE
+//
+// The jig's I2C peripheral answers as the southbridge at [`SB_I2C_ADDR`], so
+// PicoCalc firmware running on another Pico can be fed scripted key presses.
+
+/// Number of key events the target mode FIFO can hold, as many as the
+/// southbridge's own
+pub const TARGET_KEY_FIFO_LEN: usize = 31;
+
+/// What happened to a key, as numbered in the southbridge's key FIFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum KeyEventState {
+    Pressed = 1,
+    Hold = 2,
+    Released = 3,
+}
+
+impl TryFrom<u8> for KeyEventState {
+    /// The byte, if it isn't a key state
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            1 => Ok(KeyEventState::Pressed),
+            2 => Ok(KeyEventState::Hold),
+            3 => Ok(KeyEventState::Released),
+            n => Err(n),
+        }
+    }
+}
+
+/// A key event queued for the firmware under test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub struct TargetKey {
+    pub state: KeyEventState,
+    /// Key code, as reported by the southbridge
+    pub key: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub struct TargetStatus {
+    pub enabled: bool,
+    /// Key events waiting to be read
+    pub queued: u8,
+    /// Key events dropped because the FIFO was full
+    pub overflows: u32,
+    /// Reads of the key FIFO, including empty ones
+    pub fifo_reads: u32,
+}
+
+// SPI
+//
+// The jig drives the PicoCalc's SPI buses as a controller. Chip selects are
+// plain GPIOs, see [`ControlPin`], so the host decides how long they are held.
+
+/// Longest transfer a single SPI request can make
+pub const SPI_CHUNK_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum SpiPort {
+    /// SD card: SCK GP18, TX GP19, RX GP16
+    Spi0,
+    /// LCD: SCK GP10, TX GP11, RX GP12
+    Spi1,
+}
+
+/// Clock polarity and phase, numbered as usual
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum SpiMode {
+    /// Idle low, sample on the first edge
+    Mode0,
+    /// Idle low, sample on the second edge
+    Mode1,
+    /// Idle high, sample on the first edge
+    Mode2,
+    /// Idle high, sample on the second edge
+    Mode3,
+}
+
+/// Set up a port. Ports start at 1 MHz in [`SpiMode::Mode0`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct SpiConfig {
+    pub port: SpiPort,
+    /// Rounded down to what the clock divider can make
+    pub frequency_hz: u32,
+    pub mode: SpiMode,
+}
+
+/// Clock out `tx`, then `0x00` until `rx_len` bytes have been read too
+///
+/// The reply holds the first `rx_len` bytes read.
+#[cfg(not(feature = "use-std"))]
+#[derive(Debug, Serialize, Deserialize, Schema)]
+pub struct SpiTransfer<'a> {
+    pub port: SpiPort,
+    pub tx: &'a [u8],
+    pub rx_len: u32,
 }
 
+#[cfg(feature = "use-std")]
 #[derive(Debug, Serialize, Deserialize, Schema)]
-pub struct I2cError;
+pub struct SpiTransfer {
+    pub port: SpiPort,
+    pub tx: Vec<u8>,
+    pub rx_len: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum SpiError {
+    /// The frequency is out of the port's range
+    InvalidConfig,
+    /// The transfer is longer than [`SPI_CHUNK_LEN`]
+    TooLong,
+}
+
+pub type SpiConfigResult = Result<(), SpiError>;
+
+#[cfg(not(feature = "use-std"))]
+pub type SpiTransferResult<'a> = Result<ReadData<'a>, SpiError>;
+
+#[cfg(feature = "use-std")]
+pub type SpiTransferResult = Result<ReadData, SpiError>;
+
+/// Outputs wired to the LCD and SD card, next to their SPI buses
+///
+/// These start high, which leaves both chip selects inactive and the LCD out
+/// of reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Schema)]
+pub enum ControlPin {
+    /// SPI1_CS, GP13
+    LcdCs,
+    /// LCD_DC, GP14
+    LcdDc,
+    /// LCD_RST, GP15
+    LcdRst,
+    /// SPI0_CS, GP17
+    SdCs,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Schema)]
+pub struct ControlPinWrite {
+    pub pin: ControlPin,
+    pub level: GpioLevel,
+}
 
 // ---
 
@@ -105,6 +1029,40 @@ endpoints! {
     | I2cWriteEndpoint          | WriteCommand          | WriteResult           | "jig/sb/i2c/write"            | cfg(feature = "use-std")      |
     | I2cWriteReadEndpoint      | WriteReadCommand<'a>  | ReadResult<'b>        | "jig/sb/i2c/write-read"       | cfg(not(feature = "use-std")) |
     | I2cWriteReadEndpoint      | WriteReadCommand      | ReadResult            | "jig/sb/i2c/write-read"       | cfg(feature = "use-std")      |
+    | I2cWriteDelayReadEndpoint | WriteDelayReadCommand<'a> | ReadResult<'b>    | "jig/sb/i2c/write-delay-read" | cfg(not(feature = "use-std")) |
+    | I2cWriteDelayReadEndpoint | WriteDelayReadCommand | ReadResult            | "jig/sb/i2c/write-delay-read" | cfg(feature = "use-std")      |
+    | I2cBulkStageEndpoint      | BulkStage<'a>         | BulkStageResult       | "jig/sb/i2c/bulk/stage"       | cfg(not(feature = "use-std")) |
+    | I2cBulkStageEndpoint      | BulkStage             | BulkStageResult       | "jig/sb/i2c/bulk/stage"       | cfg(feature = "use-std")      |
+    | I2cBulkTransferEndpoint   | BulkTransfer          | WriteResult           | "jig/sb/i2c/bulk/transfer"    |                               |
+    | I2cBulkFetchEndpoint      | BulkFetch             | ReadResult<'a>        | "jig/sb/i2c/bulk/fetch"       | cfg(not(feature = "use-std")) |
+    | I2cBulkFetchEndpoint      | BulkFetch             | ReadResult            | "jig/sb/i2c/bulk/fetch"       | cfg(feature = "use-std")      |
+    | SmbusEndpoint             | SmbusCommand<'a>      | SmbusResult<'b>       | "jig/sb/smbus"                | cfg(not(feature = "use-std")) |
+    | SmbusEndpoint             | SmbusCommand          | SmbusResult           | "jig/sb/smbus"                | cfg(feature = "use-std")      |
+    | I2cScriptEndpoint         | ScriptCommand<'a>     | ScriptReport<'b>      | "jig/sb/i2c/script"           | cfg(not(feature = "use-std")) |
+    | I2cScriptEndpoint         | ScriptCommand         | ScriptReport          | "jig/sb/i2c/script"           | cfg(feature = "use-std")      |
+    | I2cTraceEndpoint          | bool                  | ()                    | "jig/sb/i2c/trace/enable"     |                               |
+    | I2cBusStatsEndpoint       | ()                    | I2cBusStats           | "jig/sb/i2c/stats"            |                               |
+    | I2cRecoverEndpoint        | ()                    | I2cRecoverResult      | "jig/sb/i2c/recover"          |                               |
+    | TargetModeEndpoint        | bool                  | ()                    | "jig/target/mode"             |                               |
+    | TargetStatusEndpoint      | ()                    | TargetStatus          | "jig/target/status"           |                               |
+    | PollRegisterEndpoint      | PollJob               | PollRegisterResult    | "jig/poll/register"           |                               |
+    | PollListEndpoint          | ()                    | PollJobList           | "jig/poll/list"               |                               |
+    | PollCancelEndpoint        | u32                   | PollCancelResult      | "jig/poll/cancel"             |                               |
+    | GpioConfigureEndpoint     | GpioConfig            | GpioResult            | "jig/gpio/configure"          |                               |
+    | GpioReadEndpoint          | ExpansionPin          | GpioReadResult        | "jig/gpio/read"               |                               |
+    | GpioWriteEndpoint         | GpioWrite             | GpioResult            | "jig/gpio/write"              |                               |
+    | GpioSubscribeEndpoint     | GpioSubscribe         | GpioResult            | "jig/gpio/subscribe"          |                               |
+    | AdcReadEndpoint           | AdcChannel            | AdcReadResult         | "jig/adc/read"                |                               |
+    | AdcStreamEndpoint         | AdcStream             | AdcStreamResult       | "jig/adc/stream"              |                               |
+    | CaptureStartEndpoint      | CaptureConfig         | CaptureStartResult    | "jig/capture/start"           |                               |
+    | CaptureCancelEndpoint     | ()                    | ()                    | "jig/capture/cancel"          |                               |
+    | CaptureStatusEndpoint     | ()                    | CaptureStatus         | "jig/capture/status"          |                               |
+    | CaptureFetchEndpoint      | CaptureFetch          | CaptureFetchResult<'a> | "jig/capture/fetch"          | cfg(not(feature = "use-std")) |
+    | CaptureFetchEndpoint      | CaptureFetch          | CaptureFetchResult    | "jig/capture/fetch"           | cfg(feature = "use-std")      |
+    | SpiConfigureEndpoint      | SpiConfig             | SpiConfigResult       | "jig/spi/configure"           |                               |
+    | SpiTransferEndpoint       | SpiTransfer<'a>       | SpiTransferResult<'b> | "jig/spi/transfer"            | cfg(not(feature = "use-std")) |
+    | SpiTransferEndpoint       | SpiTransfer           | SpiTransferResult     | "jig/spi/transfer"            | cfg(feature = "use-std")      |
+    | ControlPinWriteEndpoint   | ControlPinWrite       | ()                    | "jig/spi/pin/write"           |                               |
 }
 
 // incoming topics handled by our device
@@ -113,6 +1071,7 @@ topics! {
     direction = TopicDirection::ToServer;
     | TopicTy                   | MessageTy     | Path              |
     | -------                   | ---------     | ----              |
+    | TargetKeyTopic            | TargetKey     | "jig/target/key"  |
 }
 
 // outgoing topics handled by our device
@@ -121,4 +1080,10 @@ topics! {
     direction = TopicDirection::ToClient;
     | TopicTy                   | MessageTy     | Path              | Cfg                           |
     | -------                   | ---------     | ----              | ---                           |
+    | GpioEdgeTopic             | GpioEdgeEvent | "jig/gpio/edge"   |                               |
+    | AdcSampleTopic            | AdcSample     | "jig/adc/sample"  |                               |
+    | PollResultTopic           | PollResult<'a> | "jig/poll/result" | cfg(not(feature = "use-std")) |
+    | PollResultTopic           | PollResult    | "jig/poll/result" | cfg(feature = "use-std")      |
+    | I2cTraceTopic             | I2cTraceEvent<'a> | "jig/sb/i2c/trace" | cfg(not(feature = "use-std")) |
+    | I2cTraceTopic             | I2cTraceEvent | "jig/sb/i2c/trace" | cfg(feature = "use-std")      |
 }
@@ -0,0 +1,172 @@
+//! Southbridge transfers longer than [`I2C_CHUNK_LEN`]
+//!
+//! The host stages what to write into the bulk buffer a chunk at a time, runs
+//! the transfer, then fetches what was read the same way. [`Bulk`] holds the
+//! buffer, [`I2C_BULK_LEN`] bytes owned by the caller, and the session using
+//! it.
+//!
+//! Times are in milliseconds from any fixed point, as read from the caller's
+//! clock.
+
+use embedded_hal_async::i2c::I2c;
+use picocalc_jig_icd::*;
+
+/// The bulk buffer, and who is using it
+pub struct Bulk<B> {
+    buf: B,
+    session: Option<Session>,
+    next_id: BulkSession,
+}
+
+struct Session {
+    id: BulkSession,
+    last_used_ms: u64,
+    /// Where the data read by the transfer ends, once it has run
+    read_end: Option<usize>,
+}
+
+impl<B> Bulk<B> {
+    pub const fn new(buf: B) -> Self {
+        Self {
+            buf,
+            session: None,
+            next_id: 1,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Bulk<B> {
+    /// Start a new session, unless another one is using the buffer
+    fn begin(&mut self, now_ms: u64) -> Result<BulkSession, I2cError> {
+        if self
+            .session
+            .as_ref()
+            .is_some_and(|s| now_ms.saturating_sub(s.last_used_ms) < BULK_SESSION_TIMEOUT_MS.into())
+        {
+            return Err(I2cError::Busy);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.session = Some(Session {
+            id,
+            last_used_ms: now_ms,
+            read_end: None,
+        });
+        Ok(id)
+    }
+
+    /// Check that `id` is the session holding the buffer
+    fn check(&mut self, id: BulkSession, now_ms: u64) -> Result<&mut Session, I2cError> {
+        match &mut self.session {
+            Some(s) if s.id == id => {
+                s.last_used_ms = now_ms;
+                Ok(s)
+            }
+            _ => Err(I2cError::Busy),
+        }
+    }
+
+    /// Ends the session on errors other than someone else's session
+    fn end_on_error<T>(&mut self, res: Result<T, I2cError>) -> Result<T, I2cError> {
+        if res.as_ref().is_err_and(|e| *e != I2cError::Busy) {
+            self.session = None;
+        }
+        res
+    }
+
+    /// Copy `data` into the buffer at `offset` for `session`, or a new session
+    pub fn stage(
+        &mut self,
+        session: Option<BulkSession>,
+        offset: u32,
+        data: &[u8],
+        now_ms: u64,
+    ) -> BulkStageResult {
+        let id = match session {
+            Some(id) => self.check(id, now_ms)?.id,
+            None => self.begin(now_ms)?,
+        };
+        let start = offset as usize;
+        let res = self
+            .buf
+            .as_mut()
+            .get_mut(start..start.saturating_add(data.len()))
+            .ok_or(I2cError::TooLong)
+            .map(|buf| buf.copy_from_slice(data));
+        self.end_on_error(res).map(|()| id)
+    }
+
+    /// Copy part of the buffer into `out`, ending the session once all that
+    /// was read has been fetched
+    pub fn fetch<'a>(
+        &mut self,
+        arg: BulkFetch,
+        out: &'a mut [u8],
+        now_ms: u64,
+    ) -> Result<&'a [u8], I2cError> {
+        let read_end = self.check(arg.session, now_ms)?.read_end;
+        let start = arg.offset as usize;
+        let end = start.saturating_add(arg.len as usize);
+        let res = match (
+            self.buf.as_ref().get(start..end),
+            out.get_mut(..arg.len as usize),
+        ) {
+            (Some(src), Some(out)) => {
+                out.copy_from_slice(src);
+                Ok(&*out)
+            }
+            _ => Err(I2cError::TooLong),
+        };
+        if read_end.is_some_and(|read_end| end >= read_end) {
+            self.session = None;
+        }
+        self.end_on_error(res)
+    }
+
+    /// Write the start of the buffer, then read into what follows it
+    ///
+    /// The session ends here unless there is something to fetch. `now_ms` is
+    /// read before and after the transfer.
+    pub async fn transfer<I, C>(&mut self, bus: &mut I, arg: BulkTransfer, now_ms: C) -> WriteResult
+    where
+        I: I2c,
+        I::Error: Into<I2cError>,
+        C: Fn() -> u64,
+    {
+        self.check(arg.session, now_ms())?;
+        let tx_len = arg.tx_len as usize;
+        let rx_len = arg.rx_len as usize;
+        let res = match tx_len.checked_add(rx_len) {
+            Some(len) if len <= self.buf.as_ref().len() => {
+                let (tx, rx) = self.buf.as_mut().split_at_mut(tx_len);
+                let rx = &mut rx[..rx_len];
+                let res = if rx.is_empty() {
+                    bus.write(arg.addr, tx).await
+                } else if tx.is_empty() {
+                    bus.read(arg.addr, rx).await
+                } else {
+                    bus.write_read(arg.addr, tx, rx).await
+                };
+                res.map_err(Into::into)
+            }
+            _ => Err(I2cError::TooLong),
+        };
+        match (&res, self.check(arg.session, now_ms())) {
+            (Ok(()), Ok(session)) if rx_len > 0 => session.read_end = Some(tx_len + rx_len),
+            _ => self.session = None,
+        }
+        res
+    }
+}
+
+/// How long `arg` may take on a bus running at `bus_hz`
+///
+/// That is twice its time on the wire, at 9 bits a byte plus an address byte
+/// for each direction, to allow for clock stretching, plus
+/// [`DEFAULT_I2C_TIMEOUT_MS`].
+pub fn timeout_ms(arg: &BulkTransfer, bus_hz: u32) -> u32 {
+    let bytes = u64::from(arg.tx_len) + u64::from(arg.rx_len) + 2;
+    let wire_ms = bytes * 9 * 1000 / u64::from(bus_hz.max(1));
+    let timeout_ms = u64::from(DEFAULT_I2C_TIMEOUT_MS) + 2 * wire_ms;
+    timeout_ms.try_into().unwrap_or(u32::MAX)
+}
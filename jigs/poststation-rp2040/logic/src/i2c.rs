@@ -0,0 +1,110 @@
+//! Single southbridge transfers, of up to [`I2C_CHUNK_LEN`] bytes each way
+//!
+//! Each function checks the lengths it was given before touching the bus, and
+//! answers [`I2cError::TooLong`] if they don't fit. What was read is returned
+//! from the caller's `buf`.
+
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use picocalc_jig_icd::*;
+
+/// A copy of the data to write, for handlers that can't borrow the request
+pub struct TxData {
+    buf: [u8; I2C_CHUNK_LEN],
+    len: usize,
+}
+
+impl TxData {
+    /// Copy `data`, unless it's too long
+    pub fn new(data: &[u8]) -> Result<Self, I2cError> {
+        let mut buf = [0u8; I2C_CHUNK_LEN];
+        buf.get_mut(..data.len())
+            .ok_or(I2cError::TooLong)?
+            .copy_from_slice(data);
+        Ok(Self {
+            buf,
+            len: data.len(),
+        })
+    }
+
+    pub fn get(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn tx_data(data: &[u8]) -> Result<&[u8], I2cError> {
+    match data.len() <= I2C_CHUNK_LEN {
+        true => Ok(data),
+        false => Err(I2cError::TooLong),
+    }
+}
+
+fn rx_buf(buf: &mut [u8; I2C_CHUNK_LEN], len: u32) -> Result<&mut [u8], I2cError> {
+    buf.get_mut(..len as usize).ok_or(I2cError::TooLong)
+}
+
+pub async fn read<'a, B>(
+    bus: &mut B,
+    addr: u8,
+    len: u32,
+    buf: &'a mut [u8; I2C_CHUNK_LEN],
+) -> Result<&'a [u8], I2cError>
+where
+    B: I2c,
+    B::Error: Into<I2cError>,
+{
+    let rx = rx_buf(buf, len)?;
+    bus.read(addr, rx).await.map_err(Into::into)?;
+    Ok(rx)
+}
+
+pub async fn write<B>(bus: &mut B, addr: u8, tx: &[u8]) -> WriteResult
+where
+    B: I2c,
+    B::Error: Into<I2cError>,
+{
+    bus.write(addr, tx_data(tx)?).await.map_err(Into::into)
+}
+
+pub async fn write_read<'a, B>(
+    bus: &mut B,
+    addr: u8,
+    tx: &[u8],
+    rx_len: u32,
+    buf: &'a mut [u8; I2C_CHUNK_LEN],
+) -> Result<&'a [u8], I2cError>
+where
+    B: I2c,
+    B::Error: Into<I2cError>,
+{
+    let tx = tx_data(tx)?;
+    let rx = rx_buf(buf, rx_len)?;
+    bus.write_read(addr, tx, rx).await.map_err(Into::into)?;
+    Ok(rx)
+}
+
+/// Write `tx` then read `rx_len` bytes, waiting `delay_us` in between
+///
+/// Unlike [`I2c::write_read`] there is a STOP after the write, for targets that
+/// need time to prepare their answer. `bus` should be held throughout, so that
+/// nobody can get in between the write and the read.
+pub async fn write_delay_read<'a, B, D>(
+    bus: &mut B,
+    delay: &mut D,
+    addr: u8,
+    tx: &[u8],
+    delay_us: u32,
+    rx_len: u32,
+    buf: &'a mut [u8; I2C_CHUNK_LEN],
+) -> Result<&'a [u8], I2cError>
+where
+    B: I2c,
+    B::Error: Into<I2cError>,
+    D: DelayNs,
+{
+    let tx = tx_data(tx)?;
+    let rx = rx_buf(buf, rx_len)?;
+    bus.write(addr, tx).await.map_err(Into::into)?;
+    delay.delay_us(delay_us).await;
+    bus.read(addr, rx).await.map_err(Into::into)?;
+    Ok(rx)
+}
@@ -0,0 +1,17 @@
+//! What the jig's handlers do, independent of the RP2040
+//!
+//! Everything here is generic over the [`embedded_hal_async`] traits, so the
+//! firmware can run it against the southbridge bus, and the `harness` crate
+//! against mock peripherals on the host.
+//!
+//! Requests and replies are split by the ICD's `use-std` feature, so they
+//! stay out of this crate's API: callers take the fields out of the request and
+//! build the reply themselves. Checking those fields is done here.
+
+#![no_std]
+
+pub mod bulk;
+pub mod i2c;
+pub mod poll;
+pub mod script;
+pub mod smbus;
@@ -0,0 +1,61 @@
+//! The polling jobs registered by the host
+//!
+//! [`Jobs`] checks and keeps the jobs, running them is up to the caller.
+
+use picocalc_jig_icd::*;
+
+/// Every registered job, and the ID for the next one
+pub struct Jobs {
+    next_id: u32,
+    list: PollJobList,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jobs {
+    pub const fn new() -> Self {
+        Self {
+            next_id: 1,
+            list: [None; MAX_POLL_JOBS],
+        }
+    }
+
+    pub fn register(&mut self, job: PollJob) -> PollRegisterResult {
+        // A job writes the register to read then reads it, so neither can be empty
+        let tx_len = job.tx_len as usize;
+        let rx_len = job.rx_len as usize;
+        if !(1..=MAX_POLL_TX).contains(&tx_len)
+            || !(1..=MAX_POLL_RX).contains(&rx_len)
+            || job.interval_ms == 0
+        {
+            return Err(PollError::InvalidJob);
+        }
+        let slot = self
+            .list
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(PollError::Full)?;
+        let id = self.next_id;
+        *slot = Some(PollJobInfo { id, job });
+        self.next_id = id.wrapping_add(1);
+        Ok(id)
+    }
+
+    pub fn list(&self) -> PollJobList {
+        self.list
+    }
+
+    pub fn cancel(&mut self, id: u32) -> PollCancelResult {
+        let slot = self
+            .list
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|info| info.id == id))
+            .ok_or(PollError::NoSuchJob)?;
+        *slot = None;
+        Ok(())
+    }
+}
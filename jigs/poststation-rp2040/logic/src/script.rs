@@ -0,0 +1,418 @@
+//! Southbridge I2C script executor
+//!
+//! Runs a program of [`ScriptOp`]s against the bus from start to end, without
+//! handling other requests in between.
+
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use picocalc_jig_icd::{I2cError, ScriptOp, ScriptStatus, MAX_SCRIPT_LEN, MAX_SCRIPT_OPS};
+
+/// How a script ended
+pub struct Outcome {
+    pub status: ScriptStatus,
+    pub step: u16,
+    /// Bytes of the output buffer filled by `Read` steps
+    pub len: usize,
+}
+
+/// A copy of the program, for handlers that can't borrow the request
+pub struct Program {
+    buf: [u8; MAX_SCRIPT_LEN],
+    len: usize,
+}
+
+impl Program {
+    /// Copy `program`, unless it's too long
+    pub fn new(program: &[u8]) -> Result<Self, ScriptStatus> {
+        let mut buf = [0u8; MAX_SCRIPT_LEN];
+        buf.get_mut(..program.len())
+            .ok_or(ScriptStatus::InvalidProgram)?
+            .copy_from_slice(program);
+        Ok(Self {
+            buf,
+            len: program.len(),
+        })
+    }
+
+    pub fn get(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Decode the step starting at `offset`, returning it with the offset of the next one
+fn decode(program: &[u8], offset: usize) -> Result<(ScriptOp<'_>, usize), ScriptStatus> {
+    let (op, rest) =
+        postcard::take_from_bytes(&program[offset..]).map_err(|_| ScriptStatus::InvalidProgram)?;
+    Ok((op, program.len() - rest.len()))
+}
+
+/// Find where each step starts, checking the whole program before anything runs
+fn index(
+    program: &[u8],
+    offsets: &mut [usize; MAX_SCRIPT_OPS],
+) -> Result<usize, (ScriptStatus, usize)> {
+    if program.len() > MAX_SCRIPT_LEN {
+        return Err((ScriptStatus::InvalidProgram, 0));
+    }
+    let mut offset = 0;
+    let mut count = 0;
+    while offset < program.len() {
+        if count == MAX_SCRIPT_OPS {
+            return Err((ScriptStatus::InvalidProgram, count));
+        }
+        offsets[count] = offset;
+        let (op, next) = decode(program, offset).map_err(|e| (e, count))?;
+        if let ScriptOp::Loop { step, times } = op {
+            // The steps in the loop run once before it's reached, so they can't run no times
+            if step as usize > count || times == 0 {
+                return Err((ScriptStatus::InvalidProgram, count));
+            }
+        }
+        offset = next;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn failed<E: Into<I2cError>>(e: E) -> ScriptStatus {
+    ScriptStatus::I2c(e.into())
+}
+
+/// State of a running script
+struct Script<'a, B, D> {
+    bus: &'a mut B,
+    delay: &'a mut D,
+    program: &'a [u8],
+    out: &'a mut [u8],
+    /// Bytes of `out` filled so far
+    len: usize,
+    /// The last byte read, for `Expect`
+    last: Option<u8>,
+}
+
+/// Run `program`, storing everything read into `out`
+pub async fn run<B, D>(bus: &mut B, delay: &mut D, program: &[u8], out: &mut [u8]) -> Outcome
+where
+    B: I2c,
+    B::Error: Into<I2cError>,
+    D: DelayNs,
+{
+    let mut offsets = [0; MAX_SCRIPT_OPS];
+    let count = match index(program, &mut offsets) {
+        Ok(count) => count,
+        Err((status, step)) => {
+            return Outcome {
+                status,
+                step: step as u16,
+                len: 0,
+            }
+        }
+    };
+
+    let mut script = Script {
+        bus,
+        delay,
+        program,
+        out,
+        len: 0,
+        last: None,
+    };
+    // Iterations left for each `Loop` step, `None` while it isn't running
+    let mut remaining: [Option<u32>; MAX_SCRIPT_OPS] = [None; MAX_SCRIPT_OPS];
+    let mut step = 0;
+    while step < count {
+        match script.exec(offsets[step], &mut remaining[step]).await {
+            Ok(Some(next)) => step = next,
+            Ok(None) => step += 1,
+            Err(status) => {
+                return Outcome {
+                    status,
+                    step: step as u16,
+                    len: script.len,
+                }
+            }
+        }
+    }
+    Outcome {
+        status: ScriptStatus::Completed,
+        step: count as u16,
+        len: script.len,
+    }
+}
+
+impl<B, D> Script<'_, B, D>
+where
+    B: I2c,
+    B::Error: Into<I2cError>,
+    D: DelayNs,
+{
+    /// Run the step at `offset`, returning the step to jump to, if any
+    async fn exec(
+        &mut self,
+        offset: usize,
+        remaining: &mut Option<u32>,
+    ) -> Result<Option<usize>, ScriptStatus> {
+        let (op, _) = decode(self.program, offset)?;
+        match op {
+            ScriptOp::Write { addr, data } => self.bus.write(addr, data).await.map_err(failed)?,
+            ScriptOp::Read { addr, len } => {
+                let end = self.len + len as usize;
+                let Some(buf) = self.out.get_mut(self.len..end) else {
+                    return Err(ScriptStatus::I2c(I2cError::TooLong));
+                };
+                self.bus.read(addr, buf).await.map_err(failed)?;
+                self.last = buf.last().copied().or(self.last);
+                self.len = end;
+            }
+            ScriptOp::Delay { us } => self.delay.delay_us(us).await,
+            ScriptOp::PollBitsSet {
+                addr,
+                reg,
+                mask,
+                interval_us,
+                attempts,
+            } => {
+                let mut byte = [0u8];
+                for attempt in 0..attempts {
+                    if attempt != 0 {
+                        self.delay.delay_us(interval_us).await;
+                    }
+                    self.bus.write(addr, &[reg]).await.map_err(failed)?;
+                    self.bus.read(addr, &mut byte).await.map_err(failed)?;
+                    self.last = Some(byte[0]);
+                    if byte[0] & mask == mask {
+                        return Ok(None);
+                    }
+                }
+                return Err(ScriptStatus::PollTimeout);
+            }
+            ScriptOp::Loop { step, times } => {
+                // The steps in the loop have already run once by the time we get here
+                let left = remaining.get_or_insert(times - 1);
+                if *left == 0 {
+                    *remaining = None;
+                } else {
+                    *left -= 1;
+                    return Ok(Some(step as usize));
+                }
+            }
+            ScriptOp::Expect { mask, value } => {
+                if self.last.map(|b| b & mask) != Some(value) {
+                    return Err(ScriptStatus::Mismatch);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_hal_async::i2c::{ErrorKind, ErrorType, Operation};
+
+    use super::*;
+
+    /// A target that answers every read with an increasing count
+    #[derive(Default)]
+    struct Counter {
+        next: u8,
+        transactions: usize,
+    }
+
+    #[derive(Debug)]
+    struct Never;
+
+    impl embedded_hal_async::i2c::Error for Never {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    impl From<Never> for I2cError {
+        fn from(_: Never) -> Self {
+            I2cError::Bus
+        }
+    }
+
+    impl ErrorType for Counter {
+        type Error = Never;
+    }
+
+    impl I2c for Counter {
+        async fn transaction(&mut self, _addr: u8, ops: &mut [Operation<'_>]) -> Result<(), Never> {
+            self.transactions += 1;
+            for op in ops {
+                if let Operation::Read(buf) = op {
+                    for b in buf.iter_mut() {
+                        *b = self.next;
+                        self.next += 1;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl DelayNs for Counter {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// `ops` encoded one after another into `buf`
+    fn encode<'a>(ops: &[ScriptOp<'_>], buf: &'a mut [u8]) -> &'a [u8] {
+        let mut len = 0;
+        for op in ops {
+            len += postcard::to_slice(op, &mut buf[len..]).unwrap().len();
+        }
+        &buf[..len]
+    }
+
+    fn validate(ops: &[ScriptOp<'_>]) -> Result<usize, (ScriptStatus, usize)> {
+        let mut buf = [0u8; 512];
+        index(encode(ops, &mut buf), &mut [0; MAX_SCRIPT_OPS])
+    }
+
+    fn run_ops(ops: &[ScriptOp<'_>], out: &mut [u8]) -> (Outcome, usize) {
+        let mut buf = [0u8; 512];
+        let program = encode(ops, &mut buf);
+        let mut bus = Counter::default();
+        let mut delay = Counter::default();
+        let outcome = block_on(run(&mut bus, &mut delay, program, out));
+        (outcome, bus.transactions)
+    }
+
+    #[test]
+    fn steps_are_found_in_order() {
+        let ops = [
+            ScriptOp::Write {
+                addr: 0x1F,
+                data: &[1, 2, 3],
+            },
+            ScriptOp::Delay { us: 70_000 },
+            ScriptOp::Read { addr: 0x1F, len: 2 },
+        ];
+        let mut buf = [0u8; 64];
+        let program = encode(&ops, &mut buf);
+        let mut offsets = [0; MAX_SCRIPT_OPS];
+        assert_eq!(index(program, &mut offsets), Ok(3));
+        for (i, &offset) in offsets[..3].iter().enumerate() {
+            let (op, _) = decode(program, offset).unwrap();
+            assert_eq!(
+                postcard::to_slice(&op, &mut [0; 16]).unwrap(),
+                postcard::to_slice(&ops[i], &mut [0; 16]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn undecodable_programs_are_invalid() {
+        let mut buf = [0u8; 64];
+        let program = encode(
+            &[
+                ScriptOp::Delay { us: 1 },
+                ScriptOp::Read { addr: 0x1F, len: 1 },
+            ],
+            &mut buf,
+        );
+        // Cut off in the middle of the second step
+        let program = &program[..program.len() - 1];
+        assert_eq!(
+            index(program, &mut [0; MAX_SCRIPT_OPS]),
+            Err((ScriptStatus::InvalidProgram, 1))
+        );
+        assert_eq!(
+            index(&[0xFF], &mut [0; MAX_SCRIPT_OPS]),
+            Err((ScriptStatus::InvalidProgram, 0))
+        );
+    }
+
+    #[test]
+    fn too_many_steps_are_invalid() {
+        let ops = [ScriptOp::Delay { us: 0 }; MAX_SCRIPT_OPS + 1];
+        assert_eq!(validate(&ops[..MAX_SCRIPT_OPS]), Ok(MAX_SCRIPT_OPS));
+        assert_eq!(
+            validate(&ops),
+            Err((ScriptStatus::InvalidProgram, MAX_SCRIPT_OPS))
+        );
+    }
+
+    #[test]
+    fn long_programs_are_invalid() {
+        let program = [0u8; MAX_SCRIPT_LEN + 1];
+        assert_eq!(
+            index(&program, &mut [0; MAX_SCRIPT_OPS]),
+            Err((ScriptStatus::InvalidProgram, 0))
+        );
+        assert_eq!(
+            Program::new(&program).err(),
+            Some(ScriptStatus::InvalidProgram)
+        );
+
+        let mut buf = [0u8; 64];
+        let program = encode(&[ScriptOp::Delay { us: 10 }], &mut buf);
+        assert_eq!(Program::new(program).unwrap().get(), program);
+    }
+
+    #[test]
+    fn loops_must_go_back_and_run() {
+        let read = ScriptOp::Read { addr: 0x1F, len: 1 };
+        assert_eq!(
+            validate(&[read, ScriptOp::Loop { step: 0, times: 1 }]),
+            Ok(2)
+        );
+        // Back to itself is allowed, an empty loop
+        assert_eq!(
+            validate(&[read, ScriptOp::Loop { step: 1, times: 3 }]),
+            Ok(2)
+        );
+        assert_eq!(
+            validate(&[read, ScriptOp::Loop { step: 2, times: 3 }]),
+            Err((ScriptStatus::InvalidProgram, 1))
+        );
+        assert_eq!(
+            validate(&[read, ScriptOp::Loop { step: 0, times: 0 }]),
+            Err((ScriptStatus::InvalidProgram, 1))
+        );
+    }
+
+    #[test]
+    fn loops_run_their_steps_times_times_in_total() {
+        for times in 1..4 {
+            let mut out = [0u8; 8];
+            let ops = [
+                ScriptOp::Read { addr: 0x1F, len: 1 },
+                ScriptOp::Loop { step: 0, times },
+            ];
+            let (outcome, transactions) = run_ops(&ops, &mut out);
+            assert_eq!(outcome.status, ScriptStatus::Completed);
+            assert_eq!(outcome.len, times as usize);
+            assert_eq!(transactions, times as usize);
+            assert_eq!(out[..outcome.len], [0, 1, 2][..times as usize]);
+        }
+    }
+
+    #[test]
+    fn invalid_programs_touch_nothing() {
+        let mut out = [0u8; 8];
+        let ops = [
+            ScriptOp::Read { addr: 0x1F, len: 1 },
+            ScriptOp::Loop { step: 0, times: 0 },
+        ];
+        let (outcome, transactions) = run_ops(&ops, &mut out);
+        assert_eq!(outcome.status, ScriptStatus::InvalidProgram);
+        assert_eq!(outcome.step, 1);
+        assert_eq!(transactions, 0);
+    }
+
+    #[test]
+    fn reads_past_the_report_are_too_long() {
+        let mut out = [0u8; 3];
+        let ops = [
+            ScriptOp::Read { addr: 0x1F, len: 2 },
+            ScriptOp::Read { addr: 0x1F, len: 2 },
+        ];
+        let (outcome, _) = run_ops(&ops, &mut out);
+        assert_eq!(outcome.status, ScriptStatus::I2c(I2cError::TooLong));
+        assert_eq!(outcome.step, 1);
+        assert_eq!(outcome.len, 2);
+    }
+}
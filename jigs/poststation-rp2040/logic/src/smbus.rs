@@ -0,0 +1,218 @@
+//! SMBus protocols on top of I2C, with optional PEC
+
+use embedded_hal_async::i2c::I2c;
+use picocalc_jig_icd::*;
+
+/// CRC-8 used for Packet Error Checking, x^8 + x^2 + x + 1
+pub fn crc8(crc: u8, data: &[u8]) -> u8 {
+    data.iter().fold(crc, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// PEC of a transfer, covering every address and data byte
+pub fn pec(addr: u8, tx: &[u8], rx: &[u8]) -> u8 {
+    let mut crc = 0;
+    if !tx.is_empty() {
+        crc = crc8(crc, &[addr << 1]);
+        crc = crc8(crc, tx);
+    }
+    if !rx.is_empty() {
+        crc = crc8(crc, &[(addr << 1) | 1]);
+        crc = crc8(crc, rx);
+    }
+    crc
+}
+
+/// Address-only transfers, which not every I2C peripheral can make
+#[allow(async_fn_in_trait)]
+pub trait QuickCommand: I2c {
+    /// Send only the address of `addr` and the R/W bit, checking for an ACK
+    async fn quick(&mut self, addr: u8, read: bool) -> Result<(), I2cError>;
+}
+
+/// [`SmbusResponse`], without the ICD's `use-std` split
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply<'a> {
+    Done,
+    Byte(u8),
+    Word(u16),
+    Block(&'a [u8]),
+}
+
+/// How the reply is read back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Quick { read: bool },
+    Done,
+    Byte,
+    Word,
+    Block,
+}
+
+/// An [`SmbusOp`] encoded for the bus, see [`run`]
+pub struct Request {
+    /// Command, byte count, block and PEC
+    tx: [u8; 2 + SMBUS_BLOCK_MAX + 1],
+    tx_len: usize,
+    rx_len: usize,
+    shape: Shape,
+    pec: bool,
+}
+
+impl Request {
+    /// Encode `op` for `addr`, with a PEC byte if `pec` is set
+    pub fn new(addr: u8, op: &SmbusOp, pec: bool) -> Result<Self, SmbusError> {
+        let mut tx = [0u8; 2 + SMBUS_BLOCK_MAX + 1];
+        let (mut tx_len, mut rx_len, shape) = match op {
+            &SmbusOp::Quick { read } => (0, 0, Shape::Quick { read }),
+            &SmbusOp::SendByte(byte) => {
+                tx[0] = byte;
+                (1, 0, Shape::Done)
+            }
+            &SmbusOp::ReceiveByte => (0, 1, Shape::Byte),
+            &SmbusOp::WriteByte { cmd, value } => {
+                tx[..2].copy_from_slice(&[cmd, value]);
+                (2, 0, Shape::Done)
+            }
+            &SmbusOp::ReadByte { cmd } => {
+                tx[0] = cmd;
+                (1, 1, Shape::Byte)
+            }
+            &SmbusOp::WriteWord { cmd, value } => {
+                tx[0] = cmd;
+                tx[1..3].copy_from_slice(&value.to_le_bytes());
+                (3, 0, Shape::Done)
+            }
+            &SmbusOp::ProcessCall { cmd, value } => {
+                tx[0] = cmd;
+                tx[1..3].copy_from_slice(&value.to_le_bytes());
+                (3, 2, Shape::Word)
+            }
+            &SmbusOp::ReadWord { cmd } => {
+                tx[0] = cmd;
+                (1, 2, Shape::Word)
+            }
+            SmbusOp::BlockWrite { cmd, data } => {
+                if data.len() > SMBUS_BLOCK_MAX {
+                    return Err(SmbusError::BlockTooLong);
+                }
+                tx[0] = *cmd;
+                tx[1] = data.len() as u8;
+                tx[2..][..data.len()].copy_from_slice(&data[..]);
+                (2 + data.len(), 0, Shape::Done)
+            }
+            &SmbusOp::BlockRead { cmd } => {
+                tx[0] = cmd;
+                (1, 1 + SMBUS_BLOCK_MAX, Shape::Block)
+            }
+        };
+        // Quick commands have no bytes to check
+        let pec = pec && !matches!(shape, Shape::Quick { .. });
+        if pec {
+            if rx_len == 0 {
+                tx[tx_len] = self::pec(addr, &tx[..tx_len], &[]);
+                tx_len += 1;
+            } else {
+                rx_len += 1;
+            }
+        }
+        Ok(Self {
+            tx,
+            tx_len,
+            rx_len,
+            shape,
+            pec,
+        })
+    }
+}
+
+/// Run `req` against `addr`, using `out` for the data read
+pub async fn run<'a, B>(
+    bus: &mut B,
+    addr: u8,
+    req: &Request,
+    out: &'a mut [u8],
+) -> Result<Reply<'a>, SmbusError>
+where
+    B: QuickCommand,
+    B::Error: Into<I2cError>,
+{
+    if let Shape::Quick { read } = req.shape {
+        return bus
+            .quick(addr, read)
+            .await
+            .map(|()| Reply::Done)
+            .map_err(SmbusError::I2c);
+    }
+    let tx = &req.tx[..req.tx_len];
+    let rx = out
+        .get_mut(..req.rx_len)
+        .ok_or(SmbusError::I2c(I2cError::TooLong))?;
+    let res = if rx.is_empty() {
+        bus.write(addr, tx).await
+    } else if tx.is_empty() {
+        bus.read(addr, rx).await
+    } else {
+        bus.write_read(addr, tx, rx).await
+    };
+    res.map_err(|e| SmbusError::I2c(e.into()))?;
+    if rx.is_empty() {
+        return Ok(Reply::Done);
+    }
+
+    // What was read, without the PEC
+    let data_len = match req.shape {
+        Shape::Block if rx[0] as usize > SMBUS_BLOCK_MAX => return Err(SmbusError::BlockTooLong),
+        Shape::Block => 1 + rx[0] as usize,
+        _ => req.rx_len - req.pec as usize,
+    };
+    if req.pec && pec(addr, tx, &rx[..data_len]) != rx[data_len] {
+        return Err(SmbusError::Pec);
+    }
+    let data = &rx[..data_len];
+    Ok(match req.shape {
+        Shape::Block => Reply::Block(&data[1..]),
+        Shape::Word => Reply::Word(u16::from_le_bytes([data[0], data[1]])),
+        _ => Reply::Byte(data[0]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc8_check_value() {
+        // The CRC-8/SMBUS check value, over the ASCII digits 1 to 9
+        assert_eq!(crc8(0, b"123456789"), 0xF4);
+        assert_eq!(crc8(0, &[]), 0);
+        // Feeding the data in parts gives the same CRC
+        assert_eq!(crc8(crc8(0, b"1234"), b"56789"), 0xF4);
+    }
+
+    #[test]
+    fn pec_covers_addresses_and_data() {
+        // Write Byte 0x55 to command 0x01 of 0x5A
+        assert_eq!(pec(0x5A, &[0x01, 0x55], &[]), crc8(0, &[0xB4, 0x01, 0x55]));
+        // Read Word of command 0x08 from 0x0B, answered with 0x1234
+        assert_eq!(
+            pec(0x0B, &[0x08], &[0x34, 0x12]),
+            crc8(0, &[0x16, 0x08, 0x17, 0x34, 0x12])
+        );
+        // Receive Byte only has the read address
+        assert_eq!(pec(0x0B, &[], &[0x42]), crc8(0, &[0x17, 0x42]));
+        assert_ne!(
+            pec(0x0B, &[0x08], &[0x34, 0x12]),
+            pec(0x0C, &[0x08], &[0x34, 0x12])
+        );
+    }
+}
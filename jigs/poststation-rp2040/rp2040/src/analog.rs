@@ -0,0 +1,126 @@
+//! ADC sampling of GP28, VSYS and the internal temperature sensor
+//!
+//! Samples can be taken one at a time over RPC, or streamed on [`AdcSampleTopic`]
+//! by [`adc_stream_task`].
+
+use embassy_futures::select::{select, Either};
+use embassy_rp::{
+    adc::{Adc, Async, Channel},
+    gpio::Pull,
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel as SyncChannel, mutex::Mutex};
+use embassy_time::{Duration, Instant, Timer};
+use picocalc_jig_icd::*;
+use postcard_rpc::{header::VarSeq, server::Sender};
+
+use crate::{app::AppTx, gpio};
+
+/// The ADC reference is the 3v3 rail
+const VREF: f32 = 3.3;
+const FULL_SCALE: f32 = 4096.0;
+/// VSYS is measured through a 3:1 divider on the Pico
+const VSYS_DIVIDER: f32 = 3.0;
+
+/// Number of entries in [`AdcChannel`]
+const NUM_CHANNELS: usize = 3;
+
+pub struct Analog {
+    pub adc: Adc<'static, Async>,
+    pub vsys: Channel<'static>,
+    pub temp: Channel<'static>,
+}
+
+pub static ANALOG: Mutex<ThreadModeRawMutex, Option<Analog>> = Mutex::new(None);
+static STREAM: SyncChannel<ThreadModeRawMutex, AdcStream, 4> = SyncChannel::new();
+
+fn volts(raw: u16) -> f32 {
+    raw as f32 * VREF / FULL_SCALE
+}
+
+impl Analog {
+    /// Take a single sample, converted to volts or degrees C
+    pub async fn sample(&mut self, channel: AdcChannel) -> AdcReadResult {
+        let res = match channel {
+            AdcChannel::Gpio28 => {
+                // Only turned into an ADC channel while sampling, see `gpio::GP28`
+                let mut gp28 = gpio::take_gp28().ok_or(AdcError::PinInUse)?;
+                let mut ch = Channel::new_pin(&mut gp28, Pull::None);
+                let res = self.adc.read(&mut ch).await.map(volts);
+                drop(ch);
+                gpio::give_gp28(gp28);
+                res
+            }
+            AdcChannel::Vsys => self.adc.read(&mut self.vsys).await.map(|raw| volts(raw) * VSYS_DIVIDER),
+            AdcChannel::Temperature => self
+                .adc
+                .read(&mut self.temp)
+                .await
+                // From the RP2040 datasheet, section 4.9.5
+                .map(|raw| 27.0 - (volts(raw) - 0.706) / 0.001721),
+        };
+        let value = res.map_err(|_| AdcError::ConversionFailed)?;
+        Ok(AdcSample {
+            channel,
+            value,
+            timestamp_us: Instant::now().as_micros(),
+        })
+    }
+}
+
+/// Take a single sample
+pub async fn sample(channel: AdcChannel) -> AdcReadResult {
+    let mut analog = ANALOG.lock().await;
+    // ANALOG is filled in by main before the server starts
+    analog.as_mut().unwrap().sample(channel).await
+}
+
+/// Update the streaming configuration of a channel
+pub async fn stream(cfg: AdcStream) {
+    STREAM.send(cfg).await;
+}
+
+fn index(channel: AdcChannel) -> usize {
+    match channel {
+        AdcChannel::Gpio28 => 0,
+        AdcChannel::Vsys => 1,
+        AdcChannel::Temperature => 2,
+    }
+}
+
+const CHANNELS: [AdcChannel; NUM_CHANNELS] = [AdcChannel::Gpio28, AdcChannel::Vsys, AdcChannel::Temperature];
+
+/// This task publishes samples for all streaming channels
+#[embassy_executor::task]
+pub async fn adc_stream_task(sender: Sender<AppTx>) {
+    let mut intervals: [Option<Duration>; NUM_CHANNELS] = [None; NUM_CHANNELS];
+    let mut deadlines = [Instant::MAX; NUM_CHANNELS];
+    let mut seq = 0u32;
+
+    loop {
+        let (idx, next) = deadlines
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by_key(|(_, at)| *at)
+            .unwrap_or((0, Instant::MAX));
+
+        match select(STREAM.receive(), Timer::at(next)).await {
+            Either::First(AdcStream { channel, interval_ms }) => {
+                let idx = index(channel);
+                intervals[idx] = interval_ms.map(|ms| Duration::from_millis(ms.max(1).into()));
+                deadlines[idx] = match intervals[idx] {
+                    Some(_) => Instant::now(),
+                    None => Instant::MAX,
+                };
+            }
+            Either::Second(()) => {
+                deadlines[idx] = intervals[idx].map_or(Instant::MAX, |ival| (next + ival).max(Instant::now()));
+                // Samples we can't take (e.g. GP28 is in use) are skipped
+                if let Ok(smp) = sample(CHANNELS[idx]).await {
+                    let _ = sender.publish::<AdcSampleTopic>(VarSeq::Seq4(seq), &smp).await;
+                    seq = seq.wrapping_add(1);
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,7 @@
 //! A basic postcard-rpc/poststation-compatible application
 
 use crate::handlers::*;
-use embassy_rp::{
-    gpio::Output,
-    i2c::{Async, I2c},
-    peripherals::{I2C1, USB},
-    usb,
-};
+use embassy_rp::{gpio::Output, peripherals::USB, usb};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use postcard_rpc::server::impls::embassy_usb_v0_4::{
     dispatch_impl::{spawn_fn, WireRxBuf, WireRxImpl, WireSpawnImpl, WireStorage, WireTxImpl},
@@ -27,8 +22,7 @@ pub struct Context {
     pub unique_id: u64,
     pub led: Output<'static>,
 
-    // Southbridge I2C connection
-    pub sb_i2c: I2c<'static, I2C1, Async>,
+    // Scratch space for replies
     pub buf: [u8; 256],
 }
 
@@ -118,9 +112,36 @@ define_dispatch! {
         | SleepEndpoint             | spawn     | sleep_handler                 |
         | SetLedEndpoint            | blocking  | set_led                       |
         | GetLedEndpoint            | blocking  | get_led                       |
-        | I2cReadEndpoint           | async     | i2c_read                      |
-        | I2cWriteEndpoint          | async     | i2c_write                     |
-        | I2cWriteReadEndpoint      | async     | i2c_write_read                |
+        | I2cReadEndpoint           | spawn     | i2c_read                      |
+        | I2cWriteEndpoint          | spawn     | i2c_write                     |
+        | I2cWriteReadEndpoint      | spawn     | i2c_write_read                |
+        | I2cWriteDelayReadEndpoint | spawn     | i2c_write_delay_read          |
+        | I2cBulkStageEndpoint      | blocking  | i2c_bulk_stage                |
+        | I2cBulkTransferEndpoint   | spawn     | i2c_bulk_transfer             |
+        | I2cBulkFetchEndpoint      | blocking  | i2c_bulk_fetch                |
+        | SmbusEndpoint             | spawn     | smbus                         |
+        | I2cScriptEndpoint         | spawn     | i2c_script                    |
+        | I2cRecoverEndpoint        | spawn     | i2c_recover                   |
+        | I2cTraceEndpoint          | blocking  | i2c_trace                     |
+        | I2cBusStatsEndpoint       | blocking  | i2c_bus_stats                 |
+        | TargetModeEndpoint        | blocking  | target_mode                   |
+        | TargetStatusEndpoint      | blocking  | target_status                 |
+        | PollRegisterEndpoint      | blocking  | poll_register                 |
+        | PollListEndpoint          | blocking  | poll_list                     |
+        | PollCancelEndpoint        | blocking  | poll_cancel                   |
+        | GpioConfigureEndpoint     | async     | gpio_configure                |
+        | GpioReadEndpoint          | async     | gpio_read                     |
+        | GpioWriteEndpoint         | async     | gpio_write                    |
+        | GpioSubscribeEndpoint     | async     | gpio_subscribe                |
+        | AdcReadEndpoint           | async     | adc_read                      |
+        | AdcStreamEndpoint         | async     | adc_stream                    |
+        | CaptureStartEndpoint      | blocking  | capture_start                 |
+        | CaptureCancelEndpoint     | blocking  | capture_cancel                |
+        | CaptureStatusEndpoint     | blocking  | capture_status                |
+        | CaptureFetchEndpoint      | blocking  | capture_fetch                 |
+        | SpiConfigureEndpoint      | async     | spi_configure                 |
+        | SpiTransferEndpoint       | async     | spi_transfer                  |
+        | ControlPinWriteEndpoint   | async     | control_pin_write             |
     };
 
     // Topics IN are messages we receive from the client, but that we do not reply
@@ -133,6 +154,7 @@ define_dispatch! {
 
         | TopicTy                   | kind      | handler                       |
         | ----------                | ----      | -------                       |
+        | TargetKeyTopic            | blocking  | target_key                    |
     };
 
     // Topics OUT are the messages we send to the client whenever we'd like. Since
@@ -0,0 +1,36 @@
+//! Southbridge transfers longer than [`I2C_CHUNK_LEN`]
+//!
+//! The host stages what to write into [`BULK`] a chunk at a time, runs the
+//! transfer, then fetches what was read the same way, all in one session.
+
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embassy_time::Instant;
+use picocalc_jig_icd::*;
+use picocalc_jig_logic::bulk::{self, Bulk};
+
+use crate::{sb_i2c, shared_bus::I2cUser};
+
+static BULK: Mutex<ThreadModeRawMutex, Bulk<[u8; I2C_BULK_LEN]>> = Mutex::new(Bulk::new([0; I2C_BULK_LEN]));
+
+fn now_ms() -> u64 {
+    Instant::now().as_millis()
+}
+
+pub fn stage(arg: BulkStage<'_>) -> BulkStageResult {
+    let mut bulk = BULK.try_lock().map_err(|_| I2cError::Busy)?;
+    bulk.stage(arg.session, arg.offset, arg.data, now_ms())
+}
+
+/// Copy part of the bulk buffer into `out`
+pub fn fetch(arg: BulkFetch, out: &mut [u8]) -> ReadResult<'_> {
+    let mut bulk = BULK.try_lock().map_err(|_| I2cError::Busy)?;
+    bulk.fetch(arg, out, now_ms()).map(|data| ReadData { data })
+}
+
+pub async fn transfer(user: I2cUser, arg: BulkTransfer) -> WriteResult {
+    let timeout_ms = arg.timeout_ms.unwrap_or_else(|| bulk::timeout_ms(&arg, sb_i2c::FREQUENCY));
+    let mut bus = user.with_timeout(Some(timeout_ms));
+
+    let mut bulk = BULK.lock().await;
+    bulk.transfer(&mut bus, arg, now_ms).await
+}
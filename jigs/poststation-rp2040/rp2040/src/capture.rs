@@ -0,0 +1,214 @@
+//! Logic analyzer capture using PIO + DMA
+//!
+//! A state machine waits for the trigger, then shifts in a window of GPIOs every
+//! clock, with DMA draining the RX FIFO into [`BUFFER`]. The sampled pins are
+//! only read, so the peripherals using them (I2C, SPI, ...) keep working.
+
+use core::cell::Cell;
+
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    peripherals::{DMA_CH0, PIO0},
+    pio::{self, Pio, PinConfig, ShiftConfig, ShiftDirection},
+    Peripheral,
+};
+use embassy_sync::{
+    blocking_mutex::{self, raw::ThreadModeRawMutex},
+    channel::Channel,
+    mutex::{Mutex, MutexGuard},
+    signal::Signal,
+};
+use fixed::{types::extra::U8, FixedU32};
+use picocalc_jig_icd::*;
+use ::pio::{Assembler, InSource, WaitSource};
+
+/// Capture buffer size, in 32-bit words (64KiB)
+pub const CAPTURE_WORDS: usize = 16 * 1024;
+/// The RP2040 has GPIO0..=GPIO29
+const NUM_GPIOS: u8 = 30;
+/// PIO IRQ flag the state machine waits on before sampling, set once the DMA
+/// is running. Flags 4-7 are local to the PIO block
+const GO_IRQ: u8 = 4;
+
+static BUFFER: Mutex<ThreadModeRawMutex, [u32; CAPTURE_WORDS]> = Mutex::new([0; CAPTURE_WORDS]);
+static STATUS: blocking_mutex::Mutex<ThreadModeRawMutex, Cell<CaptureStatus>> =
+    blocking_mutex::Mutex::new(Cell::new(CaptureStatus {
+        state: CaptureState::Idle,
+        config: None,
+        actual_rate_hz: 0,
+    }));
+static START: Channel<ThreadModeRawMutex, (CaptureConfig, FixedU32<U8>), 1> = Channel::new();
+static CANCEL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Resources used by [`capture_task`]
+pub struct Capture {
+    pub pio: Pio<'static, PIO0>,
+    pub dma: DMA_CH0,
+}
+
+/// Bytes used to store each sample
+fn sample_bytes(gpio_count: u8) -> usize {
+    match gpio_count {
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+    }
+}
+
+fn set_state(state: CaptureState) {
+    STATUS.lock(|s| s.set(CaptureStatus { state, ..s.get() }));
+}
+
+pub fn status() -> CaptureStatus {
+    STATUS.lock(|s| s.get())
+}
+
+/// Validate `cfg` and hand it to the [`capture_task`]
+pub fn start(cfg: CaptureConfig) -> CaptureStartResult {
+    let trigger_ok = match cfg.trigger {
+        CaptureTrigger::Immediate => true,
+        CaptureTrigger::High(n) | CaptureTrigger::Low(n) | CaptureTrigger::Rising(n) | CaptureTrigger::Falling(n) => {
+            n < NUM_GPIOS
+        }
+    };
+    let bytes = (cfg.samples as usize).saturating_mul(sample_bytes(cfg.gpio_count));
+    let pins_ok = (1..=32).contains(&cfg.gpio_count) && cfg.first_gpio as u32 + cfg.gpio_count as u32 <= NUM_GPIOS as u32;
+    if !trigger_ok || !pins_ok || cfg.samples == 0 || bytes > CAPTURE_WORDS * 4 || cfg.sample_rate_hz == 0 {
+        return Err(CaptureError::InvalidConfig);
+    }
+
+    // One sample per PIO clock, so the rate is set by the clock divider
+    let clk = clk_sys_freq() as u64;
+    let div_bits = (clk << 8) / cfg.sample_rate_hz as u64;
+    if !((1 << 8)..=(65536 << 8)).contains(&div_bits) {
+        return Err(CaptureError::InvalidConfig);
+    }
+    let actual_rate_hz = ((clk << 8) / div_bits) as u32;
+
+    if matches!(status().state, CaptureState::Armed | CaptureState::Running) {
+        return Err(CaptureError::Busy);
+    }
+    START
+        .try_send((cfg, FixedU32::from_bits(div_bits as u32)))
+        .map_err(|_| CaptureError::Busy)?;
+    STATUS.lock(|s| {
+        s.set(CaptureStatus {
+            state: CaptureState::Armed,
+            config: Some(cfg),
+            actual_rate_hz,
+        })
+    });
+    Ok(())
+}
+
+pub fn cancel() {
+    CANCEL.signal(());
+}
+
+/// Lock the capture buffer, if a completed capture is available
+pub fn buffer() -> Result<MutexGuard<'static, ThreadModeRawMutex, [u32; CAPTURE_WORDS]>, CaptureError> {
+    if status().state != CaptureState::Done {
+        return Err(CaptureError::NoCapture);
+    }
+    BUFFER.try_lock().map_err(|_| CaptureError::NoCapture)
+}
+
+/// Length of the completed capture, in bytes
+pub fn len() -> usize {
+    status()
+        .config
+        .map_or(0, |cfg| cfg.samples as usize * sample_bytes(cfg.gpio_count))
+}
+
+/// This task owns the PIO block and DMA channel used for captures
+#[embassy_executor::task]
+pub async fn capture_task(mut cap: Capture) {
+    loop {
+        let (cfg, divider) = START.receive().await;
+        CANCEL.reset();
+        let mut buf = BUFFER.lock().await;
+        let done = run(&mut cap, &mut buf, &cfg, divider).await;
+        set_state(if done { CaptureState::Done } else { CaptureState::Idle });
+    }
+}
+
+/// Run a single capture, returning false if it was cancelled
+async fn run(cap: &mut Capture, buf: &mut [u32; CAPTURE_WORDS], cfg: &CaptureConfig, divider: FixedU32<U8>) -> bool {
+    let bits = sample_bytes(cfg.gpio_count) as u8 * 8;
+    let per_word = 32 / bits as usize;
+    let words = (cfg.samples as usize).div_ceil(per_word);
+
+    let mut a = Assembler::<32>::new();
+    let mut wrap_target = a.label();
+    let mut wrap_source = a.label();
+    a.wait(1, WaitSource::IRQ, GO_IRQ, false);
+    match cfg.trigger {
+        CaptureTrigger::Immediate => {}
+        CaptureTrigger::High(n) => a.wait(1, WaitSource::GPIO, n, false),
+        CaptureTrigger::Low(n) => a.wait(0, WaitSource::GPIO, n, false),
+        CaptureTrigger::Rising(n) => {
+            a.wait(0, WaitSource::GPIO, n, false);
+            a.wait(1, WaitSource::GPIO, n, false);
+        }
+        CaptureTrigger::Falling(n) => {
+            a.wait(1, WaitSource::GPIO, n, false);
+            a.wait(0, WaitSource::GPIO, n, false);
+        }
+    }
+    a.irq(false, false, 0, false);
+    a.bind(&mut wrap_target);
+    a.r#in(InSource::PINS, bits);
+    a.bind(&mut wrap_source);
+    let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+    let Capture { pio, dma } = cap;
+    let loaded = pio.common.load_program(&program);
+    let mut config = pio::Config::default();
+    config.use_program(&loaded, &[]);
+    // We only read the pins, so they are not handed over to the PIO block
+    // SAFETY: `in_base` is the only field that matters for IN instructions
+    unsafe {
+        config.set_pins(PinConfig {
+            in_base: cfg.first_gpio,
+            ..config.get_pins()
+        });
+    }
+    config.clock_divider = divider;
+    config.shift_in = ShiftConfig {
+        threshold: 32,
+        direction: ShiftDirection::Right,
+        auto_fill: true,
+    };
+    config.fifo_join = pio::FifoJoin::RxOnly;
+
+    let sm = &mut pio.sm0;
+    sm.set_config(&config);
+    sm.clear_fifos();
+    sm.restart();
+
+    pio.irq_flags.clear(0);
+    pio.irq_flags.clear(GO_IRQ.into());
+    // The transfer borrows the state machine, so it is enabled first, and held
+    // at `GO_IRQ` until the DMA is there to empty the RX FIFO
+    sm.set_enable(true);
+    let mut xfer = sm.rx().dma_pull(dma.into_ref(), &mut buf[..words]);
+    pio.irq_flags.set(GO_IRQ.into());
+
+    let done = match select3(&mut xfer, pio.irq0.wait(), CANCEL.wait()).await {
+        Either3::First(()) => true,
+        Either3::Second(()) => {
+            set_state(CaptureState::Running);
+            matches!(select(&mut xfer, CANCEL.wait()).await, Either::First(()))
+        }
+        Either3::Third(()) => false,
+    };
+    drop(xfer);
+
+    let sm = &mut pio.sm0;
+    sm.set_enable(false);
+    sm.clear_fifos();
+    // SAFETY: the state machine using the program is stopped
+    unsafe { pio.common.free_instr(loaded.used_memory) };
+    done
+}
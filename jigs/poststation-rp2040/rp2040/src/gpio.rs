@@ -0,0 +1,232 @@
+//! Expansion header GPIO handling
+//!
+//! The expansion pins are owned by [`gpio_task`], which applies requests coming
+//! from the RPC handlers and publishes edges on [`GpioEdgeTopic`] while nobody
+//! is talking to it.
+//!
+//! Pins are left untouched until they are first configured, and go back to being
+//! disconnected when configured as [`GpioMode::Disabled`].
+//!
+//! GP28 is also an ADC input. It is kept in [`GP28`] while unused, and taken
+//! from there by whichever of this task and [`analog`](crate::analog) uses it.
+
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, select_array, Either};
+use embassy_rp::{
+    gpio::{AnyPin, Flex, Level, Pull},
+    peripherals::PIN_28,
+    Peripheral,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex as BlockingMutex},
+    channel::Channel,
+    mutex::Mutex,
+};
+use embassy_time::Instant;
+use picocalc_jig_icd::*;
+use postcard_rpc::{header::VarSeq, server::Sender};
+
+use crate::app::AppTx;
+
+/// Number of entries in [`ExpansionPin`]
+pub const NUM_PINS: usize = 8;
+
+/// GP28 while neither the expansion GPIOs nor the ADC are using it
+pub static GP28: BlockingMutex<ThreadModeRawMutex, RefCell<Option<PIN_28>>> = BlockingMutex::new(RefCell::new(None));
+
+/// Take GP28, if nobody else has it
+pub fn take_gp28() -> Option<PIN_28> {
+    GP28.lock(|pin| pin.borrow_mut().take())
+}
+
+/// Hand GP28 back once done with it
+pub fn give_gp28(pin: PIN_28) {
+    GP28.lock(|slot| *slot.borrow_mut() = Some(pin));
+}
+
+/// Whether GP28 is free to be taken
+pub fn gp28_free() -> bool {
+    GP28.lock(|pin| pin.borrow().is_some())
+}
+
+pub enum GpioRequest {
+    Configure(GpioConfig),
+    Read(ExpansionPin),
+    Write(GpioWrite),
+    Subscribe(GpioSubscribe),
+}
+
+pub enum GpioReply {
+    /// The request was applied
+    Done,
+    /// The level read from the pin
+    Level(GpioLevel),
+}
+
+static REQUESTS: Channel<ThreadModeRawMutex, GpioRequest, 1> = Channel::new();
+static RESPONSES: Channel<ThreadModeRawMutex, Result<GpioReply, GpioError>, 1> = Channel::new();
+/// Makes sure each requester gets the response to its own request
+static REQUESTER: Mutex<ThreadModeRawMutex, ()> = Mutex::new(());
+
+/// Send a request to the [`gpio_task`], and wait for it to be handled
+pub async fn request(req: GpioRequest) -> Result<GpioReply, GpioError> {
+    let _guard = REQUESTER.lock().await;
+    REQUESTS.send(req).await;
+    RESPONSES.receive().await
+}
+
+/// The expansion pins, in the same order as [`ExpansionPin`], except GP28 which
+/// is taken from [`GP28`] when configured
+pub struct ExpansionPins {
+    pub pins: [AnyPin; NUM_PINS - 1],
+}
+
+struct PinState {
+    flex: Option<Flex<'static>>,
+    output: bool,
+    edge: Option<GpioEdge>,
+}
+
+fn index(pin: ExpansionPin) -> usize {
+    match pin {
+        ExpansionPin::Gpio0 => 0,
+        ExpansionPin::Gpio1 => 1,
+        ExpansionPin::Gpio2 => 2,
+        ExpansionPin::Gpio3 => 3,
+        ExpansionPin::Gpio4 => 4,
+        ExpansionPin::Gpio5 => 5,
+        ExpansionPin::Gpio21 => 6,
+        ExpansionPin::Gpio28 => 7,
+    }
+}
+
+const PINS: [ExpansionPin; NUM_PINS] = [
+    ExpansionPin::Gpio0,
+    ExpansionPin::Gpio1,
+    ExpansionPin::Gpio2,
+    ExpansionPin::Gpio3,
+    ExpansionPin::Gpio4,
+    ExpansionPin::Gpio5,
+    ExpansionPin::Gpio21,
+    ExpansionPin::Gpio28,
+];
+
+fn level(pin: &Flex<'static>) -> GpioLevel {
+    match pin.get_level() {
+        Level::Low => GpioLevel::Low,
+        Level::High => GpioLevel::High,
+    }
+}
+
+fn to_level(level: GpioLevel) -> Level {
+    match level {
+        GpioLevel::Low => Level::Low,
+        GpioLevel::High => Level::High,
+    }
+}
+
+/// Wait for the requested edge, or forever if the pin has no subscription
+async fn wait_edge(state: &mut PinState) -> GpioLevel {
+    let (Some(pin), Some(edge)) = (state.flex.as_mut(), state.edge) else {
+        return core::future::pending().await;
+    };
+    match edge {
+        GpioEdge::Rising => pin.wait_for_rising_edge().await,
+        GpioEdge::Falling => pin.wait_for_falling_edge().await,
+        GpioEdge::Any => pin.wait_for_any_edge().await,
+    }
+    level(pin)
+}
+
+/// This task owns the expansion pins
+#[embassy_executor::task]
+pub async fn gpio_task(exp: ExpansionPins, sender: Sender<AppTx>) {
+    let mut state = [const { PinState { flex: None, output: false, edge: None } }; NUM_PINS];
+    let mut seq = 0u32;
+
+    loop {
+        let edges = state.each_mut().map(wait_edge);
+
+        match select(REQUESTS.receive(), select_array(edges)).await {
+            Either::First(req) => {
+                let resp = handle(&exp, &mut state, req);
+                RESPONSES.send(resp).await;
+            }
+            Either::Second((level, idx)) => {
+                let evt = GpioEdgeEvent {
+                    pin: PINS[idx],
+                    level,
+                    timestamp_us: Instant::now().as_micros(),
+                };
+                let _ = sender.publish::<GpioEdgeTopic>(VarSeq::Seq4(seq), &evt).await;
+                seq = seq.wrapping_add(1);
+            }
+        }
+    }
+}
+
+fn handle(exp: &ExpansionPins, state: &mut [PinState; NUM_PINS], req: GpioRequest) -> Result<GpioReply, GpioError> {
+    match req {
+        GpioRequest::Configure(cfg) => {
+            let idx = index(cfg.pin);
+            let st = &mut state[idx];
+            if cfg.mode == GpioMode::Disabled {
+                if st.flex.take().is_some() && cfg.pin == ExpansionPin::Gpio28 {
+                    // SAFETY: the `Flex` that owned GP28 was just dropped
+                    give_gp28(unsafe { PIN_28::steal() });
+                }
+                st.output = false;
+                st.edge = None;
+                return Ok(GpioReply::Done);
+            }
+            let pin = match &mut st.flex {
+                Some(pin) => pin,
+                None if cfg.pin == ExpansionPin::Gpio28 => {
+                    let gp28 = take_gp28().ok_or(GpioError::PinInUse)?;
+                    st.flex.insert(Flex::new(gp28))
+                }
+                // SAFETY: only one `Flex` exists per pin, as it is stored in its `PinState`
+                None => st.flex.insert(Flex::new(unsafe { exp.pins[idx].clone_unchecked() })),
+            };
+            pin.set_pull(match cfg.pull {
+                GpioPull::None => Pull::None,
+                GpioPull::Up => Pull::Up,
+                GpioPull::Down => Pull::Down,
+            });
+            match cfg.mode {
+                GpioMode::Output(lvl) => {
+                    pin.set_level(to_level(lvl));
+                    pin.set_as_output();
+                    st.output = true;
+                }
+                _ => {
+                    pin.set_as_input();
+                    st.output = false;
+                }
+            }
+            Ok(GpioReply::Done)
+        }
+        GpioRequest::Read(pin) => {
+            let pin = state[index(pin)].flex.as_ref().ok_or(GpioError::NotConfigured)?;
+            Ok(GpioReply::Level(level(pin)))
+        }
+        GpioRequest::Write(GpioWrite { pin, level }) => {
+            let st = &mut state[index(pin)];
+            let pin = st.flex.as_mut().ok_or(GpioError::NotConfigured)?;
+            if !st.output {
+                return Err(GpioError::NotAnOutput);
+            }
+            pin.set_level(to_level(level));
+            Ok(GpioReply::Done)
+        }
+        GpioRequest::Subscribe(GpioSubscribe { pin, edge }) => {
+            let st = &mut state[index(pin)];
+            if st.flex.is_none() {
+                return Err(GpioError::NotConfigured);
+            }
+            st.edge = edge;
+            Ok(GpioReply::Done)
+        }
+    }
+}
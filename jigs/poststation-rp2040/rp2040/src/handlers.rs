@@ -1,10 +1,24 @@
 use core::sync::atomic::{compiler_fence, Ordering};
 
-use embassy_time::{Instant, Timer};
+use embassy_executor::SpawnToken;
+use embassy_time::{Delay, Instant, Timer};
 use postcard_rpc::{header::VarHeader, server::Sender};
 use picocalc_jig_icd::*;
+use picocalc_jig_logic::{i2c::{self, TxData}, script::{self, Program}, smbus::{self, Reply}};
 
-use crate::app::{AppTx, Context, TaskContext};
+use crate::{
+    analog,
+    app::{AppTx, Context, TaskContext},
+    bulk,
+    capture,
+    gpio::{self, GpioReply, GpioRequest},
+    poll,
+    sb_i2c,
+    shared_bus::{self, I2cUser},
+    spi,
+    target,
+    trace,
+};
 
 /// This is an example of a BLOCKING handler.
 pub fn unique_id(context: &mut Context, _header: VarHeader, _arg: ()) -> u64 {
@@ -35,38 +49,273 @@ pub fn get_led(context: &mut Context, _header: VarHeader, _arg: ()) -> LedState
     }
 }
 
-pub async fn i2c_read(context: &mut Context, _header: VarHeader, arg: ReadCommand) -> ReadResult<'_> {
-    let len = arg.len as usize;
-    if len > context.buf.len() {
-        return Err(I2cError)
+/// The southbridge bus, as used by requests from the host
+fn rpc_bus(timeout_ms: Option<u32>) -> I2cUser {
+    I2cUser::new(I2cBusUser::Rpc).with_timeout(timeout_ms)
+}
+
+/// How many of each I2C request can be in flight at the same time. More than that
+/// are refused with `WireError::FailedToSpawn` until one of them completes
+const I2C_QUEUE_DEPTH: usize = 4;
+
+/// The I2C handlers are SPAWN handlers, so the dispatcher can keep answering other
+/// requests while a (slow) transfer is in progress
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+pub async fn i2c_read(_context: TaskContext, header: VarHeader, arg: ReadCommand, sender: Sender<AppTx>) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = i2c::read(&mut rpc_bus(arg.timeout_ms), arg.addr, arg.len, &mut buf)
+        .await
+        .map(|data| ReadData { data });
+    let _ = sender.reply::<I2cReadEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_write(_context: TaskContext, header: VarHeader, arg: WriteCommand<'_>, sender: Sender<AppTx>) -> SpawnToken<impl Sized> {
+    i2c_write_task(header, arg.addr, TxData::new(arg.data), arg.timeout_ms, sender)
+}
+
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+async fn i2c_write_task(header: VarHeader, addr: u8, data: Result<TxData, I2cError>, timeout_ms: Option<u32>, sender: Sender<AppTx>) {
+    let res = match data {
+        Ok(data) => i2c::write(&mut rpc_bus(timeout_ms), addr, data.get()).await,
+        Err(e) => Err(e),
+    };
+    let _ = sender.reply::<I2cWriteEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_write_read(_context: TaskContext, header: VarHeader, arg: WriteReadCommand<'_>, sender: Sender<AppTx>) -> SpawnToken<impl Sized> {
+    i2c_write_read_task(header, arg.addr, TxData::new(arg.tx_data), arg.rx_len, arg.timeout_ms, sender)
+}
+
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+async fn i2c_write_read_task(header: VarHeader, addr: u8, data: Result<TxData, I2cError>, rx_len: u32, timeout_ms: Option<u32>, sender: Sender<AppTx>) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = match data {
+        Ok(data) => i2c::write_read(&mut rpc_bus(timeout_ms), addr, data.get(), rx_len, &mut buf)
+            .await
+            .map(|data| ReadData { data }),
+        Err(e) => Err(e),
+    };
+    let _ = sender.reply::<I2cWriteReadEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_write_delay_read(_context: TaskContext, header: VarHeader, arg: WriteDelayReadCommand<'_>, sender: Sender<AppTx>) -> SpawnToken<impl Sized> {
+    i2c_write_delay_read_task(header, arg.addr, TxData::new(arg.tx_data), arg.delay_us, arg.rx_len, arg.timeout_ms, sender)
+}
+
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+async fn i2c_write_delay_read_task(
+    header: VarHeader,
+    addr: u8,
+    data: Result<TxData, I2cError>,
+    delay_us: u32,
+    rx_len: u32,
+    timeout_ms: Option<u32>,
+    sender: Sender<AppTx>,
+) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = match data {
+        Ok(data) => write_delay_read(rpc_bus(timeout_ms), addr, data.get(), delay_us, rx_len, &mut buf)
+            .await
+            .map(|data| ReadData { data }),
+        Err(e) => Err(e),
+    };
+    let _ = sender.reply::<I2cWriteDelayReadEndpoint>(header.seq_no, &res).await;
+}
+
+async fn write_delay_read<'a>(
+    bus: I2cUser,
+    addr: u8,
+    tx: &[u8],
+    delay_us: u32,
+    rx_len: u32,
+    buf: &'a mut [u8; I2C_CHUNK_LEN],
+) -> Result<&'a [u8], I2cError> {
+    // Hold the bus throughout, so nobody can get in between the write and the read
+    let mut bus = bus.lock().await?;
+    i2c::write_delay_read(&mut bus, &mut Delay, addr, tx, delay_us, rx_len, buf).await
+}
+
+pub fn i2c_bulk_stage(_context: &mut Context, _header: VarHeader, arg: BulkStage<'_>) -> BulkStageResult {
+    bulk::stage(arg)
+}
+
+/// Only one bulk transfer can run at a time, for the session holding the buffer
+#[embassy_executor::task]
+pub async fn i2c_bulk_transfer(_context: TaskContext, header: VarHeader, arg: BulkTransfer, sender: Sender<AppTx>) {
+    let res = bulk::transfer(rpc_bus(None), arg).await;
+    let _ = sender.reply::<I2cBulkTransferEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_bulk_fetch(context: &mut Context, _header: VarHeader, arg: BulkFetch) -> ReadResult<'_> {
+    bulk::fetch(arg, &mut context.buf)
+}
+
+pub fn smbus(_context: TaskContext, header: VarHeader, arg: SmbusCommand<'_>, sender: Sender<AppTx>) -> SpawnToken<impl Sized> {
+    smbus_task(header, arg.addr, smbus::Request::new(arg.addr, &arg.op, arg.pec), arg.timeout_ms, sender)
+}
+
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+async fn smbus_task(header: VarHeader, addr: u8, req: Result<smbus::Request, SmbusError>, timeout_ms: Option<u32>, sender: Sender<AppTx>) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let res = match req {
+        Ok(req) => run_smbus(rpc_bus(timeout_ms), addr, &req, &mut buf).await,
+        Err(e) => Err(e),
+    };
+    let _ = sender.reply::<SmbusEndpoint>(header.seq_no, &res).await;
+}
+
+async fn run_smbus<'a>(bus: I2cUser, addr: u8, req: &smbus::Request, buf: &'a mut [u8; I2C_CHUNK_LEN]) -> SmbusResult<'a> {
+    let mut bus = bus.lock().await.map_err(|e| SmbusError::I2c(e.into()))?;
+    Ok(match smbus::run(&mut bus, addr, req, buf).await? {
+        Reply::Done => SmbusResponse::Done,
+        Reply::Byte(b) => SmbusResponse::Byte(b),
+        Reply::Word(w) => SmbusResponse::Word(w),
+        Reply::Block(data) => SmbusResponse::Block(data),
+    })
+}
+
+/// Run a script against the southbridge bus, nothing else can use it in the meantime
+pub fn i2c_script(_context: TaskContext, header: VarHeader, arg: ScriptCommand<'_>, sender: Sender<AppTx>) -> SpawnToken<impl Sized> {
+    i2c_script_task(header, Program::new(arg.program), arg.timeout_ms, sender)
+}
+
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+async fn i2c_script_task(header: VarHeader, program: Result<Program, ScriptStatus>, timeout_ms: Option<u32>, sender: Sender<AppTx>) {
+    let mut buf = [0u8; I2C_CHUNK_LEN];
+    let report = match program {
+        Ok(program) => run_script(rpc_bus(timeout_ms), program.get(), &mut buf).await,
+        Err(status) => ScriptReport { status, step: 0, data: &[] },
+    };
+    let _ = sender.reply::<I2cScriptEndpoint>(header.seq_no, &report).await;
+}
+
+async fn run_script<'a>(bus: I2cUser, program: &[u8], buf: &'a mut [u8; I2C_CHUNK_LEN]) -> ScriptReport<'a> {
+    let mut bus = match bus.lock().await {
+        Ok(bus) => bus,
+        Err(e) => return ScriptReport { status: ScriptStatus::I2c(e.into()), step: 0, data: &[] },
+    };
+    let outcome = script::run(&mut bus, &mut Delay, program, buf).await;
+    ScriptReport { status: outcome.status, step: outcome.step, data: &buf[..outcome.len] }
+}
+
+/// Un-stick the southbridge bus, e.g. after a target was reset mid-transfer
+#[embassy_executor::task(pool_size = I2C_QUEUE_DEPTH)]
+pub async fn i2c_recover(_context: TaskContext, header: VarHeader, _arg: (), sender: Sender<AppTx>) {
+    let res = match rpc_bus(None).lock().await {
+        Ok(mut bus) => Ok(sb_i2c::recover(&mut bus).await),
+        Err(e) => Err(e.into()),
+    };
+    let _ = sender.reply::<I2cRecoverEndpoint>(header.seq_no, &res).await;
+}
+
+pub fn i2c_trace(_context: &mut Context, _header: VarHeader, arg: bool) {
+    trace::set_enabled(arg)
+}
+
+pub fn i2c_bus_stats(_context: &mut Context, _header: VarHeader, _arg: ()) -> I2cBusStats {
+    shared_bus::stats()
+}
+
+pub fn poll_register(_context: &mut Context, _header: VarHeader, arg: PollJob) -> PollRegisterResult {
+    poll::register(arg)
+}
+
+pub fn poll_list(_context: &mut Context, _header: VarHeader, _arg: ()) -> PollJobList {
+    poll::list()
+}
+
+pub fn poll_cancel(_context: &mut Context, _header: VarHeader, arg: u32) -> PollCancelResult {
+    poll::cancel(arg)
+}
+
+pub fn target_mode(_context: &mut Context, _header: VarHeader, arg: bool) {
+    target::set_mode(arg)
+}
+
+pub fn target_status(_context: &mut Context, _header: VarHeader, _arg: ()) -> TargetStatus {
+    target::status()
+}
+
+pub fn target_key(_context: &mut Context, _header: VarHeader, arg: TargetKey, _sender: &Sender<AppTx>) {
+    target::push_key(arg)
+}
+
+pub async fn gpio_configure(_context: &mut Context, _header: VarHeader, arg: GpioConfig) -> GpioResult {
+    gpio::request(GpioRequest::Configure(arg)).await.map(drop)
+}
+
+pub async fn gpio_read(_context: &mut Context, _header: VarHeader, arg: ExpansionPin) -> GpioReadResult {
+    match gpio::request(GpioRequest::Read(arg)).await? {
+        GpioReply::Level(level) => Ok(level),
+        GpioReply::Done => unreachable!("reads reply with the level"),
     }
-    let Context { sb_i2c, buf, .. } = context;
-    let buf = &mut buf[..len];
-    let res = sb_i2c.read_async(arg.addr, buf).await;
-    match res {
-        Ok(()) => Ok(ReadData { data: buf }),
-        Err(_e) => Err(I2cError),
+}
+
+pub async fn gpio_write(_context: &mut Context, _header: VarHeader, arg: GpioWrite) -> GpioResult {
+    gpio::request(GpioRequest::Write(arg)).await.map(drop)
+}
+
+pub async fn gpio_subscribe(_context: &mut Context, _header: VarHeader, arg: GpioSubscribe) -> GpioResult {
+    gpio::request(GpioRequest::Subscribe(arg)).await.map(drop)
+}
+
+pub async fn adc_read(_context: &mut Context, _header: VarHeader, arg: AdcChannel) -> AdcReadResult {
+    analog::sample(arg).await
+}
+
+pub async fn adc_stream(_context: &mut Context, _header: VarHeader, arg: AdcStream) -> AdcStreamResult {
+    if arg.channel == AdcChannel::Gpio28 && arg.interval_ms.is_some() && !gpio::gp28_free() {
+        return Err(AdcError::PinInUse);
     }
+    analog::stream(arg).await;
+    Ok(())
 }
 
-pub async fn i2c_write(context: &mut Context, _header: VarHeader, arg: WriteCommand<'_>) -> WriteResult {
-    context.sb_i2c.write_async(arg.addr, arg.data.iter().copied())
-        .await
-        .map_err(|_| I2cError)
+pub fn capture_start(_context: &mut Context, _header: VarHeader, arg: CaptureConfig) -> CaptureStartResult {
+    capture::start(arg)
+}
+
+pub fn capture_cancel(_context: &mut Context, _header: VarHeader, _arg: ()) {
+    capture::cancel();
 }
 
-pub async fn i2c_write_read<'a>(context: &'a mut Context, _header: VarHeader, arg: WriteReadCommand<'_>) -> ReadResult<'a> {
-    let len = arg.rx_len as usize;
-    if len > context.buf.len() {
-        return Err(I2cError)
+pub fn capture_status(_context: &mut Context, _header: VarHeader, _arg: ()) -> CaptureStatus {
+    capture::status()
+}
+
+pub fn capture_fetch(context: &mut Context, _header: VarHeader, arg: CaptureFetch) -> CaptureFetchResult<'_> {
+    let start = arg.offset as usize;
+    let len = arg.len as usize;
+    if len > context.buf.len() || start.saturating_add(len) > capture::len() {
+        return Err(CaptureError::OutOfRange);
     }
-    let Context { sb_i2c, buf, .. } = context;
-    let buf = &mut buf[..len];
-    let res = sb_i2c.write_read_async(arg.addr, arg.tx_data.iter().copied(), buf).await;
-    match res {
-        Ok(()) => Ok(ReadData { data: buf }),
-        Err(_e) => Err(I2cError),
+    let capture = capture::buffer()?;
+    let words = &capture[start / 4..];
+    let bytes = words.iter().flat_map(|w| w.to_le_bytes()).skip(start % 4).take(len);
+    let buf = &mut context.buf[..len];
+    buf.iter_mut().zip(bytes).for_each(|(b, c)| *b = c);
+    Ok(ReadData { data: buf })
+}
+
+pub async fn spi_configure(_context: &mut Context, _header: VarHeader, arg: SpiConfig) -> SpiConfigResult {
+    // SPI is filled in by main before the server starts
+    spi::SPI.lock().await.as_mut().unwrap().configure(arg)
+}
+
+pub async fn spi_transfer<'a>(context: &'a mut Context, _header: VarHeader, arg: SpiTransfer<'_>) -> SpiTransferResult<'a> {
+    let len = arg.tx.len().max(arg.rx_len as usize);
+    if len > SPI_CHUNK_LEN {
+        return Err(SpiError::TooLong);
     }
+    let buf = &mut context.buf[..len];
+    buf[..arg.tx.len()].copy_from_slice(arg.tx);
+    buf[arg.tx.len()..].fill(0);
+    spi::SPI.lock().await.as_mut().unwrap().transfer(arg.port, buf).await;
+    Ok(ReadData { data: &buf[..arg.rx_len as usize] })
+}
+
+pub async fn control_pin_write(_context: &mut Context, _header: VarHeader, arg: ControlPinWrite) {
+    spi::SPI.lock().await.as_mut().unwrap().write_pin(arg);
 }
 
 /// This is a SPAWN handler
@@ -4,7 +4,7 @@
 use app::AppTx;
 use defmt::info;
 use embassy_executor::Spawner;
-use embassy_rp::{bind_interrupts, gpio::{Level, Output}, i2c::{self, I2c}, peripherals::{USB, I2C1}, usb};
+use embassy_rp::{adc::{self, Adc}, bind_interrupts, gpio::{Level, Output, Pin, Pull}, i2c, peripherals::{USB, I2C1, PIO0}, pio::{self, Pio}, spi::{self as rp_spi, Spi}, usb};
 use embassy_time::{Duration, Instant, Ticker};
 use embassy_usb::{Config, UsbDevice};
 use postcard_rpc::{sender_fmt, server::{Dispatch, Sender, Server}};
@@ -13,12 +13,25 @@ use static_cell::StaticCell;
 bind_interrupts!(pub struct Irqs {
     USBCTRL_IRQ => usb::InterruptHandler<USB>;
     I2C1_IRQ => i2c::InterruptHandler<I2C1>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
+    PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
 });
 
 use {defmt_rtt as _, panic_probe as _};
 
+pub mod analog;
 pub mod app;
+pub mod bulk;
+pub mod capture;
+pub mod gpio;
 pub mod handlers;
+pub mod poll;
+pub mod sb_i2c;
+pub mod shared_bus;
+pub mod smbus;
+pub mod spi;
+pub mod target;
+pub mod trace;
 
 
 fn usb_config(serial: &'static str) -> Config<'static> {
@@ -79,18 +92,53 @@ async fn main(spawner: Spawner) {
 
     // SOUTHBRIDGE I2C
     // ...
-    let mut cfg = i2c::Config::default();
-    cfg.frequency = 10_000; // The docs say this (slow) speed is important
-    let sb_i2c = I2c::new_async(p.I2C1, p.PIN_7, p.PIN_6, Irqs, cfg);
+    *shared_bus::BUS.lock().await = Some(sb_i2c::new(p.I2C1, p.PIN_7, p.PIN_6));
+
+    // LCD and SDCARD
+    let spi_ports = spi::SpiPorts {
+        spi0: Spi::new(p.SPI0, p.PIN_18, p.PIN_19, p.PIN_16, p.DMA_CH1, p.DMA_CH2, rp_spi::Config::default()),
+        spi1: Spi::new(p.SPI1, p.PIN_10, p.PIN_11, p.PIN_12, p.DMA_CH3, p.DMA_CH4, rp_spi::Config::default()),
+        // In the same order as `ControlPin`, all inactive
+        pins: [
+            Output::new(p.PIN_13, Level::High),
+            Output::new(p.PIN_14, Level::High),
+            Output::new(p.PIN_15, Level::High),
+            Output::new(p.PIN_17, Level::High),
+        ],
+    };
+    *spi::SPI.lock().await = Some(spi_ports);
 
-    // LCD
+    // SOUND
     // ...
 
-    // SDCARD
-    // ...
+    // EXPANSION HEADERS (J702/J703)
+    let exp_pins = gpio::ExpansionPins {
+        pins: [
+            p.PIN_0.degrade(),
+            p.PIN_1.degrade(),
+            p.PIN_2.degrade(),
+            p.PIN_3.degrade(),
+            p.PIN_4.degrade(),
+            p.PIN_5.degrade(),
+            p.PIN_21.degrade(),
+        ],
+    };
+    // GP28 is shared with the ADC, see `gpio::GP28`
+    gpio::give_gp28(p.PIN_28);
+
+    // ADC
+    let adc_inputs = analog::Analog {
+        adc: Adc::new(p.ADC, Irqs, adc::Config::default()),
+        vsys: adc::Channel::new_pin(p.PIN_29, Pull::None),
+        temp: adc::Channel::new_temp_sensor(p.ADC_TEMP_SENSOR),
+    };
+    *analog::ANALOG.lock().await = Some(adc_inputs);
 
-    // SOUND
-    // ...
+    // LOGIC ANALYZER
+    let capture = capture::Capture {
+        pio: Pio::new(p.PIO0, Irqs),
+        dma: p.DMA_CH0,
+    };
 
 
     // USB/RPC INIT
@@ -99,7 +147,7 @@ async fn main(spawner: Spawner) {
     let config = usb_config(ser_buf);
     let led = Output::new(p.PIN_25, Level::Low);
 
-    let context = app::Context { unique_id, led, sb_i2c, buf: [0u8; 256] };
+    let context = app::Context { unique_id, led, buf: [0u8; 256] };
 
     let (device, tx_impl, rx_impl) = app::STORAGE.init_poststation(driver, config, pbufs.tx_buf.as_mut_slice());
     let dispatcher = app::MyApp::new(context, spawner.into());
@@ -115,7 +163,13 @@ async fn main(spawner: Spawner) {
     // We need to spawn the USB task so that USB messages are handled by
     // embassy-usb
     spawner.must_spawn(usb_task(device));
-    spawner.must_spawn(logging_task(sender));
+    spawner.must_spawn(logging_task(sender.clone()));
+    spawner.must_spawn(gpio::gpio_task(exp_pins, sender.clone()));
+    spawner.must_spawn(analog::adc_stream_task(sender.clone()));
+    spawner.must_spawn(poll::poll_task(sender.clone()));
+    spawner.must_spawn(trace::trace_task(sender));
+    spawner.must_spawn(capture::capture_task(capture));
+    spawner.must_spawn(target::target_task());
 
     // Begin running!
     loop {
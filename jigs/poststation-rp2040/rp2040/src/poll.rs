@@ -0,0 +1,105 @@
+//! Periodic southbridge reads registered by the host
+//!
+//! Each job does a write-read on the southbridge bus at its own interval, and
+//! [`poll_task`] publishes what it read on [`PollResultTopic`], tagged with the
+//! job's ID.
+
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{
+    blocking_mutex::{self, raw::ThreadModeRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+use picocalc_jig_icd::*;
+use picocalc_jig_logic::poll::Jobs;
+use postcard_rpc::{header::VarSeq, server::Sender};
+
+use crate::{
+    app::AppTx,
+    shared_bus::{BusError, I2cUser},
+};
+
+static JOBS: blocking_mutex::Mutex<ThreadModeRawMutex, RefCell<Jobs>> = blocking_mutex::Mutex::new(RefCell::new(Jobs::new()));
+/// Wakes the [`poll_task`] when jobs are registered or cancelled
+static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+pub fn register(job: PollJob) -> PollRegisterResult {
+    let id = JOBS.lock(|jobs| jobs.borrow_mut().register(job))?;
+    CHANGED.signal(());
+    Ok(id)
+}
+
+pub fn list() -> PollJobList {
+    JOBS.lock(|jobs| jobs.borrow().list())
+}
+
+pub fn cancel(id: u32) -> PollCancelResult {
+    JOBS.lock(|jobs| jobs.borrow_mut().cancel(id))?;
+    CHANGED.signal(());
+    Ok(())
+}
+
+/// A job as seen by the [`poll_task`]
+#[derive(Clone, Copy)]
+struct Running {
+    info: PollJobInfo,
+    next: Instant,
+    /// The last result published, for `only_on_change`
+    last: Option<Result<[u8; MAX_POLL_RX], I2cError>>,
+}
+
+/// This task runs the registered jobs, sharing the bus with the RPC handlers
+#[embassy_executor::task]
+pub async fn poll_task(sender: Sender<AppTx>) {
+    let mut running: [Option<Running>; MAX_POLL_JOBS] = [None; MAX_POLL_JOBS];
+    let mut seq = 0u32;
+
+    loop {
+        // Pick up jobs registered or cancelled since last time, new jobs run right away
+        for (run, info) in running.iter_mut().zip(list()) {
+            if run.map(|r| r.info.id) != info.map(|i| i.id) {
+                *run = info.map(|info| Running { info, next: Instant::now(), last: None });
+            }
+        }
+        let next = running.iter().flatten().map(|r| r.next).min().unwrap_or(Instant::MAX);
+        if let Either::Second(()) = select(Timer::at(next), CHANGED.wait()).await {
+            continue;
+        }
+
+        let now = Instant::now();
+        for run in running.iter_mut().flatten().filter(|r| r.next <= now) {
+            let PollJobInfo { id, job } = run.info;
+            run.next = (run.next + Duration::from_millis(job.interval_ms.into())).max(now);
+
+            let rx_len = job.rx_len as usize;
+            let mut buf = [0u8; MAX_POLL_RX];
+            let res = transfer(&job, &mut buf[..rx_len]).await.map(|()| buf).map_err(I2cError::from);
+            if job.only_on_change && run.last == Some(res) {
+                continue;
+            }
+            run.last = Some(res);
+
+            let msg = PollResult {
+                id,
+                timestamp_us: Instant::now().as_micros(),
+                data: res.as_ref().map(|buf| ReadData { data: &buf[..rx_len] }).map_err(|e| *e),
+            };
+            let _ = sender.publish::<PollResultTopic>(VarSeq::Seq4(seq), &msg).await;
+            seq = seq.wrapping_add(1);
+        }
+    }
+}
+
+async fn transfer(job: &PollJob, rx: &mut [u8]) -> Result<(), BusError> {
+    let mut bus = I2cUser::new(I2cBusUser::Poll).lock().await?;
+    let tx = &job.tx_data[..job.tx_len as usize];
+    if job.delay_us == 0 {
+        return bus.write_read(job.addr, tx, rx).await;
+    }
+    bus.write(job.addr, tx).await?;
+    Timer::after_micros(job.delay_us.into()).await;
+    bus.read(job.addr, rx).await
+}
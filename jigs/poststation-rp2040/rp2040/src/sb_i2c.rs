@@ -0,0 +1,182 @@
+//! Southbridge I2C bus helpers
+//!
+//! The southbridge sits on I2C1, with SCL on GPIO7 and SDA on GPIO6.
+
+use embassy_rp::{
+    gpio::{Flex, Level, Pull},
+    i2c::{self, Async, I2c},
+    peripherals::{I2C1, PIN_6, PIN_7},
+};
+use embassy_time::{Duration, TimeoutError, Timer};
+use picocalc_jig_icd::{I2cError, I2cRecovery, DEFAULT_I2C_TIMEOUT_MS};
+
+use crate::Irqs;
+
+pub type SbI2c = I2c<'static, I2C1, Async>;
+
+/// The docs say this (slow) speed is important
+pub const FREQUENCY: u32 = 10_000;
+/// Half of a bit period at [`FREQUENCY`], in microseconds
+const HALF_BIT_US: u64 = 1_000_000 / FREQUENCY as u64 / 2;
+
+/// Create the southbridge I2C driver
+pub fn new(i2c: I2C1, scl: PIN_7, sda: PIN_6) -> SbI2c {
+    let mut cfg = i2c::Config::default();
+    cfg.frequency = FREQUENCY;
+    I2c::new_async(i2c, scl, sda, Irqs, cfg)
+}
+
+/// Re-create the southbridge I2C driver, resetting the peripheral
+///
+/// # Safety
+///
+/// The previous driver must not be used afterwards, it should be replaced by
+/// the returned one.
+pub unsafe fn recreate() -> SbI2c {
+    new(I2C1::steal(), PIN_7::steal(), PIN_6::steal())
+}
+
+/// How long a transfer may take, to be used with [`embassy_time::with_timeout`]
+pub fn deadline(timeout_ms: Option<u32>) -> Duration {
+    Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_I2C_TIMEOUT_MS).into())
+}
+
+/// Map the outcome of a transfer run with a [`deadline`], resetting the
+/// peripheral if the transfer timed out
+pub fn check<T>(bus: &mut SbI2c, res: Result<Result<T, i2c::Error>, TimeoutError>) -> Result<T, I2cError> {
+    match res {
+        Ok(Ok(t)) => Ok(t),
+        Ok(Err(_e)) => Err(I2cError::Bus),
+        Err(TimeoutError) => {
+            // SAFETY: the old driver is dropped by this assignment
+            *bus = unsafe { recreate() };
+            Err(I2cError::Timeout)
+        }
+    }
+}
+
+/// Open-drain bit-banging of the southbridge bus, used while the I2C peripheral
+/// can't help us
+pub struct BitBang {
+    scl: Flex<'static>,
+    sda: Flex<'static>,
+}
+
+impl BitBang {
+    /// Take over the bus pins from the I2C peripheral, releasing both lines
+    ///
+    /// # Safety
+    ///
+    /// The I2C driver must not be used until it has been re-created with
+    /// [`recreate`], after this has been dropped.
+    pub unsafe fn new() -> Self {
+        let mut scl = Flex::new(PIN_7::steal());
+        let mut sda = Flex::new(PIN_6::steal());
+        for pin in [&mut scl, &mut sda] {
+            pin.set_pull(Pull::Up);
+            pin.set_low();
+            pin.set_as_input();
+        }
+        Self { scl, sda }
+    }
+
+    async fn half_bit(&self) {
+        Timer::after_micros(HALF_BIT_US).await;
+    }
+
+    fn set(pin: &mut Flex<'static>, level: Level) {
+        match level {
+            // Let the pull-ups do the work
+            Level::High => pin.set_as_input(),
+            Level::Low => pin.set_as_output(),
+        }
+    }
+
+    pub fn sda_is_high(&self) -> bool {
+        self.sda.is_high()
+    }
+
+    pub fn scl_is_high(&self) -> bool {
+        self.scl.is_high()
+    }
+
+    /// Pulse SCL once, leaving it high
+    pub async fn clock(&mut self) {
+        Self::set(&mut self.scl, Level::Low);
+        self.half_bit().await;
+        Self::set(&mut self.scl, Level::High);
+        self.half_bit().await;
+    }
+
+    /// Generate a START condition from an idle bus, leaving SCL low
+    pub async fn start(&mut self) {
+        self.half_bit().await;
+        Self::set(&mut self.sda, Level::Low);
+        self.half_bit().await;
+        Self::set(&mut self.scl, Level::Low);
+        self.half_bit().await;
+    }
+
+    /// Shift out `byte` after a [`start`](Self::start), returning whether it was
+    /// ACKed. Leaves SCL low
+    pub async fn write_byte(&mut self, byte: u8) -> bool {
+        for bit in (0..8).rev() {
+            let level = if byte & (1 << bit) != 0 { Level::High } else { Level::Low };
+            Self::set(&mut self.sda, level);
+            self.half_bit().await;
+            Self::set(&mut self.scl, Level::High);
+            self.half_bit().await;
+            Self::set(&mut self.scl, Level::Low);
+        }
+        // The target pulls SDA low during the ninth clock to ACK
+        Self::set(&mut self.sda, Level::High);
+        self.half_bit().await;
+        Self::set(&mut self.scl, Level::High);
+        self.half_bit().await;
+        let ack = !self.sda_is_high();
+        Self::set(&mut self.scl, Level::Low);
+        ack
+    }
+
+    /// Generate a STOP condition, leaving both lines released
+    pub async fn stop(&mut self) {
+        Self::set(&mut self.scl, Level::Low);
+        Self::set(&mut self.sda, Level::Low);
+        self.half_bit().await;
+        Self::set(&mut self.scl, Level::High);
+        self.half_bit().await;
+        Self::set(&mut self.sda, Level::High);
+        self.half_bit().await;
+    }
+}
+
+/// Recover a bus where a target is holding SDA low, and re-create the driver
+///
+/// Clocks SCL until SDA is released (at most nine times, enough to finish any
+/// byte and its ACK), then generates a STOP.
+pub async fn recover(bus: &mut SbI2c) -> I2cRecovery {
+    // SAFETY: the driver is replaced before anyone can use it again
+    let mut bb = unsafe { BitBang::new() };
+    bb.half_bit().await;
+
+    let sda_was_stuck = !bb.sda_is_high();
+    let scl_was_stuck = !bb.scl_is_high();
+    let mut clocks = 0;
+    while !bb.sda_is_high() && clocks < 9 {
+        bb.clock().await;
+        clocks += 1;
+    }
+    bb.stop().await;
+    let sda_released = bb.sda_is_high();
+    drop(bb);
+
+    // SAFETY: the old driver is dropped by this assignment
+    *bus = unsafe { recreate() };
+
+    I2cRecovery {
+        sda_was_stuck,
+        scl_was_stuck,
+        clocks,
+        sda_released,
+    }
+}
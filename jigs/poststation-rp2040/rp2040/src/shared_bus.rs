@@ -0,0 +1,284 @@
+//! The southbridge bus, shared between the RPC handlers and on-device tasks
+//!
+//! Each [`I2cBusUser`] gets its own [`I2cUser`] handle implementing
+//! [`embedded_hal_async::i2c::I2c`]. When several users are waiting, the bus is
+//! handed out round-robin so a busy user can't starve the others, and each
+//! user's activity is counted in [`I2cUserStats`].
+//!
+//! While [`I2cBusUser::Target`] holds the bus, everyone else is turned away
+//! with [`I2cError::TargetMode`] rather than left waiting.
+
+use core::{
+    cell::RefCell,
+    future::poll_fn,
+    ops::{Deref, DerefMut},
+    task::Poll,
+};
+
+use embassy_rp::i2c;
+use embassy_sync::{
+    blocking_mutex::{self, raw::ThreadModeRawMutex},
+    mutex::{MappedMutexGuard, Mutex, MutexGuard},
+    waitqueue::MultiWakerRegistration,
+};
+use embassy_time::{with_timeout, Instant, TimeoutError};
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, Operation};
+use picocalc_jig_icd::*;
+
+use crate::{
+    sb_i2c::{self, SbI2c},
+    trace,
+};
+
+/// The bus itself, set up in `main`. Only the current owner may lock it
+pub static BUS: Mutex<ThreadModeRawMutex, Option<SbI2c>> = Mutex::new(None);
+
+static ARBITER: blocking_mutex::Mutex<ThreadModeRawMutex, RefCell<Arbiter>> =
+    blocking_mutex::Mutex::new(RefCell::new(Arbiter::new()));
+
+/// Every [`I2cBusUser`], in the order used for [`I2cBusStats`]
+const USERS: [I2cBusUser; I2C_BUS_USERS] = [I2cBusUser::Rpc, I2cBusUser::Poll, I2cBusUser::Target];
+
+fn index(user: I2cBusUser) -> usize {
+    match user {
+        I2cBusUser::Rpc => 0,
+        I2cBusUser::Poll => 1,
+        I2cBusUser::Target => 2,
+    }
+}
+
+struct Arbiter {
+    /// Index of the user holding the bus
+    owner: Option<usize>,
+    /// The owner keeps the bus to itself, nobody else may wait for it
+    exclusive: bool,
+    /// Index of the previous owner, the next one is picked round-robin from here
+    last: usize,
+    /// Number of waiters for each user
+    waiting: [u8; I2C_BUS_USERS],
+    wakers: MultiWakerRegistration<4>,
+    stats: I2cBusStats,
+}
+
+impl Arbiter {
+    const fn new() -> Self {
+        let mut stats = [I2cUserStats {
+            user: I2cBusUser::Rpc,
+            locks: 0,
+            transfers: 0,
+            errors: 0,
+            timeouts: 0,
+            wait_us: 0,
+            max_wait_us: 0,
+            hold_us: 0,
+        }; I2C_BUS_USERS];
+        let mut i = 0;
+        while i < I2C_BUS_USERS {
+            stats[i].user = USERS[i];
+            i += 1;
+        }
+        Self { owner: None, exclusive: false, last: 0, waiting: [0; I2C_BUS_USERS], wakers: MultiWakerRegistration::new(), stats }
+    }
+
+    /// The waiting user that should get the bus next
+    fn next(&self) -> Option<usize> {
+        (1..=I2C_BUS_USERS)
+            .map(|i| (self.last + i) % I2C_BUS_USERS)
+            .find(|&user| self.waiting[user] != 0)
+    }
+}
+
+fn with_arbiter<R>(f: impl FnOnce(&mut Arbiter) -> R) -> R {
+    ARBITER.lock(|arb| f(&mut arb.borrow_mut()))
+}
+
+pub fn stats() -> I2cBusStats {
+    with_arbiter(|arb| arb.stats)
+}
+
+/// Stops waiting when dropped, whether or not the bus was acquired
+struct Waiter(usize);
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        with_arbiter(|arb| {
+            arb.waiting[self.0] -= 1;
+            arb.wakers.wake();
+        });
+    }
+}
+
+/// Releases the bus when dropped
+struct Owner {
+    user: usize,
+    since: Instant,
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        with_arbiter(|arb| {
+            arb.owner = None;
+            arb.exclusive = false;
+            arb.last = self.user;
+            arb.stats[self.user].hold_us += self.since.elapsed().as_micros();
+            arb.wakers.wake();
+        });
+    }
+}
+
+async fn acquire(user: usize, exclusive: bool) -> Result<Owner, BusError> {
+    let start = Instant::now();
+    with_arbiter(|arb| arb.waiting[user] += 1);
+    let waiter = Waiter(user);
+    poll_fn(|cx| {
+        with_arbiter(|arb| {
+            if arb.exclusive {
+                Poll::Ready(Err(BusError(I2cError::TargetMode)))
+            } else if arb.owner.is_none() && arb.next() == Some(user) {
+                arb.owner = Some(user);
+                arb.exclusive = exclusive;
+                Poll::Ready(Ok(()))
+            } else {
+                arb.wakers.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    })
+    .await?;
+    drop(waiter);
+
+    let wait_us = start.elapsed().as_micros();
+    with_arbiter(|arb| {
+        let stats = &mut arb.stats[user];
+        stats.locks += 1;
+        stats.wait_us += wait_us;
+        stats.max_wait_us = stats.max_wait_us.max(wait_us);
+    });
+    Ok(Owner { user, since: Instant::now() })
+}
+
+/// [`I2cError`], for [`embedded_hal_async::i2c`] users
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusError(pub I2cError);
+
+impl Error for BusError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl From<BusError> for I2cError {
+    fn from(value: BusError) -> Self {
+        value.0
+    }
+}
+
+/// A handle to the southbridge bus for one [`I2cBusUser`]
+///
+/// Each transaction takes the bus on its own, use [`I2cUser::lock`] to keep it
+/// across several of them.
+#[derive(Clone, Copy)]
+pub struct I2cUser {
+    user: I2cBusUser,
+    timeout_ms: Option<u32>,
+}
+
+impl I2cUser {
+    pub const fn new(user: I2cBusUser) -> Self {
+        Self { user, timeout_ms: None }
+    }
+
+    /// Limit each transfer to `timeout_ms`, or [`DEFAULT_I2C_TIMEOUT_MS`] if `None`
+    pub const fn with_timeout(self, timeout_ms: Option<u32>) -> Self {
+        Self { timeout_ms, ..self }
+    }
+
+    /// Wait for our turn on the bus, keeping it until the guard is dropped
+    pub async fn lock(&self) -> Result<BusGuard, BusError> {
+        self.lock_inner(false).await
+    }
+
+    /// Like [`I2cUser::lock`], but anyone else trying to use the bus fails
+    /// until the guard is dropped
+    pub async fn lock_exclusive(&self) -> Result<BusGuard, BusError> {
+        self.lock_inner(true).await
+    }
+
+    async fn lock_inner(&self, exclusive: bool) -> Result<BusGuard, BusError> {
+        let owner = acquire(index(self.user), exclusive).await?;
+        // Nobody else can hold the mutex while we own the bus
+        let bus = MutexGuard::map(BUS.lock().await, |bus| bus.as_mut().unwrap());
+        Ok(BusGuard { bus, timeout_ms: self.timeout_ms, owner })
+    }
+}
+
+impl ErrorType for I2cUser {
+    type Error = BusError;
+}
+
+impl I2c for I2cUser {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        self.lock().await?.transaction(address, operations).await
+    }
+}
+
+/// Exclusive use of the southbridge bus, see [`I2cUser::lock`]
+pub struct BusGuard {
+    // Dropped before `owner`, so the next user finds the mutex free
+    bus: MappedMutexGuard<'static, ThreadModeRawMutex, SbI2c>,
+    timeout_ms: Option<u32>,
+    owner: Owner,
+}
+
+impl BusGuard {
+    pub fn user(&self) -> I2cBusUser {
+        USERS[self.owner.user]
+    }
+
+    /// Map the outcome of a transfer, counting it in the owner's statistics
+    fn check<T>(&mut self, res: Result<Result<T, i2c::Error>, TimeoutError>) -> Result<T, BusError> {
+        let res = sb_i2c::check(&mut self.bus, res);
+        with_arbiter(|arb| {
+            let stats = &mut arb.stats[self.owner.user];
+            stats.transfers += 1;
+            match res {
+                Ok(_) => {}
+                Err(I2cError::Timeout) => {
+                    stats.errors += 1;
+                    stats.timeouts += 1;
+                }
+                Err(_) => stats.errors += 1,
+            }
+        });
+        res.map_err(BusError)
+    }
+}
+
+impl Deref for BusGuard {
+    type Target = SbI2c;
+
+    fn deref(&self) -> &SbI2c {
+        &self.bus
+    }
+}
+
+impl DerefMut for BusGuard {
+    fn deref_mut(&mut self) -> &mut SbI2c {
+        &mut self.bus
+    }
+}
+
+impl ErrorType for BusGuard {
+    type Error = BusError;
+}
+
+impl I2c for BusGuard {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let deadline = sb_i2c::deadline(self.timeout_ms);
+        let start = Instant::now();
+        let res = with_timeout(deadline, I2c::transaction(&mut *self.bus, address, operations)).await;
+        let res = self.check(res);
+        trace::record(self.user(), address, operations, res.map_err(I2cError::from), start);
+        res
+    }
+}
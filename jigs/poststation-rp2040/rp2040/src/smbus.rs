@@ -0,0 +1,40 @@
+//! SMBus quick commands on the southbridge bus
+//!
+//! The rest of SMBus is plain I2C, see [`picocalc_jig_logic::smbus`].
+
+use embassy_time::Instant;
+use embedded_hal_async::i2c::Operation;
+use picocalc_jig_icd::I2cError;
+use picocalc_jig_logic::smbus::QuickCommand;
+
+use crate::{
+    sb_i2c::{self, BitBang},
+    shared_bus::BusGuard,
+    trace,
+};
+
+/// Address-only transfers, which the I2C peripheral can't make
+impl QuickCommand for BusGuard {
+    async fn quick(&mut self, addr: u8, read: bool) -> Result<(), I2cError> {
+        let start = Instant::now();
+        // SAFETY: the driver is replaced before anyone can use it again
+        let mut bb = unsafe { BitBang::new() };
+        bb.start().await;
+        let ack = bb.write_byte((addr << 1) | read as u8).await;
+        bb.stop().await;
+        drop(bb);
+
+        // SAFETY: the old driver is dropped by this assignment
+        **self = unsafe { sb_i2c::recreate() };
+        let res = match ack {
+            true => Ok(()),
+            false => Err(I2cError::Bus),
+        };
+        let op = match read {
+            true => Operation::Read(&mut []),
+            false => Operation::Write(&[]),
+        };
+        trace::record(self.user(), addr, &[op], res, start);
+        res
+    }
+}
@@ -0,0 +1,73 @@
+//! The LCD and SD card SPI buses, and the GPIOs that go with them
+//!
+//! The host runs its own LCD and SD card drivers, toggling the chip selects and
+//! the LCD's DC and RST lines over RPC around each transfer.
+
+use embassy_rp::{
+    clocks::clk_peri_freq,
+    gpio::Output,
+    peripherals::{SPI0, SPI1},
+    spi::{self, Async, Phase, Polarity},
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use picocalc_jig_icd::*;
+
+/// Lowest frequency the clock divider can make, with margin
+const MIN_FREQUENCY_HZ: u32 = 4_000;
+
+pub struct SpiPorts {
+    pub spi0: spi::Spi<'static, SPI0, Async>,
+    pub spi1: spi::Spi<'static, SPI1, Async>,
+    /// In the same order as [`ControlPin`]
+    pub pins: [Output<'static>; 4],
+}
+
+pub static SPI: Mutex<ThreadModeRawMutex, Option<SpiPorts>> = Mutex::new(None);
+
+fn index(pin: ControlPin) -> usize {
+    match pin {
+        ControlPin::LcdCs => 0,
+        ControlPin::LcdDc => 1,
+        ControlPin::LcdRst => 2,
+        ControlPin::SdCs => 3,
+    }
+}
+
+impl SpiPorts {
+    pub fn configure(&mut self, cfg: SpiConfig) -> SpiConfigResult {
+        // The controller can't go faster than half the peripheral clock
+        if !(MIN_FREQUENCY_HZ..=clk_peri_freq() / 2).contains(&cfg.frequency_hz) {
+            return Err(SpiError::InvalidConfig);
+        }
+        let mut config = spi::Config::default();
+        config.frequency = cfg.frequency_hz;
+        (config.polarity, config.phase) = match cfg.mode {
+            SpiMode::Mode0 => (Polarity::IdleLow, Phase::CaptureOnFirstTransition),
+            SpiMode::Mode1 => (Polarity::IdleLow, Phase::CaptureOnSecondTransition),
+            SpiMode::Mode2 => (Polarity::IdleHigh, Phase::CaptureOnFirstTransition),
+            SpiMode::Mode3 => (Polarity::IdleHigh, Phase::CaptureOnSecondTransition),
+        };
+        match cfg.port {
+            SpiPort::Spi0 => self.spi0.set_config(&config),
+            SpiPort::Spi1 => self.spi1.set_config(&config),
+        }
+        Ok(())
+    }
+
+    /// Clock out `buf` and read into it at the same time
+    pub async fn transfer(&mut self, port: SpiPort, buf: &mut [u8]) {
+        // The driver has no errors to report
+        let _ = match port {
+            SpiPort::Spi0 => self.spi0.transfer_in_place(buf).await,
+            SpiPort::Spi1 => self.spi1.transfer_in_place(buf).await,
+        };
+    }
+
+    pub fn write_pin(&mut self, ControlPinWrite { pin, level }: ControlPinWrite) {
+        let pin = &mut self.pins[index(pin)];
+        match level {
+            GpioLevel::Low => pin.set_low(),
+            GpioLevel::High => pin.set_high(),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Target mode, where the jig impersonates the southbridge
+//!
+//! While enabled, [`target_task`] holds the southbridge bus exclusively and
+//! answers at [`SB_I2C_ADDR`] with the I2C peripheral in target mode, serving
+//! key events queued by the host on [`TargetKeyTopic`].
+
+use core::cell::RefCell;
+
+use embassy_futures::select::select;
+use embassy_rp::{
+    i2c_slave::{self, Command, I2cSlave},
+    peripherals::{I2C1, PIN_6, PIN_7},
+};
+use embassy_sync::{
+    blocking_mutex::{self, raw::ThreadModeRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use picocalc_jig_icd::*;
+
+use crate::{sb_i2c, shared_bus::I2cUser, Irqs};
+
+/// Reported in `REG_ID_VER`, telling the jig apart from a real southbridge
+const VERSION: u8 = 0xA0;
+
+static KEYS: Channel<ThreadModeRawMutex, TargetKey, TARGET_KEY_FIFO_LEN> = Channel::new();
+/// Wakes the [`target_task`] when target mode is switched on or off
+static MODE: Signal<ThreadModeRawMutex, bool> = Signal::new();
+
+struct State {
+    enabled: bool,
+    overflows: u32,
+    fifo_reads: u32,
+}
+
+static STATE: blocking_mutex::Mutex<ThreadModeRawMutex, RefCell<State>> =
+    blocking_mutex::Mutex::new(RefCell::new(State { enabled: false, overflows: 0, fifo_reads: 0 }));
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    STATE.lock(|state| f(&mut state.borrow_mut()))
+}
+
+pub fn set_mode(enabled: bool) {
+    with_state(|state| state.enabled = enabled);
+    MODE.signal(enabled);
+}
+
+pub fn status() -> TargetStatus {
+    with_state(|state| TargetStatus {
+        enabled: state.enabled,
+        queued: KEYS.len() as u8,
+        overflows: state.overflows,
+        fifo_reads: state.fifo_reads,
+    })
+}
+
+/// Queue a key event, dropping it if the FIFO is full like the southbridge does
+pub fn push_key(key: TargetKey) {
+    if KEYS.try_send(key).is_err() {
+        with_state(|state| state.overflows = state.overflows.wrapping_add(1));
+    }
+}
+
+/// The southbridge's registers, as seen by the firmware under test
+struct Registers {
+    values: [u8; SB_REGISTERS],
+    /// Register selected by the last write
+    selected: u8,
+}
+
+impl Registers {
+    fn new() -> Self {
+        let mut values = [0; SB_REGISTERS];
+        values[REG_ID_VER as usize] = VERSION;
+        Self { values, selected: 0 }
+    }
+
+    /// Handle a write, selecting a register and setting it if asked to
+    fn write(&mut self, data: &[u8]) {
+        let Some((&reg, rest)) = data.split_first() else {
+            return;
+        };
+        self.selected = reg & !REG_WRITE_MASK;
+        if let (true, Some(&value)) = (reg & REG_WRITE_MASK != 0, rest.first()) {
+            if let Some(slot) = self.values.get_mut(self.selected as usize) {
+                *slot = value;
+            }
+        }
+    }
+
+    /// The reply to a read of the selected register
+    fn read(&mut self) -> [u8; 2] {
+        match self.selected {
+            REG_ID_FIF => {
+                with_state(|state| state.fifo_reads = state.fifo_reads.wrapping_add(1));
+                KEYS.try_receive().map(|key| [key.state as u8, key.key]).unwrap_or([0, 0])
+            }
+            // The low bits hold the number of queued events
+            REG_ID_KEY => [REG_ID_KEY, KEYS.len() as u8 & 0x1F],
+            reg => [reg, self.values.get(reg as usize).copied().unwrap_or(0)],
+        }
+    }
+}
+
+async fn serve(dev: &mut I2cSlave<'static, I2C1>) -> ! {
+    let mut regs = Registers::new();
+    let mut buf = [0u8; 8];
+    loop {
+        let reply = match dev.listen(&mut buf).await {
+            Ok(Command::Write(len)) => {
+                regs.write(&buf[..len]);
+                continue;
+            }
+            Ok(Command::WriteRead(len)) => {
+                regs.write(&buf[..len]);
+                regs.read()
+            }
+            Ok(Command::Read) => regs.read(),
+            Ok(Command::GeneralCall(_)) | Err(_) => continue,
+        };
+        // Pad with zeroes if the controller reads more than we have
+        let _ = dev.respond_and_fill(&reply, 0).await;
+    }
+}
+
+async fn wait_for(enabled: bool) {
+    while MODE.wait().await != enabled {}
+}
+
+/// This task runs target mode whenever it is enabled
+#[embassy_executor::task]
+pub async fn target_task() {
+    loop {
+        wait_for(true).await;
+        // Wait for in-flight transfers, then keep everyone else off the bus
+        let Ok(mut bus) = I2cUser::new(I2cBusUser::Target).lock_exclusive().await else {
+            continue;
+        };
+
+        let mut cfg = i2c_slave::Config::default();
+        cfg.addr = SB_I2C_ADDR.into();
+        cfg.general_call = false;
+        // SAFETY: the controller driver isn't used while we hold the bus, and
+        // is re-created before it's released
+        let mut dev = unsafe { I2cSlave::new(I2C1::steal(), PIN_7::steal(), PIN_6::steal(), Irqs, cfg) };
+        select(serve(&mut dev), wait_for(false)).await;
+
+        // SAFETY: the old driver is dropped by this assignment
+        *bus = unsafe { sb_i2c::recreate() };
+    }
+}
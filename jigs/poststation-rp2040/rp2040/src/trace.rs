@@ -0,0 +1,127 @@
+//! Opt-in tracing of every southbridge bus transaction
+//!
+//! Transactions are recorded by [`crate::shared_bus::BusGuard`] as they finish,
+//! and published on [`I2cTraceTopic`] by [`trace_task`]. If the host can't keep
+//! up, events are dropped and counted rather than slowing the bus down.
+
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use embassy_sync::{
+    blocking_mutex::{self, raw::ThreadModeRawMutex},
+    channel::Channel,
+};
+use embassy_time::Instant;
+use embedded_hal_async::i2c::Operation;
+use picocalc_jig_icd::*;
+use postcard_rpc::{header::VarSeq, server::Sender};
+
+use crate::app::AppTx;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static EVENTS: Channel<ThreadModeRawMutex, Record, 8> = Channel::new();
+/// Events dropped since the last one sent
+static DROPPED: blocking_mutex::Mutex<ThreadModeRawMutex, Cell<u32>> = blocking_mutex::Mutex::new(Cell::new(0));
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// The start of some data, and how long it was
+struct Data {
+    buf: [u8; I2C_TRACE_DATA_MAX],
+    len: usize,
+}
+
+impl Data {
+    fn new() -> Self {
+        Self { buf: [0; I2C_TRACE_DATA_MAX], len: 0 }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        let kept = self.len.min(I2C_TRACE_DATA_MAX);
+        let n = data.len().min(I2C_TRACE_DATA_MAX - kept);
+        self.buf[kept..][..n].copy_from_slice(&data[..n]);
+        self.len += data.len();
+    }
+
+    fn kept(&self) -> &[u8] {
+        &self.buf[..self.len.min(I2C_TRACE_DATA_MAX)]
+    }
+}
+
+struct Record {
+    user: I2cBusUser,
+    addr: u8,
+    write: Data,
+    read: Data,
+    has_read: bool,
+    has_write: bool,
+    result: Result<(), I2cError>,
+    start: Instant,
+    duration_us: u32,
+}
+
+/// Record a finished transaction, if tracing is enabled
+pub fn record(user: I2cBusUser, addr: u8, ops: &[Operation<'_>], result: Result<(), I2cError>, start: Instant) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut rec = Record {
+        user,
+        addr,
+        write: Data::new(),
+        read: Data::new(),
+        has_read: false,
+        has_write: false,
+        result,
+        start,
+        duration_us: start.elapsed().as_micros().try_into().unwrap_or(u32::MAX),
+    };
+    for op in ops {
+        match op {
+            Operation::Write(data) => {
+                rec.has_write = true;
+                rec.write.extend(data);
+            }
+            Operation::Read(data) => {
+                rec.has_read = true;
+                rec.read.extend(data);
+            }
+        }
+    }
+    if EVENTS.try_send(rec).is_err() {
+        DROPPED.lock(|dropped| dropped.set(dropped.get().wrapping_add(1)));
+    }
+}
+
+/// This task publishes the recorded transactions
+#[embassy_executor::task]
+pub async fn trace_task(sender: Sender<AppTx>) {
+    let mut seq = 0u32;
+    loop {
+        let rec = EVENTS.receive().await;
+        let dir = match (rec.has_write, rec.has_read) {
+            (true, true) => I2cDirection::WriteRead,
+            (false, true) => I2cDirection::Read,
+            _ => I2cDirection::Write,
+        };
+        let msg = I2cTraceEvent {
+            user: rec.user,
+            addr: rec.addr,
+            dir,
+            write: rec.write.kept(),
+            write_len: rec.write.len as u32,
+            read: rec.read.kept(),
+            read_len: rec.read.len as u32,
+            result: rec.result,
+            start_us: rec.start.as_micros(),
+            duration_us: rec.duration_us,
+            dropped: DROPPED.lock(|dropped| dropped.replace(0)),
+        };
+        let _ = sender.publish::<I2cTraceTopic>(VarSeq::Seq4(seq), &msg).await;
+        seq = seq.wrapping_add(1);
+    }
+}